@@ -0,0 +1,41 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rust_pg::balance::Balance;
+use rust_pg::id::time_ordered;
+use rust_pg::ledger::{Action, Ledger};
+
+fn account_with(entries: usize) -> Balance {
+    let mut balance = Balance::new("USD").unwrap();
+    for _ in 0..entries {
+        let ledger = Ledger::new(Action::Deposit("1.25".to_string()), time_ordered()).unwrap();
+        balance.mutate(ledger).unwrap();
+    }
+    balance
+}
+
+// Mutating and reading the balance should cost the same whether the account
+// holds a thousand entries or millions.
+fn mutate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("balance");
+    for entries in [1_000, 100_000, 2_000_000] {
+        let mut balance = account_with(entries);
+        group.bench_with_input(BenchmarkId::new("mutate", entries), &entries, |b, _| {
+            b.iter_batched(
+                || Ledger::new(Action::Deposit("0.01".to_string()), time_ordered()).unwrap(),
+                |ledger| balance.mutate(ledger).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("amount", entries), &entries, |b, _| {
+            b.iter(|| balance.amount())
+        });
+        // Historical lookups binary search, so they grow only logarithmically.
+        let midpoint = balance.ledgers().entries()[entries / 2].booked_at();
+        group.bench_with_input(BenchmarkId::new("amount_at", entries), &entries, |b, _| {
+            b.iter(|| balance.amount_at(midpoint))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, mutate);
+criterion_main!(benches);
@@ -1,4 +1,12 @@
-use crate::ledger::{Ledger, Ledgers};
+use crate::clock::{Clock, SystemClock};
+use crate::currency::{self, Rounding};
+use crate::hold::Hold;
+use crate::id::IdGenerator;
+use crate::ledger::{parse_amount, Action, Ledger, LedgerError, Ledgers};
+use crate::limits::{VelocityLimit, VelocityLimits};
+use crate::policy::Policy;
+use crate::status::{AccountStatus, StatusChange};
+use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
 use std::fmt;
 use std::ops::Add;
@@ -7,19 +15,119 @@ use std::ops::Add;
 pub enum BalanceError {
     InvalidCurrency,
     BalanceNotEnough,
+    ExcessPrecision {
+        scale: u32,
+        minor_units: u32,
+    },
+    AmountTooSmall,
+    IdempotencyConflict,
+    Ledger(LedgerError),
+    LedgerNotFound,
+    NotReversible,
+    AlreadyReversed,
+    RefundExceedsOriginal {
+        remaining: Decimal,
+    },
+    HoldNotFound,
+    HoldExpired,
+    DuplicateHold,
+    CaptureExceedsHold {
+        held: Decimal,
+    },
+    /// The debit would go past the overdraft limit; `headroom` is how much
+    /// could still have been debited.
+    OverdraftLimitExceeded {
+        headroom: Decimal,
+    },
+    /// The debit would leave less than the required minimum balance.
+    BelowMinimumBalance {
+        headroom: Decimal,
+    },
+    VelocityLimitExceeded(VelocityLimit),
+    AccountFrozen,
+    AccountDormant,
+    AccountClosed,
+    InvalidTransition {
+        from: AccountStatus,
+        to: AccountStatus,
+    },
+    MissingReason,
+    NonZeroBalance,
+    HoldsOutstanding,
+    /// A replacement balance drops or alters recorded status changes.
+    StatusHistoryRewritten,
+}
+
+/// The rules an account's postings are checked against besides its funds.
+/// A pocket is governed by its account's, applied to amounts in the
+/// pocket's currency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Controls {
+    status: AccountStatus,
+    policy: Policy,
+    limits: VelocityLimits,
+}
+
+impl Controls {
+    // Closed accounts take no postings at all; frozen and dormant ones only
+    // credits.
+    pub(crate) fn check_status(&self, debit: bool) -> Result<(), BalanceError> {
+        match self.status {
+            AccountStatus::Closed => Err(BalanceError::AccountClosed),
+            AccountStatus::Frozen if debit => Err(BalanceError::AccountFrozen),
+            AccountStatus::Dormant if debit => Err(BalanceError::AccountDormant),
+            _ => Ok(()),
+        }
+    }
+
+    // Rejects taking the available balance from `before` to `after` if that
+    // goes below the policy's floor. The error reports the headroom: how much
+    // could still have been debited.
+    fn check_policy(&self, before: Decimal, after: Decimal) -> Result<(), BalanceError> {
+        let floor = self.policy.floor();
+        if after >= floor {
+            return Ok(());
+        }
+        let headroom = (before - floor).max(Decimal::ZERO);
+        Err(match self.policy {
+            Policy::NoOverdraft => BalanceError::BalanceNotEnough,
+            Policy::Overdraft { .. } => BalanceError::OverdraftLimitExceeded { headroom },
+            Policy::MinimumBalance { .. } => BalanceError::BelowMinimumBalance { headroom },
+        })
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Balance {
     pub currency: String,
+    minor_units: u32,
+    rounding: Rounding,
+    policy: Policy,
+    limits: VelocityLimits,
     ledgers: Ledgers,
+    // Outstanding holds, in the order they were placed.
+    holds: Vec<Hold>,
+    status: AccountStatus,
+    // Every status transition, oldest first.
+    status_history: Vec<StatusChange>,
+    // The account this balance is a currency pocket of, if it isn't an
+    // account's primary balance.
+    pocket_of: Option<String>,
 }
 
 impl Clone for Balance {
     fn clone(&self) -> Self {
         Balance {
             currency: self.currency.clone(),
+            minor_units: self.minor_units,
+            rounding: self.rounding,
+            policy: self.policy,
+            limits: self.limits,
             ledgers: self.ledgers.clone(),
+            holds: self.holds.clone(),
+            status: self.status,
+            status_history: self.status_history.clone(),
+            pocket_of: self.pocket_of.clone(),
         }
     }
 }
@@ -29,33 +137,500 @@ impl Balance {
         if currency.is_empty() {
             return Err(BalanceError::InvalidCurrency);
         }
-        let currency = currency.to_string().to_uppercase();
-        return Ok(Balance {
-            currency,
+        let currency = currency::lookup(currency).ok_or(BalanceError::InvalidCurrency)?;
+        Ok(Balance::restore_currency(
+            &currency.code,
+            currency.minor_units,
+        ))
+    }
+
+    /// A balance in a currency that was validated when the account was
+    /// opened, so custom currencies load without being registered again.
+    pub(crate) fn restore_currency(currency: &str, minor_units: u32) -> Balance {
+        Balance {
+            currency: currency.to_string(),
+            minor_units,
+            rounding: Rounding::Reject,
+            policy: Policy::default(),
+            limits: VelocityLimits::default(),
             ledgers: Ledgers::new(),
-        });
+            holds: vec![],
+            status: AccountStatus::default(),
+            status_history: vec![],
+            pocket_of: None,
+        }
+    }
+
+    pub(crate) fn restore(currency: &str, minor_units: u32, ledgers: Ledgers) -> Balance {
+        let mut balance = Balance::restore_currency(currency, minor_units);
+        balance.ledgers = ledgers;
+        balance
+    }
+
+    /// Adds a stored entry as it was posted, without checking it against the
+    /// balance's rules again.
+    pub(crate) fn restore_ledger(&mut self, ledger: Ledger) -> Result<(), LedgerError> {
+        self.ledgers.add(ledger)
+    }
+
+    pub(crate) fn restore_ledgers(&mut self, ledgers: Ledgers) {
+        self.ledgers = ledgers;
+    }
+
+    pub(crate) fn restore_holds(&mut self, holds: Vec<Hold>) {
+        self.holds = holds;
+    }
+
+    /// Replaces the status history; the status becomes that of the last
+    /// change, or active if there is none.
+    pub(crate) fn restore_status_history(&mut self, history: Vec<StatusChange>) {
+        self.status = history
+            .last()
+            .map_or(AccountStatus::default(), StatusChange::to);
+        self.status_history = history;
+    }
+
+    pub(crate) fn with_pocket_of(mut self, account_id: Option<String>) -> Balance {
+        self.pocket_of = account_id;
+        self
+    }
+
+    pub(crate) fn pocket_of(&self) -> Option<&str> {
+        self.pocket_of.as_deref()
+    }
+
+    pub fn with_rounding(mut self, rounding: Rounding) -> Balance {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+
+    pub fn with_policy(mut self, policy: Policy) -> Balance {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// Overrides this account's outflow velocity limits.
+    pub fn with_limits(mut self, limits: VelocityLimits) -> Balance {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> VelocityLimits {
+        self.limits
+    }
+
+    pub fn minor_units(&self) -> u32 {
+        self.minor_units
     }
 
     pub fn mutate(&mut self, ledger: Ledger) -> Result<Decimal, BalanceError> {
-        let amount = ledger.amount();
+        self.mutate_under(ledger, self.controls())
+    }
 
-        let current_balance = self.ledgers.sum();
-        if current_balance.add(amount).is_sign_negative() {
-            return Err(BalanceError::BalanceNotEnough);
+    /// Like `mutate`, but checks `ledger` against `controls` instead of this
+    /// balance's own.
+    pub(crate) fn mutate_under(
+        &mut self,
+        ledger: Ledger,
+        controls: Controls,
+    ) -> Result<Decimal, BalanceError> {
+        if let Some(amount) = self.replayed(&ledger)? {
+            return Ok(amount);
         }
+        let ledger = self.prepare_under(ledger, controls)?;
+        Ok(self.commit(ledger))
+    }
 
-        let _ = self.ledgers.add(ledger);
+    pub(crate) fn controls(&self) -> Controls {
+        Controls {
+            status: self.status,
+            policy: self.policy,
+            limits: self.limits,
+        }
+    }
 
-        let total = self.ledgers.sum();
-        return Ok(total);
+    /// If `ledger` retries an entry already posted under the same idempotency
+    /// key, returns the balance that entry produced. Reusing a key for a
+    /// different action or amount is an `IdempotencyConflict`.
+    pub(crate) fn replayed(&self, ledger: &Ledger) -> Result<Option<Decimal>, BalanceError> {
+        let Some(key) = ledger.idempotency_key() else {
+            return Ok(None);
+        };
+        let Some(original) = self.ledgers.find_by_idempotency_key(key) else {
+            return Ok(None);
+        };
+        let same_amount = self.apply_precision(ledger.amount())? == original.amount;
+        if original.action != ledger.action() || !same_amount {
+            return Err(BalanceError::IdempotencyConflict);
+        }
+        Ok(Some(original.total))
     }
 
-    pub fn amount(&self) -> Decimal {
-        if self.ledgers.len() == 0 {
-            return Decimal::default();
+    /// Validates `ledger` against this balance without applying it, returning
+    /// the entry as it would be posted (after currency rounding).
+    pub(crate) fn prepare(&self, ledger: Ledger) -> Result<Ledger, BalanceError> {
+        self.prepare_under(ledger, self.controls())
+    }
+
+    /// Like `prepare`, but checks `ledger` against `controls` instead of this
+    /// balance's own.
+    pub(crate) fn prepare_under(
+        &self,
+        mut ledger: Ledger,
+        controls: Controls,
+    ) -> Result<Ledger, BalanceError> {
+        self.ledgers.check(&ledger).map_err(BalanceError::Ledger)?;
+        let amount = self.apply_precision(ledger.amount())?;
+        ledger.set_amount(amount);
+        controls.check_status(amount.is_sign_negative())?;
+        if let Some(original) = ledger.reverses() {
+            self.check_reversal(original, amount)?;
+        }
+
+        // Holds still active when the entry is booked reserve funds too.
+        let available = self.available_at(ledger.booked_at());
+        if amount.is_sign_negative() {
+            controls.check_policy(available, available.add(amount))?;
         }
+        if VelocityLimits::counts(ledger.action()) {
+            controls
+                .limits
+                .check(&self.ledgers, ledger.booked_at(), amount.abs())
+                .map_err(BalanceError::VelocityLimitExceeded)?;
+        }
+        Ok(ledger)
+    }
+
+    /// Posts an entry returned by `prepare` on this balance, which already
+    /// ruled out duplicates.
+    pub(crate) fn commit(&mut self, ledger: Ledger) -> Decimal {
+        self.ledgers
+            .add(ledger)
+            .expect("prepared entries are not duplicates");
         self.ledgers.sum()
     }
+
+    /// Reserves `hold`'s amount against the available balance and returns
+    /// what is left available.
+    pub fn place_hold(&mut self, mut hold: Hold) -> Result<Decimal, BalanceError> {
+        if self.holds.iter().any(|held| held.id() == hold.id()) {
+            return Err(BalanceError::DuplicateHold);
+        }
+        self.controls().check_status(true)?;
+        let amount = self.apply_precision(hold.amount())?;
+        hold.set_amount(amount);
+        let available = self.available_at(hold.placed_at());
+        self.controls()
+            .check_policy(available, available - amount)?;
+        self.holds.push(hold);
+        Ok(available - amount)
+    }
+
+    /// Moves the account to `to`, recording `reason`. Only a zero balance
+    /// with no outstanding holds can be closed.
+    pub fn transition(
+        &mut self,
+        to: AccountStatus,
+        reason: &str,
+    ) -> Result<StatusChange, BalanceError> {
+        self.transition_with_clock(to, reason, &SystemClock)
+    }
+
+    /// Like `transition`, but takes the change's timestamp from `clock`.
+    pub fn transition_with_clock(
+        &mut self,
+        to: AccountStatus,
+        reason: &str,
+        clock: &dyn Clock,
+    ) -> Result<StatusChange, BalanceError> {
+        if !self.status.can_become(to) {
+            return Err(BalanceError::InvalidTransition {
+                from: self.status,
+                to,
+            });
+        }
+        if reason.trim().is_empty() {
+            return Err(BalanceError::MissingReason);
+        }
+        if to == AccountStatus::Closed {
+            if !self.amount().is_zero() {
+                return Err(BalanceError::NonZeroBalance);
+            }
+            if !self.holds.is_empty() {
+                return Err(BalanceError::HoldsOutstanding);
+            }
+        }
+        let change = StatusChange::new(self.status, to, reason, clock);
+        self.status = to;
+        self.status_history.push(change.clone());
+        Ok(change)
+    }
+
+    /// Checks that this balance may replace `stored` wholesale. Closed
+    /// accounts can't be rewritten, and the status history may only grow by
+    /// transitions `transition` would have allowed.
+    pub(crate) fn check_replaces(&self, stored: &Balance) -> Result<(), BalanceError> {
+        if stored.status == AccountStatus::Closed {
+            return Err(BalanceError::AccountClosed);
+        }
+        let Some(added) = self
+            .status_history
+            .strip_prefix(stored.status_history.as_slice())
+        else {
+            return Err(BalanceError::StatusHistoryRewritten);
+        };
+        let mut status = stored.status;
+        for change in added {
+            if change.from() != status || !status.can_become(change.to()) {
+                return Err(BalanceError::InvalidTransition {
+                    from: status,
+                    to: change.to(),
+                });
+            }
+            if change.reason().trim().is_empty() {
+                return Err(BalanceError::MissingReason);
+            }
+            status = change.to();
+        }
+        if status == AccountStatus::Closed {
+            if !self.amount().is_zero() {
+                return Err(BalanceError::NonZeroBalance);
+            }
+            if !self.holds.is_empty() {
+                return Err(BalanceError::HoldsOutstanding);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    pub fn status_history(&self) -> &[StatusChange] {
+        &self.status_history
+    }
+
+    /// Settles `amount` of the hold `hold_id` (all of it when `None`) as a
+    /// `Withdrawal` booked at `clock`'s time, releasing whatever part of the
+    /// hold is not captured. Returns the ledger balance.
+    pub fn capture(
+        &mut self,
+        hold_id: &str,
+        amount: Option<&str>,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Decimal, BalanceError> {
+        let position = self.hold_position(hold_id)?;
+        let held = self.holds[position].amount();
+        let amount = amount.map_or_else(|| held.to_string(), str::to_string);
+        let ledger = Ledger::new_with_clock(Action::Withdrawal(amount), ids, clock)
+            .map_err(BalanceError::Ledger)?;
+        if !self.holds[position].is_active_at(ledger.booked_at()) {
+            return Err(BalanceError::HoldExpired);
+        }
+        if self.apply_precision(ledger.amount())?.abs() > held {
+            return Err(BalanceError::CaptureExceedsHold { held });
+        }
+
+        let hold = self.holds.remove(position);
+        match self.prepare(ledger) {
+            Ok(ledger) => Ok(self.commit(ledger)),
+            Err(e) => {
+                self.holds.insert(position, hold);
+                Err(e)
+            }
+        }
+    }
+
+    /// Drops the hold `hold_id`, returning its funds to the available
+    /// balance.
+    pub fn release(&mut self, hold_id: &str) -> Result<Hold, BalanceError> {
+        let position = self.hold_position(hold_id)?;
+        Ok(self.holds.remove(position))
+    }
+
+    /// Drops every hold that has expired by `now`, returning them.
+    pub fn expire_holds(&mut self, now: DateTime<Utc>) -> Vec<Hold> {
+        let (active, expired) = self
+            .holds
+            .drain(..)
+            .partition(|hold| hold.is_active_at(now));
+        self.holds = active;
+        expired
+    }
+
+    fn hold_position(&self, hold_id: &str) -> Result<usize, BalanceError> {
+        self.holds
+            .iter()
+            .position(|hold| hold.id() == hold_id)
+            .ok_or(BalanceError::HoldNotFound)
+    }
+
+    /// Builds an entry that undoes `amount` of the entry `ledger_id`, or all
+    /// of it when `amount` is `None`, booked at `clock`'s time. Post it with
+    /// `mutate` or `Storage::append`, which re-check it against the
+    /// reversals posted in the meantime.
+    pub fn reversal_of(
+        &self,
+        ledger_id: &str,
+        amount: Option<&str>,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Ledger, BalanceError> {
+        let original = self.original_amount(ledger_id)?;
+        let amount = match amount {
+            None => original,
+            Some(amount) => {
+                let amount = parse_amount(amount).map_err(BalanceError::Ledger)?;
+                if amount.is_zero() {
+                    let msg = "amount can't zero".to_string();
+                    return Err(BalanceError::Ledger(LedgerError::InvalidAmount(msg)));
+                }
+                if original.is_sign_negative() {
+                    -amount
+                } else {
+                    amount
+                }
+            }
+        };
+        Ok(Ledger::reversing(ledger_id, amount, ids, clock))
+    }
+
+    /// Posts an equal-and-opposite entry for `ledger_id`.
+    pub fn reverse(
+        &mut self,
+        ledger_id: &str,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Decimal, BalanceError> {
+        let reversal = self.reversal_of(ledger_id, None, ids, clock)?;
+        self.mutate(reversal)
+    }
+
+    /// Gives back part of `ledger_id`. Refunds of one entry can never add up
+    /// to more than its amount.
+    pub fn refund(
+        &mut self,
+        ledger_id: &str,
+        amount: &str,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Decimal, BalanceError> {
+        let refund = self.reversal_of(ledger_id, Some(amount), ids, clock)?;
+        self.mutate(refund)
+    }
+
+    // Amount of the entry `ledger_id` as a reversal sees it, even once the
+    // entry has been compacted away.
+    fn original_amount(&self, ledger_id: &str) -> Result<Decimal, BalanceError> {
+        match self.ledgers.get(ledger_id) {
+            Some(original) if original.reverses().is_some() => Err(BalanceError::NotReversible),
+            Some(original) => Ok(original.amount()),
+            None => self
+                .ledgers
+                .compacted_amount(ledger_id)
+                .ok_or(BalanceError::LedgerNotFound),
+        }
+    }
+
+    fn check_reversal(&self, original_id: &str, amount: Decimal) -> Result<(), BalanceError> {
+        let original = self.original_amount(original_id)?;
+        let remaining = original.abs() - self.ledgers.reversed_amount(original_id);
+        if remaining.is_zero() {
+            return Err(BalanceError::AlreadyReversed);
+        }
+        if amount.abs() > remaining {
+            return Err(BalanceError::RefundExceedsOriginal { remaining });
+        }
+        Ok(())
+    }
+
+    fn apply_precision(&self, amount: Decimal) -> Result<Decimal, BalanceError> {
+        let scale = amount.normalize().scale();
+        if scale <= self.minor_units {
+            return Ok(amount);
+        }
+        let rounded = match self.rounding {
+            Rounding::Reject => {
+                return Err(BalanceError::ExcessPrecision {
+                    scale,
+                    minor_units: self.minor_units,
+                })
+            }
+            Rounding::Round(strategy) => amount.round_dp_with_strategy(self.minor_units, strategy),
+        };
+        if rounded.is_zero() {
+            return Err(BalanceError::AmountTooSmall);
+        }
+        Ok(rounded)
+    }
+
+    /// Drops every ledger after the first `len`, undoing commits that could
+    /// not be made durable.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.ledgers.truncate(len);
+    }
+
+    pub fn ledgers(&self) -> &Ledgers {
+        &self.ledgers
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.ledgers.sum()
+    }
+
+    /// Total of all posted ledgers, ignoring holds. Same as `amount`.
+    pub fn ledger_balance(&self) -> Decimal {
+        self.ledgers.sum()
+    }
+
+    /// The ledger balance minus every hold still active now.
+    pub fn available(&self) -> Decimal {
+        self.available_at(Utc::now())
+    }
+
+    /// The ledger balance minus the holds active at `at`.
+    pub fn available_at(&self, at: DateTime<Utc>) -> Decimal {
+        let held: Decimal = self
+            .holds
+            .iter()
+            .filter(|hold| hold.is_active_at(at))
+            .map(Hold::amount)
+            .sum();
+        self.ledgers.sum() - held
+    }
+
+    /// How much can still be debited now before the policy's floor is
+    /// reached. Saturates for floors too low to represent the difference.
+    pub fn headroom(&self) -> Decimal {
+        self.available()
+            .checked_sub(self.policy.floor())
+            .unwrap_or(Decimal::MAX)
+            .max(Decimal::ZERO)
+    }
+
+    pub fn holds(&self) -> &[Hold] {
+        &self.holds
+    }
+
+    /// Balance as it stood at `at`; see `Ledgers::balance_at`.
+    pub fn amount_at(&self, at: DateTime<Utc>) -> Decimal {
+        self.ledgers.balance_at(at)
+    }
+
+    /// Balance right after the ledger `ledger_id` was posted.
+    pub fn amount_after(&self, ledger_id: &str) -> Option<Decimal> {
+        self.ledgers.balance_after(ledger_id)
+    }
 }
 
 impl fmt::Display for Balance {
@@ -63,16 +638,23 @@ impl fmt::Display for Balance {
         if self.amount().is_integer() {
             return write!(f, "{} {:.0}", self.currency, self.amount());
         }
-        write!(f, "{} {}", self.currency, self.amount().to_string())
+        write!(f, "{} {}", self.currency, self.amount())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::id::{time_ordered, Sequence};
     use rust_decimal_macros::dec;
 
-    use crate::ledger::Action;
+    use crate::clock::{Clock, FixedClock, SystemClock};
+    use crate::currency::{register, Currency};
+    use crate::ledger::ActionKind;
+    use crate::limits::Limit;
+    use crate::status::AccountStatus;
+    use chrono::{Duration, TimeZone};
+    use rust_decimal::RoundingStrategy;
 
     #[test]
     fn test_balance_new_valid_currency() {
@@ -91,7 +673,7 @@ mod tests {
     #[test]
     fn test_balance_mutate_with_sufficient_funds() {
         let mut balance = Balance::new("USD").unwrap();
-        let ledger = Ledger::new(Action::Deposit("100.0".to_string())).unwrap();
+        let ledger = Ledger::new(Action::Deposit("100.0".to_string()), time_ordered()).unwrap();
         let result = balance.mutate(ledger);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), dec!(100.0));
@@ -100,11 +682,27 @@ mod tests {
     #[test]
     fn test_balance_mutate_with_insufficient_funds() {
         let mut balance = Balance::new("USD").unwrap();
-        let ledger = Ledger::new(Action::Withdrawal("100.0".to_string())).unwrap();
+        let ledger = Ledger::new(Action::Withdrawal("100.0".to_string()), time_ordered()).unwrap();
         let result = balance.mutate(ledger);
         assert!(matches!(result, Err(BalanceError::BalanceNotEnough)));
     }
 
+    #[test]
+    fn test_balance_mutate_rejects_duplicate_id() {
+        let mut balance = Balance::new("USD").unwrap();
+        let deposit = || Ledger::new(Action::Deposit("10".to_string()), &Sequence::new()).unwrap();
+        balance.mutate(deposit()).unwrap();
+        assert_eq!(
+            balance.mutate(deposit()),
+            Err(BalanceError::Ledger(LedgerError::DuplicateLedger))
+        );
+        assert_eq!(balance.amount(), dec!(10));
+
+        let ids = Sequence::after(balance.ledgers());
+        let next = Ledger::new(Action::Deposit("10".to_string()), &ids).unwrap();
+        assert_eq!(balance.mutate(next), Ok(dec!(20)));
+    }
+
     #[test]
     fn test_balance_amount_with_no_ledgers() {
         let balance = Balance::new("USD").unwrap();
@@ -114,10 +712,708 @@ mod tests {
     #[test]
     fn test_balance_amount_with_ledgers() {
         let mut balance = Balance::new("USD").unwrap();
-        let ledger_deposit = Ledger::new(Action::Deposit("100.0".to_string())).unwrap();
+        let ledger_deposit =
+            Ledger::new(Action::Deposit("100.0".to_string()), time_ordered()).unwrap();
         balance.mutate(ledger_deposit).unwrap();
-        let ledger_withdrawal = Ledger::new(Action::Withdrawal("50.0".to_string())).unwrap();
+        let ledger_withdrawal =
+            Ledger::new(Action::Withdrawal("50.0".to_string()), time_ordered()).unwrap();
         balance.mutate(ledger_withdrawal).unwrap();
         assert_eq!(balance.amount(), dec!(50.0));
     }
+
+    #[test]
+    fn test_balance_new_unknown_currency() {
+        let balance = Balance::new("XYZ");
+        assert!(matches!(balance, Err(BalanceError::InvalidCurrency)));
+    }
+
+    #[test]
+    fn test_balance_new_lowercase_currency() {
+        let balance = Balance::new("jpy").unwrap();
+        assert_eq!(balance.currency, "JPY");
+        assert_eq!(balance.minor_units(), 0);
+    }
+
+    #[test]
+    fn test_balance_mutate_rejects_excess_precision() {
+        let mut balance = Balance::new("JPY").unwrap();
+        let ledger = Ledger::new(Action::Deposit("10.555".to_string()), time_ordered()).unwrap();
+        let result = balance.mutate(ledger);
+        assert_eq!(
+            result,
+            Err(BalanceError::ExcessPrecision {
+                scale: 3,
+                minor_units: 0
+            })
+        );
+        assert_eq!(balance.amount(), Decimal::default());
+    }
+
+    #[test]
+    fn test_balance_mutate_accepts_trailing_zeros() {
+        let mut balance = Balance::new("JPY").unwrap();
+        let ledger = Ledger::new(Action::Deposit("100.00".to_string()), time_ordered()).unwrap();
+        assert_eq!(balance.mutate(ledger), Ok(dec!(100)));
+    }
+
+    #[test]
+    fn test_balance_mutate_rounds_excess_precision() {
+        let mut balance = Balance::new("USD")
+            .unwrap()
+            .with_rounding(Rounding::Round(RoundingStrategy::MidpointNearestEven));
+        let ledger = Ledger::new(Action::Deposit("10.555".to_string()), time_ordered()).unwrap();
+        assert_eq!(balance.mutate(ledger), Ok(dec!(10.56)));
+        let ledger = Ledger::new(Action::Withdrawal("0.125".to_string()), time_ordered()).unwrap();
+        assert_eq!(balance.mutate(ledger), Ok(dec!(10.44)));
+    }
+
+    #[test]
+    fn test_balance_mutate_rounds_to_zero() {
+        let mut balance = Balance::new("USD")
+            .unwrap()
+            .with_rounding(Rounding::Round(RoundingStrategy::ToZero));
+        let ledger = Ledger::new(Action::Deposit("0.001".to_string()), time_ordered()).unwrap();
+        assert_eq!(balance.mutate(ledger), Err(BalanceError::AmountTooSmall));
+    }
+
+    #[test]
+    fn test_balance_with_custom_currency() {
+        register(Currency::new("PTS8", 8, "pts").unwrap()).unwrap();
+        let mut balance = Balance::new("pts8").unwrap();
+        let ledger =
+            Ledger::new(Action::Deposit("0.00000001".to_string()), time_ordered()).unwrap();
+        assert_eq!(balance.mutate(ledger), Ok(dec!(0.00000001)));
+    }
+
+    #[test]
+    fn test_balance_amount_at_and_after() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 31, 12, 0, 0).unwrap();
+        let clock = FixedClock::new(start);
+        let mut balance = Balance::new("USD").unwrap();
+        let mut ids = vec![];
+        for action in [
+            Action::Deposit("100".to_string()),
+            Action::Withdrawal("40".to_string()),
+            Action::Deposit("5".to_string()),
+        ] {
+            let ledger = Ledger::new_with_clock(action, time_ordered(), &clock).unwrap();
+            ids.push(ledger.id().to_string());
+            balance.mutate(ledger).unwrap();
+            clock.advance(Duration::days(1));
+        }
+
+        assert_eq!(balance.amount_at(start - Duration::seconds(1)), dec!(0));
+        assert_eq!(balance.amount_at(start), dec!(100));
+        assert_eq!(balance.amount_at(start + Duration::hours(36)), dec!(60));
+        assert_eq!(balance.amount_at(start + Duration::days(30)), dec!(65));
+
+        assert_eq!(balance.amount_after(&ids[0]), Some(dec!(100)));
+        assert_eq!(balance.amount_after(&ids[1]), Some(dec!(60)));
+        assert_eq!(balance.amount_after(&ids[2]), Some(dec!(65)));
+        assert_eq!(balance.amount_after("missing"), None);
+    }
+
+    #[test]
+    fn test_balance_amount_at_with_clock_step_back() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 31, 12, 0, 0).unwrap();
+        let clock = FixedClock::new(start);
+        let mut balance = Balance::new("USD").unwrap();
+        let deposit =
+            Ledger::new_with_clock(Action::Deposit("10".to_string()), time_ordered(), &clock)
+                .unwrap();
+        balance.mutate(deposit).unwrap();
+
+        // Stamped before the first entry but posted after it.
+        clock.set(start - Duration::hours(1));
+        let late = Ledger::new_with_clock(Action::Deposit("1".to_string()), time_ordered(), &clock)
+            .unwrap();
+        balance.mutate(late).unwrap();
+
+        assert_eq!(balance.amount_at(start - Duration::minutes(30)), dec!(0));
+        assert_eq!(balance.amount_at(start), dec!(11));
+    }
+
+    #[test]
+    fn test_balance_mutate_replays_idempotency_key() {
+        let mut balance = Balance::new("USD").unwrap();
+        let keyed = |action: Action, key: &str| {
+            Ledger::new(action, time_ordered())
+                .unwrap()
+                .with_idempotency_key(key.to_string())
+        };
+        let deposit = |key: &str| keyed(Action::Deposit("100".to_string()), key);
+
+        assert_eq!(balance.mutate(deposit("req-1")), Ok(dec!(100)));
+        assert_eq!(
+            balance.mutate(keyed(Action::Withdrawal("30".to_string()), "req-2")),
+            Ok(dec!(70))
+        );
+        // A retry answers with the original result and posts nothing.
+        assert_eq!(balance.mutate(deposit("req-1")), Ok(dec!(100)));
+        assert_eq!(balance.ledgers().len(), 2);
+        assert_eq!(balance.amount(), dec!(70));
+
+        assert_eq!(
+            balance.mutate(keyed(Action::Deposit("100.01".to_string()), "req-1")),
+            Err(BalanceError::IdempotencyConflict)
+        );
+        assert_eq!(
+            balance.mutate(keyed(Action::Withdrawal("100".to_string()), "req-1")),
+            Err(BalanceError::IdempotencyConflict)
+        );
+        assert_eq!(balance.mutate(deposit("req-3")), Ok(dec!(170)));
+    }
+
+    #[test]
+    fn test_balance_replay_compares_rounded_amount() {
+        let mut balance = Balance::new("USD")
+            .unwrap()
+            .with_rounding(Rounding::Round(RoundingStrategy::MidpointNearestEven));
+        let deposit = |amount: &str| {
+            Ledger::new(Action::Deposit(amount.to_string()), time_ordered())
+                .unwrap()
+                .with_idempotency_key("req".to_string())
+        };
+        assert_eq!(balance.mutate(deposit("1.005")), Ok(dec!(1.00)));
+        assert_eq!(balance.mutate(deposit("1.00")), Ok(dec!(1.00)));
+        assert_eq!(balance.ledgers().len(), 1);
+    }
+
+    #[test]
+    fn test_balance_replayed_withdrawal_skips_funds_check() {
+        let mut balance = Balance::new("USD").unwrap();
+        balance
+            .mutate(Ledger::new(Action::Deposit("10".to_string()), time_ordered()).unwrap())
+            .unwrap();
+        let withdrawal = Ledger::new(Action::Withdrawal("10".to_string()), time_ordered())
+            .unwrap()
+            .with_idempotency_key("req".to_string());
+        assert_eq!(balance.mutate(withdrawal.clone()), Ok(dec!(0)));
+        assert_eq!(balance.mutate(withdrawal), Ok(dec!(0)));
+    }
+
+    fn posted(balance: &mut Balance, action: Action) -> String {
+        let ledger = Ledger::new(action, time_ordered()).unwrap();
+        let id = ledger.id().to_string();
+        balance.mutate(ledger).unwrap();
+        id
+    }
+
+    #[test]
+    fn test_balance_reverse() {
+        let mut balance = Balance::new("USD").unwrap();
+        let deposit = posted(&mut balance, Action::Deposit("100".to_string()));
+        let fee = posted(&mut balance, Action::Fee("2.5".to_string()));
+
+        assert_eq!(
+            balance.reverse(&fee, time_ordered(), &SystemClock),
+            Ok(dec!(100))
+        );
+        let reversal = balance.ledgers().entries().last().unwrap().clone();
+        assert_eq!(reversal.action(), ActionKind::Reversal);
+        assert_eq!(reversal.amount(), dec!(2.5));
+        assert_eq!(reversal.reverses(), Some(fee.as_str()));
+
+        assert_eq!(
+            balance.reverse(&fee, time_ordered(), &SystemClock),
+            Err(BalanceError::AlreadyReversed)
+        );
+        assert_eq!(
+            balance.reverse(reversal.id(), time_ordered(), &SystemClock),
+            Err(BalanceError::NotReversible)
+        );
+        assert_eq!(
+            balance.reverse("missing", time_ordered(), &SystemClock),
+            Err(BalanceError::LedgerNotFound)
+        );
+        assert_eq!(
+            balance.reverse(&deposit, time_ordered(), &SystemClock),
+            Ok(dec!(0))
+        );
+    }
+
+    #[test]
+    fn test_balance_partial_refunds() {
+        let mut balance = Balance::new("USD").unwrap();
+        posted(&mut balance, Action::Deposit("100".to_string()));
+        let purchase = posted(&mut balance, Action::Withdrawal("30".to_string()));
+
+        assert_eq!(
+            balance.refund(&purchase, "10", time_ordered(), &SystemClock),
+            Ok(dec!(80))
+        );
+        assert_eq!(
+            balance.refund(&purchase, "15.01", time_ordered(), &SystemClock),
+            Ok(dec!(95.01))
+        );
+        assert_eq!(
+            balance.refund(&purchase, "5", time_ordered(), &SystemClock),
+            Err(BalanceError::RefundExceedsOriginal {
+                remaining: dec!(4.99)
+            })
+        );
+        assert_eq!(
+            balance.reverse(&purchase, time_ordered(), &SystemClock),
+            Err(BalanceError::RefundExceedsOriginal {
+                remaining: dec!(4.99)
+            })
+        );
+        assert_eq!(
+            balance.refund(&purchase, "0", time_ordered(), &SystemClock),
+            Err(BalanceError::Ledger(LedgerError::InvalidAmount(
+                "amount can't zero".to_string()
+            )))
+        );
+        assert_eq!(
+            balance.refund(&purchase, "4.99", time_ordered(), &SystemClock),
+            Ok(dec!(100))
+        );
+        assert_eq!(
+            balance.refund(&purchase, "0.01", time_ordered(), &SystemClock),
+            Err(BalanceError::AlreadyReversed)
+        );
+        assert_eq!(balance.ledgers().reversed_amount(&purchase), dec!(30));
+    }
+
+    #[test]
+    fn test_balance_reversal_needs_funds() {
+        let mut balance = Balance::new("USD").unwrap();
+        let deposit = posted(&mut balance, Action::Deposit("100".to_string()));
+        posted(&mut balance, Action::Withdrawal("60".to_string()));
+        assert_eq!(
+            balance.reverse(&deposit, time_ordered(), &SystemClock),
+            Err(BalanceError::BalanceNotEnough)
+        );
+        assert_eq!(
+            balance.refund(&deposit, "40", time_ordered(), &SystemClock),
+            Ok(dec!(0))
+        );
+    }
+
+    #[test]
+    fn test_balance_reversal_booked_by_clock() {
+        let mut balance = Balance::new("USD").unwrap();
+        let deposit = posted(&mut balance, Action::Deposit("100".to_string()));
+        let now = Utc.with_ymd_and_hms(2030, 1, 2, 3, 4, 5).unwrap();
+        let refund = balance
+            .reversal_of(&deposit, Some("1"), time_ordered(), &FixedClock::new(now))
+            .unwrap();
+        assert_eq!(refund.booked_at(), now);
+        assert_eq!(refund.value_date(), now.date_naive());
+    }
+
+    #[test]
+    fn test_balance_holds_reduce_available() {
+        let mut balance = Balance::new("USD").unwrap();
+        posted(&mut balance, Action::Deposit("100".to_string()));
+
+        let hold = Hold::new("60", time_ordered()).unwrap();
+        let hold_id = hold.id().to_string();
+        assert_eq!(balance.place_hold(hold), Ok(dec!(40)));
+        assert_eq!(balance.ledger_balance(), dec!(100));
+        assert_eq!(balance.available(), dec!(40));
+        assert_eq!(balance.ledgers().len(), 1);
+
+        let withdrawal = Ledger::new(Action::Withdrawal("50".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            balance.mutate(withdrawal),
+            Err(BalanceError::BalanceNotEnough)
+        );
+        assert_eq!(
+            balance.place_hold(Hold::new("40.01", time_ordered()).unwrap()),
+            Err(BalanceError::BalanceNotEnough)
+        );
+
+        assert_eq!(balance.release(&hold_id).unwrap().amount(), dec!(60));
+        assert_eq!(balance.available(), dec!(100));
+        assert_eq!(balance.release(&hold_id), Err(BalanceError::HoldNotFound));
+    }
+
+    #[test]
+    fn test_balance_capture_hold() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 10, 0, 0).unwrap();
+        let clock = FixedClock::new(now);
+        let mut balance = Balance::new("USD").unwrap();
+        posted(&mut balance, Action::Deposit("100".to_string()));
+        let hold = Hold::new("60", time_ordered()).unwrap();
+        let hold_id = hold.id().to_string();
+        balance.place_hold(hold.clone()).unwrap();
+        assert_eq!(balance.place_hold(hold), Err(BalanceError::DuplicateHold));
+
+        assert_eq!(
+            balance.capture(&hold_id, Some("60.01"), time_ordered(), &clock),
+            Err(BalanceError::CaptureExceedsHold { held: dec!(60) })
+        );
+        // Capturing less than held releases the rest.
+        assert_eq!(
+            balance.capture(&hold_id, Some("45.5"), time_ordered(), &clock),
+            Ok(dec!(54.5))
+        );
+        let capture = balance.ledgers().entries().last().unwrap();
+        assert_eq!(capture.action(), ActionKind::Withdrawal);
+        assert_eq!(capture.amount(), dec!(-45.5));
+        assert_eq!(capture.booked_at(), now);
+        assert!(balance.holds().is_empty());
+        assert_eq!(balance.available(), dec!(54.5));
+        assert_eq!(
+            balance.capture(&hold_id, None, time_ordered(), &clock),
+            Err(BalanceError::HoldNotFound)
+        );
+
+        let hold = Hold::new("4.5", time_ordered()).unwrap();
+        let hold_id = hold.id().to_string();
+        balance.place_hold(hold).unwrap();
+        assert_eq!(
+            balance.capture(&hold_id, None, time_ordered(), &clock),
+            Ok(dec!(50))
+        );
+    }
+
+    #[test]
+    fn test_balance_hold_expiry() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 10, 0, 0).unwrap();
+        let clock = FixedClock::new(now);
+        let mut balance = Balance::new("USD").unwrap();
+        posted(&mut balance, Action::Deposit("100".to_string()));
+        let hold = Hold::new_with_clock("80", time_ordered(), &clock)
+            .unwrap()
+            .with_expiry(now + Duration::days(1));
+        let hold_id = hold.id().to_string();
+        balance.place_hold(hold).unwrap();
+
+        let withdraw = |clock: &FixedClock| {
+            Ledger::new_with_clock(Action::Withdrawal("30".to_string()), time_ordered(), clock)
+                .unwrap()
+        };
+        assert_eq!(
+            balance.mutate(withdraw(&clock)),
+            Err(BalanceError::BalanceNotEnough)
+        );
+        assert_eq!(balance.available_at(now + Duration::days(1)), dec!(100));
+
+        clock.advance(Duration::days(1));
+        assert_eq!(balance.mutate(withdraw(&clock)), Ok(dec!(70)));
+        assert_eq!(
+            balance.capture(&hold_id, None, time_ordered(), &clock),
+            Err(BalanceError::HoldExpired)
+        );
+
+        let expired = balance.expire_holds(clock.now());
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id(), hold_id);
+        assert!(balance.holds().is_empty());
+    }
+
+    #[test]
+    fn test_balance_hold_respects_minor_units() {
+        let mut balance = Balance::new("USD").unwrap();
+        posted(&mut balance, Action::Deposit("1".to_string()));
+        assert_eq!(
+            balance.place_hold(Hold::new("0.001", time_ordered()).unwrap()),
+            Err(BalanceError::ExcessPrecision {
+                scale: 3,
+                minor_units: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_balance_overdraft_policy() {
+        let mut balance = Balance::new("USD")
+            .unwrap()
+            .with_policy(Policy::overdraft("100").unwrap());
+        posted(&mut balance, Action::Deposit("20".to_string()));
+        assert_eq!(balance.headroom(), dec!(120));
+
+        posted(&mut balance, Action::Withdrawal("90".to_string()));
+        assert_eq!(balance.amount(), dec!(-70));
+        assert_eq!(balance.headroom(), dec!(30));
+
+        let withdrawal =
+            Ledger::new(Action::Withdrawal("30.01".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            balance.mutate(withdrawal),
+            Err(BalanceError::OverdraftLimitExceeded { headroom: dec!(30) })
+        );
+        assert_eq!(
+            balance.place_hold(Hold::new("31", time_ordered()).unwrap()),
+            Err(BalanceError::OverdraftLimitExceeded { headroom: dec!(30) })
+        );
+        posted(&mut balance, Action::Withdrawal("30".to_string()));
+        assert_eq!(balance.amount(), dec!(-100));
+        assert_eq!(balance.headroom(), dec!(0));
+    }
+
+    #[test]
+    fn test_balance_minimum_balance_policy() {
+        let mut balance = Balance::new("USD")
+            .unwrap()
+            .with_policy(Policy::minimum_balance("50").unwrap());
+        // Credits are always accepted, even while below the minimum.
+        posted(&mut balance, Action::Deposit("40".to_string()));
+        assert_eq!(balance.headroom(), dec!(0));
+        let withdrawal = Ledger::new(Action::Withdrawal("1".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            balance.mutate(withdrawal),
+            Err(BalanceError::BelowMinimumBalance { headroom: dec!(0) })
+        );
+
+        posted(&mut balance, Action::Deposit("35".to_string()));
+        let fee = Ledger::new(Action::Fee("25.5".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            balance.mutate(fee),
+            Err(BalanceError::BelowMinimumBalance { headroom: dec!(25) })
+        );
+        posted(&mut balance, Action::Fee("25".to_string()));
+        assert_eq!(balance.amount(), dec!(50));
+    }
+
+    #[test]
+    fn test_balance_default_policy_forbids_overdraft() {
+        let mut balance = Balance::new("USD").unwrap();
+        assert_eq!(balance.policy(), Policy::NoOverdraft);
+        posted(&mut balance, Action::Deposit("10".to_string()));
+        let withdrawal =
+            Ledger::new(Action::Withdrawal("10.01".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            balance.mutate(withdrawal),
+            Err(BalanceError::BalanceNotEnough)
+        );
+        assert_eq!(balance.headroom(), dec!(10));
+    }
+
+    #[test]
+    fn test_balance_velocity_limits() {
+        let start = Utc.with_ymd_and_hms(2024, 7, 1, 9, 0, 0).unwrap();
+        let clock = FixedClock::new(start);
+        let limits = VelocityLimits {
+            daily: Limit {
+                max_amount: Some(dec!(100)),
+                max_count: Some(3),
+            },
+            monthly: Limit {
+                max_amount: Some(dec!(250)),
+                max_count: None,
+            },
+        };
+        let mut balance = Balance::new("USD").unwrap().with_limits(limits);
+        let mut post = |action: Action, clock: &FixedClock| {
+            let ledger = Ledger::new_with_clock(action, time_ordered(), clock).unwrap();
+            balance.mutate(ledger)
+        };
+        let withdraw = |amount: &str| Action::Withdrawal(amount.to_string());
+        post(Action::Deposit("1000".to_string()), &clock).unwrap();
+
+        post(withdraw("60"), &clock).unwrap();
+        assert_eq!(
+            post(withdraw("40.01"), &clock),
+            Err(BalanceError::VelocityLimitExceeded(
+                VelocityLimit::DailyAmount
+            ))
+        );
+        post(withdraw("20"), &clock).unwrap();
+        // Fees are exempt, but outgoing transfers count like withdrawals.
+        post(Action::Fee("500".to_string()), &clock).unwrap();
+        post(Action::TransferOut("10".to_string()), &clock).unwrap();
+        assert_eq!(
+            post(withdraw("1"), &clock),
+            Err(BalanceError::VelocityLimitExceeded(
+                VelocityLimit::DailyCount
+            ))
+        );
+
+        // The daily window rolls over 24 hours after the first withdrawal.
+        clock.advance(Duration::hours(24));
+        post(withdraw("100"), &clock).unwrap();
+        clock.advance(Duration::days(1));
+        assert_eq!(
+            post(withdraw("60.01"), &clock),
+            Err(BalanceError::VelocityLimitExceeded(
+                VelocityLimit::MonthlyAmount
+            ))
+        );
+        post(withdraw("60"), &clock).unwrap();
+
+        // Once the first day's withdrawals leave the 30-day window, their
+        // amount frees up again.
+        clock.set(start + Duration::days(30));
+        post(withdraw("90"), &clock).unwrap();
+        assert_eq!(balance.amount(), dec!(160));
+    }
+
+    #[test]
+    fn test_balance_velocity_limits_per_account() {
+        let daily = Limit {
+            max_amount: None,
+            max_count: Some(1),
+        };
+        let limited = VelocityLimits {
+            daily,
+            ..VelocityLimits::default()
+        };
+        let mut balance = Balance::new("USD").unwrap().with_limits(limited);
+        let mut unlimited = Balance::new("USD").unwrap();
+        assert_eq!(unlimited.limits(), VelocityLimits::default());
+        for balance in [&mut balance, &mut unlimited] {
+            posted(balance, Action::Deposit("10".to_string()));
+            posted(balance, Action::Withdrawal("1".to_string()));
+        }
+
+        let withdrawal =
+            || Ledger::new(Action::Withdrawal("1".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            balance.mutate(withdrawal()),
+            Err(BalanceError::VelocityLimitExceeded(
+                VelocityLimit::DailyCount
+            ))
+        );
+        assert_eq!(unlimited.mutate(withdrawal()), Ok(dec!(8)));
+    }
+
+    #[test]
+    fn test_balance_frozen_and_dormant_accept_only_credits() {
+        for status in [AccountStatus::Frozen, AccountStatus::Dormant] {
+            let error = || match status {
+                AccountStatus::Frozen => BalanceError::AccountFrozen,
+                _ => BalanceError::AccountDormant,
+            };
+            let mut balance = Balance::new("USD").unwrap();
+            let deposit = posted(&mut balance, Action::Deposit("50".to_string()));
+            balance.transition(status, "review").unwrap();
+            assert_eq!(balance.status(), status);
+
+            let withdrawal =
+                Ledger::new(Action::Withdrawal("1".to_string()), time_ordered()).unwrap();
+            assert_eq!(balance.mutate(withdrawal), Err(error()));
+            assert_eq!(
+                balance.reverse(&deposit, time_ordered(), &SystemClock),
+                Err(error())
+            );
+            assert_eq!(
+                balance.place_hold(Hold::new("1", time_ordered()).unwrap()),
+                Err(error())
+            );
+            posted(&mut balance, Action::Interest("0.5".to_string()));
+
+            balance
+                .transition(AccountStatus::Active, "review passed")
+                .unwrap();
+            posted(&mut balance, Action::Withdrawal("10".to_string()));
+            assert_eq!(balance.amount(), dec!(40.5));
+        }
+    }
+
+    #[test]
+    fn test_balance_close() {
+        let mut balance = Balance::new("USD")
+            .unwrap()
+            .with_policy(Policy::overdraft("5").unwrap());
+        posted(&mut balance, Action::Deposit("20".to_string()));
+        assert_eq!(
+            balance.transition(AccountStatus::Closed, "customer request"),
+            Err(BalanceError::NonZeroBalance)
+        );
+        posted(&mut balance, Action::Withdrawal("20".to_string()));
+        let hold = Hold::new("5", time_ordered()).unwrap();
+        let hold_id = hold.id().to_string();
+        balance.place_hold(hold).unwrap();
+        assert_eq!(
+            balance.transition(AccountStatus::Closed, "customer request"),
+            Err(BalanceError::HoldsOutstanding)
+        );
+        balance.release(&hold_id).unwrap();
+        balance
+            .transition(AccountStatus::Closed, "customer request")
+            .unwrap();
+
+        let deposit = Ledger::new(Action::Deposit("1".to_string()), time_ordered()).unwrap();
+        assert_eq!(balance.mutate(deposit), Err(BalanceError::AccountClosed));
+        assert_eq!(
+            balance.transition(AccountStatus::Active, "reopen"),
+            Err(BalanceError::InvalidTransition {
+                from: AccountStatus::Closed,
+                to: AccountStatus::Active
+            })
+        );
+    }
+
+    #[test]
+    fn test_balance_check_replaces() {
+        let mut stored = Balance::new("USD").unwrap();
+        posted(&mut stored, Action::Deposit("20".to_string()));
+        stored.transition(AccountStatus::Frozen, "review").unwrap();
+        let with_change = |from, to| {
+            let mut balance = stored.clone();
+            let mut history = stored.status_history().to_vec();
+            history.push(StatusChange::new(from, to, "edited", &SystemClock));
+            balance.restore_status_history(history);
+            balance
+        };
+
+        let mut resolved = stored.clone();
+        resolved
+            .transition(AccountStatus::Active, "review passed")
+            .unwrap();
+        assert_eq!(resolved.check_replaces(&stored), Ok(()));
+        assert_eq!(
+            Balance::new("USD").unwrap().check_replaces(&stored),
+            Err(BalanceError::StatusHistoryRewritten)
+        );
+        assert_eq!(
+            with_change(AccountStatus::Frozen, AccountStatus::Dormant).check_replaces(&stored),
+            Err(BalanceError::InvalidTransition {
+                from: AccountStatus::Frozen,
+                to: AccountStatus::Dormant
+            })
+        );
+        assert_eq!(
+            with_change(AccountStatus::Frozen, AccountStatus::Closed).check_replaces(&stored),
+            Err(BalanceError::NonZeroBalance)
+        );
+
+        let mut closed = Balance::new("USD").unwrap();
+        closed
+            .transition(AccountStatus::Closed, "customer request")
+            .unwrap();
+        assert_eq!(
+            closed.clone().check_replaces(&closed),
+            Err(BalanceError::AccountClosed)
+        );
+    }
+
+    #[test]
+    fn test_balance_status_history() {
+        let at = Utc.with_ymd_and_hms(2024, 8, 1, 12, 0, 0).unwrap();
+        let clock = FixedClock::new(at);
+        let mut balance = Balance::new("USD").unwrap();
+        assert_eq!(balance.status(), AccountStatus::Active);
+        assert_eq!(
+            balance.transition(AccountStatus::Frozen, " "),
+            Err(BalanceError::MissingReason)
+        );
+        assert_eq!(
+            balance.transition(AccountStatus::Active, "noop"),
+            Err(BalanceError::InvalidTransition {
+                from: AccountStatus::Active,
+                to: AccountStatus::Active
+            })
+        );
+
+        balance
+            .transition_with_clock(AccountStatus::Dormant, "no activity for a year", &clock)
+            .unwrap();
+        clock.advance(Duration::days(3));
+        let change = balance
+            .transition_with_clock(AccountStatus::Frozen, "suspected fraud", &clock)
+            .unwrap();
+        assert_eq!(change.from(), AccountStatus::Dormant);
+        assert_eq!(change.to(), AccountStatus::Frozen);
+        assert_eq!(change.reason(), "suspected fraud");
+        assert_eq!(change.changed_at(), at + Duration::days(3));
+
+        let history = balance.status_history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].changed_at(), at);
+        assert_eq!(history[1], change);
+    }
 }
@@ -0,0 +1,404 @@
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::storage::{Storage, StorageError};
+
+#[derive(Debug, PartialEq)]
+pub enum ChartError {
+    InvalidCode(String),
+    CodeAlreadyExists,
+    CodeNotExists,
+    ParentNotExists,
+    TypeMismatch { parent: AccountType },
+    AlreadyAssigned { code: AccountCode },
+}
+
+/// Which side of the books an account normally carries its balance on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalBalance {
+    Debit,
+    Credit,
+}
+
+impl NormalBalance {
+    /// Restates a stored balance on this side, so an account holding its
+    /// usual kind of balance reports a positive amount.
+    ///
+    /// Stored amounts grow with credits: a customer deposit is +100 on the
+    /// customer's account and -100 on cash.
+    pub fn present(&self, amount: Decimal) -> Decimal {
+        match self {
+            NormalBalance::Debit => -amount,
+            NormalBalance::Credit => amount,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountType {
+    Asset,
+    Liability,
+    Equity,
+    Income,
+    Expense,
+}
+
+impl AccountType {
+    pub fn normal_balance(&self) -> NormalBalance {
+        match self {
+            AccountType::Asset | AccountType::Expense => NormalBalance::Debit,
+            AccountType::Liability | AccountType::Equity | AccountType::Income => {
+                NormalBalance::Credit
+            }
+        }
+    }
+}
+
+/// A dotted account code such as `1.2.10`. Codes sort segment by segment,
+/// so `1.2` comes before `1.10` and every parent before its children.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AccountCode(Vec<u32>);
+
+impl AccountCode {
+    /// The code one level up, e.g. `1.2` for `1.2.10`.
+    pub fn parent(&self) -> Option<AccountCode> {
+        match self.0.len() {
+            1 => None,
+            len => Some(AccountCode(self.0[..len - 1].to_vec())),
+        }
+    }
+
+    /// Levels below the top; `0` for `1`, `2` for `1.2.10`.
+    pub fn depth(&self) -> usize {
+        self.0.len() - 1
+    }
+}
+
+impl FromStr for AccountCode {
+    type Err = ChartError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ChartError::InvalidCode(s.to_string());
+        let segments = s
+            .split('.')
+            .map(|segment| {
+                if segment.is_empty() || !segment.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                segment.parse().map_err(|_| invalid())
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(AccountCode(segments))
+    }
+}
+
+impl fmt::Display for AccountCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let segments: Vec<String> = self.0.iter().map(u32::to_string).collect();
+        f.write_str(&segments.join("."))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChartEntry {
+    pub code: AccountCode,
+    pub name: String,
+    pub account_type: AccountType,
+    /// Storage accounts booked under this code.
+    pub account_ids: Vec<String>,
+}
+
+/// Names and classifies the storage accounts. Every code but a top-level
+/// one sits under its parent and shares its type.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChartOfAccounts {
+    entries: BTreeMap<AccountCode, ChartEntry>,
+    assigned: HashMap<String, AccountCode>,
+}
+
+impl ChartOfAccounts {
+    pub fn new() -> ChartOfAccounts {
+        ChartOfAccounts::default()
+    }
+
+    pub fn add(
+        &mut self,
+        code: &str,
+        name: &str,
+        account_type: AccountType,
+    ) -> Result<AccountCode, ChartError> {
+        let code: AccountCode = code.parse()?;
+        if self.entries.contains_key(&code) {
+            return Err(ChartError::CodeAlreadyExists);
+        }
+        if let Some(parent) = code.parent() {
+            let parent = self
+                .entries
+                .get(&parent)
+                .ok_or(ChartError::ParentNotExists)?;
+            if parent.account_type != account_type {
+                return Err(ChartError::TypeMismatch {
+                    parent: parent.account_type,
+                });
+            }
+        }
+        self.entries.insert(
+            code.clone(),
+            ChartEntry {
+                code: code.clone(),
+                name: name.to_string(),
+                account_type,
+                account_ids: vec![],
+            },
+        );
+        Ok(code)
+    }
+
+    /// Books the storage account `account_id` under `code`. An account can
+    /// only be booked under one code.
+    pub fn assign(&mut self, code: &str, account_id: &str) -> Result<(), ChartError> {
+        let code: AccountCode = code.parse()?;
+        let entry = self
+            .entries
+            .get_mut(&code)
+            .ok_or(ChartError::CodeNotExists)?;
+        if let Some(code) = self.assigned.get(account_id) {
+            return Err(ChartError::AlreadyAssigned { code: code.clone() });
+        }
+        entry.account_ids.push(account_id.to_string());
+        self.assigned.insert(account_id.to_string(), code);
+        Ok(())
+    }
+
+    pub fn entry(&self, code: &str) -> Option<&ChartEntry> {
+        self.entries.get(&code.parse().ok()?)
+    }
+
+    /// The code `account_id` is booked under.
+    pub fn code_of(&self, account_id: &str) -> Option<&AccountCode> {
+        self.assigned.get(account_id)
+    }
+
+    /// Entries in code order.
+    pub fn entries(&self) -> impl Iterator<Item = &ChartEntry> + '_ {
+        self.entries.values()
+    }
+
+    /// Every code's balance, including all of its descendants', per
+    /// currency and stated on its normal side. Each account's pockets are
+    /// included.
+    pub fn roll_up<S: Storage>(&self, storage: &S) -> Result<RollUp, StorageError> {
+        let mut totals: BTreeMap<&AccountCode, BTreeMap<String, Decimal>> = BTreeMap::new();
+        for (code, entry) in &self.entries {
+            let own = totals.entry(code).or_default();
+            for account_id in &entry.account_ids {
+                for balance in storage.wallet(account_id)?.pockets() {
+                    *own.entry(balance.currency.clone()).or_default() += balance.amount();
+                }
+            }
+        }
+        // Children sort after their parents, so walking backwards folds
+        // every subtree up before its root is reached.
+        for code in self.entries.keys().rev() {
+            let Some(parent) = code.parent() else {
+                continue;
+            };
+            let child = totals[code].clone();
+            let parent = totals.get_mut(&parent).unwrap();
+            for (currency, amount) in child {
+                *parent.entry(currency).or_default() += amount;
+            }
+        }
+
+        let lines = self
+            .entries
+            .values()
+            .map(|entry| {
+                let side = entry.account_type.normal_balance();
+                RollUpLine {
+                    code: entry.code.clone(),
+                    name: entry.name.clone(),
+                    account_type: entry.account_type,
+                    totals: totals[&entry.code]
+                        .iter()
+                        .map(|(currency, amount)| (currency.clone(), side.present(*amount)))
+                        .collect(),
+                }
+            })
+            .collect();
+        Ok(RollUp { lines })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollUpLine {
+    pub code: AccountCode,
+    pub name: String,
+    pub account_type: AccountType,
+    pub totals: BTreeMap<String, Decimal>,
+}
+
+impl RollUpLine {
+    /// The line's total in `currency`, zero if it has none.
+    pub fn total(&self, currency: &str) -> Decimal {
+        self.totals.get(currency).copied().unwrap_or_default()
+    }
+}
+
+/// Balances of a `ChartOfAccounts`, one line per code in code order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollUp {
+    lines: Vec<RollUpLine>,
+}
+
+impl RollUp {
+    pub fn lines(&self) -> &[RollUpLine] {
+        &self.lines
+    }
+
+    pub fn line(&self, code: &str) -> Option<&RollUpLine> {
+        let code: AccountCode = code.parse().ok()?;
+        self.lines.iter().find(|line| line.code == code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::Balance;
+    use crate::journal::{InternalAccount, Transaction};
+    use crate::storage::InMemory;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_account_code_ordering_and_parents() {
+        let code = |s: &str| s.parse::<AccountCode>().unwrap();
+        assert!(code("1.2") < code("1.10"));
+        assert!(code("1") < code("1.0"));
+        assert_eq!(code("1.2.10").parent(), Some(code("1.2")));
+        assert_eq!(code("1").parent(), None);
+        assert_eq!(code("1.2.10").depth(), 2);
+        assert_eq!(code("01.2").to_string(), "1.2");
+        for invalid in ["", "1.", ".1", "1..2", "1.a", "-1", "99999999999"] {
+            assert_eq!(
+                invalid.parse::<AccountCode>(),
+                Err(ChartError::InvalidCode(invalid.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_chart_rejects_invalid_structure() {
+        let mut chart = ChartOfAccounts::new();
+        chart.add("1", "Assets", AccountType::Asset).unwrap();
+        assert_eq!(
+            chart.add("1", "Assets", AccountType::Asset),
+            Err(ChartError::CodeAlreadyExists)
+        );
+        assert_eq!(
+            chart.add("2.1", "Deposits", AccountType::Liability),
+            Err(ChartError::ParentNotExists)
+        );
+        assert_eq!(
+            chart.add("1.1", "Deposits", AccountType::Liability),
+            Err(ChartError::TypeMismatch {
+                parent: AccountType::Asset
+            })
+        );
+        chart.add("1.1", "Cash", AccountType::Asset).unwrap();
+        chart.assign("1.1", "internal:cash:USD").unwrap();
+        assert_eq!(
+            chart.assign("1", "internal:cash:USD"),
+            Err(ChartError::AlreadyAssigned {
+                code: "1.1".parse().unwrap()
+            })
+        );
+        assert_eq!(
+            chart.assign("3", "account_1"),
+            Err(ChartError::CodeNotExists)
+        );
+        assert_eq!(
+            chart.code_of("internal:cash:USD").unwrap().to_string(),
+            "1.1"
+        );
+    }
+
+    #[test]
+    fn test_roll_up_sums_children_on_their_normal_side() {
+        let mut storage = InMemory::new();
+        storage
+            .insert("account_1", Balance::new("USD").unwrap())
+            .unwrap();
+        storage
+            .insert("account_2", Balance::new("USD").unwrap())
+            .unwrap();
+        storage.open_pocket("account_2", "EUR").unwrap();
+        for currency in ["USD", "EUR"] {
+            for account in InternalAccount::ALL {
+                account.open(&mut storage, currency).unwrap();
+            }
+        }
+        for transaction in [
+            Transaction::deposit("account_1", "USD", "100").unwrap(),
+            Transaction::deposit("account_2", "USD", "50").unwrap(),
+            Transaction::deposit("account_2", "EUR", "30").unwrap(),
+            Transaction::fee("account_1", "USD", "4").unwrap(),
+            Transaction::withdrawal("account_2", "USD", "10").unwrap(),
+        ] {
+            storage.post(transaction).unwrap();
+        }
+
+        let mut chart = ChartOfAccounts::new();
+        chart.add("1", "Assets", AccountType::Asset).unwrap();
+        chart.add("1.1", "Cash", AccountType::Asset).unwrap();
+        chart.add("1.1.1", "Cash USD", AccountType::Asset).unwrap();
+        chart.add("1.1.2", "Cash EUR", AccountType::Asset).unwrap();
+        chart
+            .add("2", "Liabilities", AccountType::Liability)
+            .unwrap();
+        chart
+            .add("2.1", "Customer deposits", AccountType::Liability)
+            .unwrap();
+        chart
+            .add("2.2", "Suspense", AccountType::Liability)
+            .unwrap();
+        chart.add("4", "Income", AccountType::Income).unwrap();
+        chart.add("4.1", "Fees", AccountType::Income).unwrap();
+        chart.assign("1.1.1", "internal:cash:USD").unwrap();
+        chart.assign("1.1.2", "internal:cash:EUR").unwrap();
+        chart.assign("2.1", "account_1").unwrap();
+        chart.assign("2.1", "account_2").unwrap();
+        chart.assign("2.2", "internal:suspense:USD").unwrap();
+        chart.assign("4.1", "internal:fees_income:USD").unwrap();
+
+        let roll_up = chart.roll_up(&storage).unwrap();
+        let codes: Vec<String> = roll_up
+            .lines()
+            .iter()
+            .map(|line| line.code.to_string())
+            .collect();
+        assert_eq!(
+            codes,
+            ["1", "1.1", "1.1.1", "1.1.2", "2", "2.1", "2.2", "4", "4.1"]
+        );
+        let assets = roll_up.line("1").unwrap();
+        assert_eq!(assets.total("USD"), dec!(140));
+        assert_eq!(assets.total("EUR"), dec!(30));
+        assert_eq!(roll_up.line("1.1.2").unwrap().total("USD"), dec!(0));
+        let liabilities = roll_up.line("2").unwrap();
+        assert_eq!(liabilities.total("USD"), dec!(136));
+        assert_eq!(liabilities.total("EUR"), dec!(30));
+        assert_eq!(roll_up.line("4").unwrap().total("USD"), dec!(4));
+        // Assets = liabilities + income, as nothing is left unbooked.
+        assert_eq!(
+            assets.total("USD"),
+            liabilities.total("USD") + roll_up.line("4").unwrap().total("USD")
+        );
+
+        chart.add("3", "Equity", AccountType::Equity).unwrap();
+        chart.assign("3", "account_9").unwrap();
+        assert_eq!(chart.roll_up(&storage), Err(StorageError::AccountNotExists));
+    }
+}
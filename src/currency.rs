@@ -0,0 +1,347 @@
+use rust_decimal::RoundingStrategy;
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use crate::ledger::MAX_AMOUNT_SCALE;
+
+#[derive(Debug, PartialEq)]
+pub enum CurrencyError {
+    InvalidCode,
+    InvalidMinorUnits(u32),
+    AlreadyRegistered,
+}
+
+/// What `Balance::mutate` does with an amount finer than the currency's
+/// minor units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    Reject,
+    Round(RoundingStrategy),
+}
+
+impl Rounding {
+    /// Deprecated strategies are stored as the strategy that replaced them.
+    #[allow(deprecated)]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rounding::Reject => "reject",
+            Rounding::Round(strategy) => match strategy {
+                RoundingStrategy::MidpointNearestEven | RoundingStrategy::BankersRounding => {
+                    "midpoint_nearest_even"
+                }
+                RoundingStrategy::MidpointAwayFromZero | RoundingStrategy::RoundHalfUp => {
+                    "midpoint_away_from_zero"
+                }
+                RoundingStrategy::MidpointTowardZero | RoundingStrategy::RoundHalfDown => {
+                    "midpoint_toward_zero"
+                }
+                RoundingStrategy::ToZero | RoundingStrategy::RoundDown => "to_zero",
+                RoundingStrategy::AwayFromZero | RoundingStrategy::RoundUp => "away_from_zero",
+                RoundingStrategy::ToNegativeInfinity => "to_negative_infinity",
+                RoundingStrategy::ToPositiveInfinity => "to_positive_infinity",
+            },
+        }
+    }
+
+    pub(crate) fn restore(name: &str) -> Option<Rounding> {
+        let strategy = match name {
+            "reject" => return Some(Rounding::Reject),
+            "midpoint_nearest_even" => RoundingStrategy::MidpointNearestEven,
+            "midpoint_away_from_zero" => RoundingStrategy::MidpointAwayFromZero,
+            "midpoint_toward_zero" => RoundingStrategy::MidpointTowardZero,
+            "to_zero" => RoundingStrategy::ToZero,
+            "away_from_zero" => RoundingStrategy::AwayFromZero,
+            "to_negative_infinity" => RoundingStrategy::ToNegativeInfinity,
+            "to_positive_infinity" => RoundingStrategy::ToPositiveInfinity,
+            _ => return None,
+        };
+        Some(Rounding::Round(strategy))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Currency {
+    pub code: String,
+    pub minor_units: u32,
+    pub symbol: String,
+}
+
+impl Currency {
+    pub fn new(code: &str, minor_units: u32, symbol: &str) -> Result<Currency, CurrencyError> {
+        if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(CurrencyError::InvalidCode);
+        }
+        if minor_units > MAX_AMOUNT_SCALE {
+            return Err(CurrencyError::InvalidMinorUnits(minor_units));
+        }
+        Ok(Currency {
+            code: code.to_uppercase(),
+            minor_units,
+            symbol: symbol.to_string(),
+        })
+    }
+}
+
+// Active ISO 4217 codes with their minor-unit exponent and display symbol.
+const ISO_4217: &[(&str, u32, &str)] = &[
+    ("AED", 2, "د.إ"),
+    ("AFN", 2, "؋"),
+    ("ALL", 2, "L"),
+    ("AMD", 2, "֏"),
+    ("ANG", 2, "ƒ"),
+    ("AOA", 2, "Kz"),
+    ("ARS", 2, "$"),
+    ("AUD", 2, "A$"),
+    ("AWG", 2, "ƒ"),
+    ("AZN", 2, "₼"),
+    ("BAM", 2, "KM"),
+    ("BBD", 2, "$"),
+    ("BDT", 2, "৳"),
+    ("BGN", 2, "лв"),
+    ("BHD", 3, ".د.ب"),
+    ("BIF", 0, "FBu"),
+    ("BMD", 2, "$"),
+    ("BND", 2, "$"),
+    ("BOB", 2, "Bs."),
+    ("BRL", 2, "R$"),
+    ("BSD", 2, "$"),
+    ("BTN", 2, "Nu."),
+    ("BWP", 2, "P"),
+    ("BYN", 2, "Br"),
+    ("BZD", 2, "$"),
+    ("CAD", 2, "C$"),
+    ("CDF", 2, "FC"),
+    ("CHF", 2, "CHF"),
+    ("CLP", 0, "$"),
+    ("CNY", 2, "¥"),
+    ("COP", 2, "$"),
+    ("CRC", 2, "₡"),
+    ("CUP", 2, "$"),
+    ("CVE", 2, "$"),
+    ("CZK", 2, "Kč"),
+    ("DJF", 0, "Fdj"),
+    ("DKK", 2, "kr"),
+    ("DOP", 2, "$"),
+    ("DZD", 2, "د.ج"),
+    ("EGP", 2, "£"),
+    ("ERN", 2, "Nfk"),
+    ("ETB", 2, "Br"),
+    ("EUR", 2, "€"),
+    ("FJD", 2, "$"),
+    ("FKP", 2, "£"),
+    ("GBP", 2, "£"),
+    ("GEL", 2, "₾"),
+    ("GHS", 2, "₵"),
+    ("GIP", 2, "£"),
+    ("GMD", 2, "D"),
+    ("GNF", 0, "FG"),
+    ("GTQ", 2, "Q"),
+    ("GYD", 2, "$"),
+    ("HKD", 2, "HK$"),
+    ("HNL", 2, "L"),
+    ("HTG", 2, "G"),
+    ("HUF", 2, "Ft"),
+    ("IDR", 2, "Rp"),
+    ("ILS", 2, "₪"),
+    ("INR", 2, "₹"),
+    ("IQD", 3, "ع.د"),
+    ("IRR", 2, "﷼"),
+    ("ISK", 0, "kr"),
+    ("JMD", 2, "$"),
+    ("JOD", 3, "د.ا"),
+    ("JPY", 0, "¥"),
+    ("KES", 2, "KSh"),
+    ("KGS", 2, "с"),
+    ("KHR", 2, "៛"),
+    ("KMF", 0, "CF"),
+    ("KPW", 2, "₩"),
+    ("KRW", 0, "₩"),
+    ("KWD", 3, "د.ك"),
+    ("KYD", 2, "$"),
+    ("KZT", 2, "₸"),
+    ("LAK", 2, "₭"),
+    ("LBP", 2, "ل.ل"),
+    ("LKR", 2, "Rs"),
+    ("LRD", 2, "$"),
+    ("LSL", 2, "L"),
+    ("LYD", 3, "ل.د"),
+    ("MAD", 2, "د.م."),
+    ("MDL", 2, "L"),
+    ("MGA", 2, "Ar"),
+    ("MKD", 2, "ден"),
+    ("MMK", 2, "K"),
+    ("MNT", 2, "₮"),
+    ("MOP", 2, "MOP$"),
+    ("MRU", 2, "UM"),
+    ("MUR", 2, "₨"),
+    ("MVR", 2, "Rf"),
+    ("MWK", 2, "MK"),
+    ("MXN", 2, "$"),
+    ("MYR", 2, "RM"),
+    ("MZN", 2, "MT"),
+    ("NAD", 2, "$"),
+    ("NGN", 2, "₦"),
+    ("NIO", 2, "C$"),
+    ("NOK", 2, "kr"),
+    ("NPR", 2, "₨"),
+    ("NZD", 2, "NZ$"),
+    ("OMR", 3, "ر.ع."),
+    ("PAB", 2, "B/."),
+    ("PEN", 2, "S/"),
+    ("PGK", 2, "K"),
+    ("PHP", 2, "₱"),
+    ("PKR", 2, "₨"),
+    ("PLN", 2, "zł"),
+    ("PYG", 0, "₲"),
+    ("QAR", 2, "ر.ق"),
+    ("RON", 2, "lei"),
+    ("RSD", 2, "дин."),
+    ("RUB", 2, "₽"),
+    ("RWF", 0, "FRw"),
+    ("SAR", 2, "ر.س"),
+    ("SBD", 2, "$"),
+    ("SCR", 2, "₨"),
+    ("SDG", 2, "ج.س."),
+    ("SEK", 2, "kr"),
+    ("SGD", 2, "S$"),
+    ("SHP", 2, "£"),
+    ("SLE", 2, "Le"),
+    ("SOS", 2, "Sh"),
+    ("SRD", 2, "$"),
+    ("SSP", 2, "£"),
+    ("STN", 2, "Db"),
+    ("SYP", 2, "£"),
+    ("SZL", 2, "L"),
+    ("THB", 2, "฿"),
+    ("TJS", 2, "SM"),
+    ("TMT", 2, "m"),
+    ("TND", 3, "د.ت"),
+    ("TOP", 2, "T$"),
+    ("TRY", 2, "₺"),
+    ("TTD", 2, "$"),
+    ("TWD", 2, "NT$"),
+    ("TZS", 2, "TSh"),
+    ("UAH", 2, "₴"),
+    ("UGX", 0, "USh"),
+    ("USD", 2, "$"),
+    ("UYU", 2, "$"),
+    ("UZS", 2, "soʻm"),
+    ("VES", 2, "Bs.S"),
+    ("VND", 0, "₫"),
+    ("VUV", 0, "VT"),
+    ("WST", 2, "T"),
+    ("XAF", 0, "FCFA"),
+    ("XCD", 2, "$"),
+    ("XOF", 0, "CFA"),
+    ("XPF", 0, "₣"),
+    ("YER", 2, "﷼"),
+    ("ZAR", 2, "R"),
+    ("ZMW", 2, "ZK"),
+    ("ZWL", 2, "$"),
+];
+
+#[derive(Debug, Default)]
+pub struct CurrencyRegistry {
+    currencies: HashMap<String, Currency>,
+}
+
+impl CurrencyRegistry {
+    pub fn new() -> CurrencyRegistry {
+        CurrencyRegistry::default()
+    }
+
+    pub fn with_iso() -> CurrencyRegistry {
+        let mut registry = CurrencyRegistry::new();
+        for (code, minor_units, symbol) in ISO_4217 {
+            let currency = Currency::new(code, *minor_units, symbol).unwrap();
+            registry.currencies.insert(currency.code.clone(), currency);
+        }
+        registry
+    }
+
+    pub fn register(&mut self, currency: Currency) -> Result<(), CurrencyError> {
+        if self.currencies.contains_key(&currency.code) {
+            return Err(CurrencyError::AlreadyRegistered);
+        }
+        self.currencies.insert(currency.code.clone(), currency);
+        Ok(())
+    }
+
+    pub fn get(&self, code: &str) -> Option<Currency> {
+        self.currencies.get(&code.to_uppercase()).cloned()
+    }
+}
+
+fn global() -> &'static RwLock<CurrencyRegistry> {
+    static REGISTRY: OnceLock<RwLock<CurrencyRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(CurrencyRegistry::with_iso()))
+}
+
+/// Registers a custom currency (loyalty points, crypto, ...) in the
+/// process-wide registry used by `Balance::new`.
+pub fn register(currency: Currency) -> Result<(), CurrencyError> {
+    global().write().unwrap().register(currency)
+}
+
+pub fn lookup(code: &str) -> Option<Currency> {
+    global().read().unwrap().get(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_iso_currency() {
+        let usd = lookup("usd").unwrap();
+        assert_eq!(usd.code, "USD");
+        assert_eq!(usd.minor_units, 2);
+        assert_eq!(usd.symbol, "$");
+        assert_eq!(lookup("JPY").unwrap().minor_units, 0);
+        assert_eq!(lookup("KWD").unwrap().minor_units, 3);
+    }
+
+    #[test]
+    fn test_lookup_unknown_currency() {
+        assert_eq!(lookup("XYZ"), None);
+    }
+
+    #[test]
+    fn test_register_custom_currency() {
+        let btc = Currency::new("BTC", 8, "₿").unwrap();
+        assert_eq!(register(btc.clone()), Ok(()));
+        assert_eq!(lookup("btc"), Some(btc.clone()));
+        assert_eq!(register(btc), Err(CurrencyError::AlreadyRegistered));
+    }
+
+    #[test]
+    fn test_register_iso_currency_twice() {
+        let usd = Currency::new("USD", 2, "$").unwrap();
+        assert_eq!(register(usd), Err(CurrencyError::AlreadyRegistered));
+    }
+
+    #[test]
+    fn test_currency_new_invalid() {
+        assert_eq!(Currency::new("", 2, "$"), Err(CurrencyError::InvalidCode));
+        assert_eq!(
+            Currency::new("U D", 2, "$"),
+            Err(CurrencyError::InvalidCode)
+        );
+        assert_eq!(
+            Currency::new("PTS", 19, "pts"),
+            Err(CurrencyError::InvalidMinorUnits(19))
+        );
+    }
+
+    #[test]
+    fn test_rounding_round_trip() {
+        for rounding in [
+            Rounding::Reject,
+            Rounding::Round(RoundingStrategy::MidpointNearestEven),
+            Rounding::Round(RoundingStrategy::ToNegativeInfinity),
+        ] {
+            assert_eq!(Rounding::restore(rounding.as_str()), Some(rounding));
+        }
+        assert_eq!(Rounding::restore("nearest"), None);
+    }
+}
@@ -0,0 +1,352 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::currency;
+use crate::ledger::parse_amount;
+
+#[derive(Debug, PartialEq)]
+pub enum FxError {
+    MissingRate {
+        from: String,
+        to: String,
+    },
+    InvalidRate(String),
+    InvalidMarkup(String),
+    InvalidCurrency,
+    SameCurrency,
+    AmountTooSmall,
+    InvalidRatesFile {
+        line: usize,
+    },
+    Io(String),
+    /// The converted amount doesn't fit in a `Decimal`.
+    Overflow,
+}
+
+/// Source of mid-market exchange rates. `rate(from, to)` is how many units
+/// of `to` one unit of `from` buys; codes are upper case.
+pub trait RateProvider {
+    fn rate(&self, from: &str, to: &str) -> Option<Decimal>;
+}
+
+impl<F: Fn(&str, &str) -> Option<Decimal>> RateProvider for F {
+    fn rate(&self, from: &str, to: &str) -> Option<Decimal> {
+        self(from, to)
+    }
+}
+
+fn parse_rate(rate: &str) -> Result<Decimal, FxError> {
+    let rate = parse_amount(rate).map_err(|e| FxError::InvalidRate(format!("{e:?}")))?;
+    if rate.is_zero() {
+        return Err(FxError::InvalidRate("rate can't be zero".to_string()));
+    }
+    Ok(rate)
+}
+
+/// A fixed table of rates. A pair missing in one direction is answered
+/// with the inverse of the other.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StaticRates {
+    rates: HashMap<(String, String), Decimal>,
+}
+
+impl StaticRates {
+    pub fn new() -> StaticRates {
+        StaticRates::default()
+    }
+
+    pub fn with_rate(mut self, from: &str, to: &str, rate: &str) -> Result<StaticRates, FxError> {
+        let rate = parse_rate(rate)?;
+        self.rates
+            .insert((from.to_uppercase(), to.to_uppercase()), rate);
+        Ok(self)
+    }
+}
+
+impl RateProvider for StaticRates {
+    fn rate(&self, from: &str, to: &str) -> Option<Decimal> {
+        let (from, to) = (from.to_string(), to.to_string());
+        if let Some(rate) = self.rates.get(&(from.clone(), to.clone())) {
+            return Some(*rate);
+        }
+        let inverse = self.rates.get(&(to, from))?;
+        Decimal::ONE.checked_div(*inverse)
+    }
+}
+
+/// Rates read from a local file with one `FROM TO RATE` triple per line,
+/// e.g. `EUR USD 1.0850`. Blank lines and lines starting with `#` are
+/// skipped.
+#[derive(Debug)]
+pub struct FileRates {
+    path: PathBuf,
+    rates: StaticRates,
+}
+
+impl FileRates {
+    pub fn load(path: impl AsRef<Path>) -> Result<FileRates, FxError> {
+        let path = path.as_ref().to_path_buf();
+        let rates = read_rates(&path)?;
+        Ok(FileRates { path, rates })
+    }
+
+    /// Re-reads the file. On error the rates loaded before stay in use.
+    pub fn reload(&mut self) -> Result<(), FxError> {
+        self.rates = read_rates(&self.path)?;
+        Ok(())
+    }
+}
+
+impl RateProvider for FileRates {
+    fn rate(&self, from: &str, to: &str) -> Option<Decimal> {
+        self.rates.rate(from, to)
+    }
+}
+
+fn read_rates(path: &Path) -> Result<StaticRates, FxError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| FxError::Io(e.to_string()))?;
+    let mut rates = StaticRates::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || FxError::InvalidRatesFile { line: index + 1 };
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [from, to, rate] = fields[..] else {
+            return Err(invalid());
+        };
+        if currency::lookup(from).is_none() || currency::lookup(to).is_none() {
+            return Err(invalid());
+        }
+        rates = rates.with_rate(from, to, rate).map_err(|_| invalid())?;
+    }
+    Ok(rates)
+}
+
+/// Rates that only change when told to, counting how often they are
+/// consulted.
+#[derive(Debug, Default)]
+pub struct MockRates {
+    rates: Mutex<HashMap<(String, String), Decimal>>,
+    lookups: Mutex<usize>,
+}
+
+impl MockRates {
+    pub fn new() -> MockRates {
+        MockRates::default()
+    }
+
+    pub fn set(&self, from: &str, to: &str, rate: Decimal) {
+        self.rates
+            .lock()
+            .unwrap()
+            .insert((from.to_string(), to.to_string()), rate);
+    }
+
+    pub fn remove(&self, from: &str, to: &str) {
+        self.rates
+            .lock()
+            .unwrap()
+            .remove(&(from.to_string(), to.to_string()));
+    }
+
+    pub fn lookups(&self) -> usize {
+        *self.lookups.lock().unwrap()
+    }
+}
+
+impl RateProvider for MockRates {
+    fn rate(&self, from: &str, to: &str) -> Option<Decimal> {
+        *self.lookups.lock().unwrap() += 1;
+        let rates = self.rates.lock().unwrap();
+        rates.get(&(from.to_string(), to.to_string())).copied()
+    }
+}
+
+/// The terms of one conversion. Amounts are magnitudes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub from: String,
+    pub to: String,
+    pub mid_rate: Decimal,
+    /// `mid_rate` less the converter's markup; what the customer gets.
+    pub rate: Decimal,
+    pub source_amount: Decimal,
+    /// `source_amount * rate` rounded down to the target's minor units.
+    pub target_amount: Decimal,
+    /// What rounding `target_amount` dropped, in the target currency.
+    pub residue: Decimal,
+}
+
+/// Prices conversions from a `RateProvider`'s mid rates plus a markup.
+#[derive(Debug)]
+pub struct Converter<R> {
+    rates: R,
+    markup: Decimal,
+}
+
+impl<R: RateProvider> Converter<R> {
+    pub fn new(rates: R) -> Converter<R> {
+        Converter {
+            rates,
+            markup: Decimal::ZERO,
+        }
+    }
+
+    /// Charges `markup` as a fraction of the mid rate, e.g. `"0.005"` for
+    /// 50 basis points. Must be below 1.
+    pub fn with_markup(mut self, markup: &str) -> Result<Converter<R>, FxError> {
+        let markup = parse_amount(markup).map_err(|e| FxError::InvalidMarkup(format!("{e:?}")))?;
+        if markup >= Decimal::ONE {
+            return Err(FxError::InvalidMarkup("markup must be below 1".to_string()));
+        }
+        self.markup = markup;
+        Ok(self)
+    }
+
+    pub fn markup(&self) -> Decimal {
+        self.markup
+    }
+
+    pub fn rates(&self) -> &R {
+        &self.rates
+    }
+
+    /// Prices converting `amount` of `from` into `to`.
+    pub fn quote(&self, from: &str, to: &str, amount: Decimal) -> Result<Quote, FxError> {
+        let source = currency::lookup(from).ok_or(FxError::InvalidCurrency)?;
+        let target = currency::lookup(to).ok_or(FxError::InvalidCurrency)?;
+        if source.code == target.code {
+            return Err(FxError::SameCurrency);
+        }
+        let mid_rate = self
+            .rates
+            .rate(&source.code, &target.code)
+            .filter(|rate| rate.is_sign_positive() && !rate.is_zero())
+            .ok_or_else(|| FxError::MissingRate {
+                from: source.code.clone(),
+                to: target.code.clone(),
+            })?;
+        let rate = mid_rate
+            .checked_mul(Decimal::ONE - self.markup)
+            .ok_or(FxError::Overflow)?;
+        let exact = amount.abs().checked_mul(rate).ok_or(FxError::Overflow)?;
+        let target_amount =
+            exact.round_dp_with_strategy(target.minor_units, RoundingStrategy::ToZero);
+        if target_amount.is_zero() {
+            return Err(FxError::AmountTooSmall);
+        }
+        Ok(Quote {
+            from: source.code,
+            to: target.code,
+            mid_rate,
+            rate,
+            source_amount: amount.abs(),
+            target_amount,
+            residue: exact - target_amount,
+        })
+    }
+}
+
+/// A posted conversion. Its ledgers share `transfer_id`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversion {
+    pub transfer_id: String,
+    pub quote: Quote,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_util::TempPath;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_static_rates_answer_inverse_pairs() {
+        let rates = StaticRates::new().with_rate("eur", "usd", "1.25").unwrap();
+        assert_eq!(rates.rate("EUR", "USD"), Some(dec!(1.25)));
+        assert_eq!(rates.rate("USD", "EUR"), Some(dec!(0.8)));
+        assert_eq!(rates.rate("USD", "JPY"), None);
+        assert!(matches!(
+            StaticRates::new().with_rate("EUR", "USD", "0"),
+            Err(FxError::InvalidRate(_))
+        ));
+    }
+
+    #[test]
+    fn test_file_rates_load_and_reload() {
+        let path = TempPath::new("rates");
+        std::fs::write(&path, "# mid rates\nEUR USD 1.0850\n\nUSD JPY 149.5\n").unwrap();
+        let mut rates = FileRates::load(&path).unwrap();
+        assert_eq!(rates.rate("EUR", "USD"), Some(dec!(1.0850)));
+        assert_eq!(rates.rate("USD", "JPY"), Some(dec!(149.5)));
+
+        std::fs::write(&path, "EUR USD 1.09\nGBP USD\n").unwrap();
+        assert_eq!(rates.reload(), Err(FxError::InvalidRatesFile { line: 2 }));
+        assert_eq!(rates.rate("EUR", "USD"), Some(dec!(1.0850)));
+        std::fs::write(&path, "EUR USD 1.09\n").unwrap();
+        rates.reload().unwrap();
+        assert_eq!(rates.rate("EUR", "USD"), Some(dec!(1.09)));
+        assert_eq!(rates.rate("USD", "JPY"), None);
+
+        std::fs::write(&path, "EUR ABCD 1\n").unwrap();
+        assert_eq!(
+            FileRates::load(&path).unwrap_err(),
+            FxError::InvalidRatesFile { line: 1 }
+        );
+        assert!(matches!(
+            FileRates::load(TempPath::new("rates")),
+            Err(FxError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_quote_applies_markup_and_rounds_down() {
+        let rates = MockRates::new();
+        rates.set("USD", "JPY", dec!(149.37));
+        let converter = Converter::new(rates).with_markup("0.01").unwrap();
+        let quote = converter.quote("usd", "jpy", dec!(10.05)).unwrap();
+        assert_eq!(quote.rate, dec!(147.8763));
+        // 10.05 * 147.8763 = 1486.156815
+        assert_eq!(quote.target_amount, dec!(1486));
+        assert_eq!(quote.residue, dec!(0.156815));
+        assert_eq!(converter.rates().lookups(), 1);
+
+        assert_eq!(
+            converter.quote("JPY", "USD", dec!(1)),
+            Err(FxError::MissingRate {
+                from: "JPY".to_string(),
+                to: "USD".to_string()
+            })
+        );
+        assert_eq!(
+            converter.quote("USD", "JPY", dec!(0.001)),
+            Err(FxError::AmountTooSmall)
+        );
+        assert_eq!(
+            converter.quote("USD", "usd", dec!(1)),
+            Err(FxError::SameCurrency)
+        );
+        assert_eq!(
+            converter.quote("USD", "JPY", Decimal::MAX),
+            Err(FxError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_converter_rejects_invalid_markup() {
+        let converter = Converter::new(StaticRates::new());
+        assert_eq!(converter.markup(), dec!(0));
+        assert!(matches!(
+            Converter::new(StaticRates::new()).with_markup("1"),
+            Err(FxError::InvalidMarkup(_))
+        ));
+        assert!(matches!(
+            Converter::new(StaticRates::new()).with_markup("-0.1"),
+            Err(FxError::InvalidMarkup(_))
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+use chrono::{DateTime, SubsecRound, Utc};
+use rust_decimal::Decimal;
+use std::sync::Arc;
+
+use crate::clock::{Clock, SystemClock};
+use crate::id::IdGenerator;
+use crate::ledger::{parse_amount, LedgerError};
+
+/// Funds reserved on a balance ahead of settlement, e.g. a card
+/// authorization. A hold posts no ledger; it only lowers the available
+/// balance until it is captured, released or expires.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hold {
+    id: Arc<String>,
+    amount: Decimal,
+    placed_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl Hold {
+    pub fn new(amount: &str, ids: &dyn IdGenerator) -> Result<Hold, LedgerError> {
+        Hold::new_with_clock(amount, ids, &SystemClock)
+    }
+
+    /// Like `new`, but takes the placement timestamp from `clock`.
+    pub fn new_with_clock(
+        amount: &str,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Hold, LedgerError> {
+        let amount = parse_amount(amount)?;
+        if amount.is_zero() {
+            let msg = "amount can't zero".to_string();
+            return Err(LedgerError::InvalidAmount(msg));
+        }
+        Ok(Hold {
+            id: Arc::new(ids.next_id()),
+            amount,
+            placed_at: clock.now().trunc_subsecs(6),
+            expires_at: None,
+        })
+    }
+
+    pub(crate) fn restore(
+        id: String,
+        amount: Decimal,
+        placed_at: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Hold {
+        Hold {
+            id: Arc::new(id),
+            amount,
+            placed_at,
+            expires_at,
+        }
+    }
+
+    /// Stops reserving funds at `expires_at`.
+    pub fn with_expiry(mut self, expires_at: DateTime<Utc>) -> Hold {
+        self.expires_at = Some(expires_at.trunc_subsecs(6));
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The reserved amount, always positive.
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn placed_at(&self) -> DateTime<Utc> {
+        self.placed_at
+    }
+
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.expires_at
+    }
+
+    pub fn is_active_at(&self, at: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires_at| at < expires_at)
+    }
+
+    pub(crate) fn set_amount(&mut self, amount: Decimal) {
+        self.amount = amount;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::id::Sequence;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn test_hold_new() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 10, 0, 0).unwrap();
+        let hold = Hold::new_with_clock("25.50", &Sequence::new(), &FixedClock::new(now))
+            .unwrap()
+            .with_expiry(now + Duration::days(7));
+        assert_eq!(hold.id(), "00000000000000000000");
+        assert_eq!(hold.amount(), Decimal::new(2550, 2));
+        assert_eq!(hold.placed_at(), now);
+        assert!(hold.is_active_at(now + Duration::days(6)));
+        assert!(!hold.is_active_at(now + Duration::days(7)));
+    }
+
+    #[test]
+    fn test_hold_new_invalid_amount() {
+        let ids = Sequence::new();
+        assert_eq!(Hold::new("", &ids), Err(LedgerError::EmptyAmount));
+        assert!(matches!(
+            Hold::new("0", &ids),
+            Err(LedgerError::InvalidAmount(_))
+        ));
+        assert!(matches!(
+            Hold::new("-5", &ids),
+            Err(LedgerError::InvalidAmount(_))
+        ));
+    }
+
+    #[test]
+    fn test_hold_without_expiry_stays_active() {
+        let hold = Hold::new("1", &Sequence::new()).unwrap();
+        assert!(hold.is_active_at(DateTime::<Utc>::MAX_UTC));
+    }
+}
@@ -0,0 +1,223 @@
+use rand::distributions::Alphanumeric;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::clock::{Clock, SystemClock};
+use crate::ledger::Ledgers;
+
+/// Source of ledger ids. Every id a generator hands out must be distinct.
+pub trait IdGenerator: Send + Sync {
+    fn next_id(&self) -> String;
+}
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const RANDOM_BITS: u32 = 80;
+const RANDOM_MASK: u128 = (1 << RANDOM_BITS) - 1;
+
+/// ULID-style ids: a 48-bit millisecond timestamp followed by 80 random bits,
+/// as 26 Crockford base32 characters, so ids sort by creation time.
+///
+/// Ids from one generator are strictly increasing: within a millisecond, or
+/// if the clock steps back, the previous id is incremented instead of
+/// drawing new randomness.
+pub struct TimeOrdered {
+    clock: Arc<dyn Clock>,
+    last: Mutex<u128>,
+}
+
+impl TimeOrdered {
+    pub fn new() -> TimeOrdered {
+        TimeOrdered::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> TimeOrdered {
+        TimeOrdered {
+            clock,
+            last: Mutex::new(0),
+        }
+    }
+}
+
+impl Default for TimeOrdered {
+    fn default() -> Self {
+        TimeOrdered::new()
+    }
+}
+
+impl IdGenerator for TimeOrdered {
+    fn next_id(&self) -> String {
+        let millis = self.clock.now().timestamp_millis().max(0) as u128 & ((1 << 48) - 1);
+        let mut last = self.last.lock().unwrap();
+        let value = if millis > *last >> RANDOM_BITS {
+            millis << RANDOM_BITS | thread_rng().gen::<u128>() & RANDOM_MASK
+        } else {
+            // Spills into the timestamp bits if the randomness is exhausted,
+            // which still keeps the ids increasing.
+            *last + 1
+        };
+        *last = value;
+        encode_crockford(value)
+    }
+}
+
+fn encode_crockford(value: u128) -> String {
+    (0..26)
+        .map(|i| char::from(CROCKFORD[(value >> (125 - 5 * i)) as usize & 31]))
+        .collect()
+}
+
+/// Increasing sequence numbers, zero-padded to 20 digits so they also sort
+/// as strings. Keep one generator per account.
+#[derive(Debug, Default)]
+pub struct Sequence {
+    next: AtomicU64,
+}
+
+impl Sequence {
+    pub fn new() -> Sequence {
+        Sequence::default()
+    }
+
+    /// Resumes an account's sequence, e.g. after a restart.
+    pub fn starting_at(next: u64) -> Sequence {
+        Sequence {
+            next: AtomicU64::new(next),
+        }
+    }
+
+    /// Resumes the sequence of the account holding `ledgers`, right after its
+    /// last entry (compacted or not). Starts at zero if the account has no
+    /// entries or its last id isn't a sequence number.
+    pub fn after(ledgers: &Ledgers) -> Sequence {
+        let next = ledgers
+            .last_id()
+            .and_then(|id| id.parse::<u64>().ok())
+            .map_or(0, |last| last + 1);
+        Sequence::starting_at(next)
+    }
+}
+
+impl IdGenerator for Sequence {
+    fn next_id(&self) -> String {
+        format!("{:020}", self.next.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Reproducible 16-character alphanumeric ids for tests. Unlike the other
+/// generators, distinct seeds are not guaranteed to yield distinct ids.
+#[derive(Debug)]
+pub struct Seeded {
+    rng: Mutex<StdRng>,
+}
+
+impl Seeded {
+    pub fn new(seed: u64) -> Seeded {
+        Seeded {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl IdGenerator for Seeded {
+    fn next_id(&self) -> String {
+        let mut rng = self.rng.lock().unwrap();
+        (0..16)
+            .map(|_| char::from(rng.sample(Alphanumeric)))
+            .collect()
+    }
+}
+
+/// Process-wide time-ordered generator, used where no generator is passed
+/// in explicitly.
+pub fn time_ordered() -> &'static TimeOrdered {
+    static IDS: OnceLock<TimeOrdered> = OnceLock::new();
+    IDS.get_or_init(TimeOrdered::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::ledger::{Action, Ledger};
+    use chrono::{Duration, TimeZone, Utc};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_time_ordered_ids_sort_by_creation() {
+        let clock = Arc::new(FixedClock::new(
+            Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap(),
+        ));
+        let ids = TimeOrdered::with_clock(clock.clone());
+
+        let mut generated = vec![];
+        for step in [0, 0, 1, 0, -5, 2] {
+            clock.advance(Duration::milliseconds(step));
+            generated.push(ids.next_id());
+        }
+        assert!(generated.iter().all(|id| id.len() == 26));
+        assert!(generated.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_time_ordered_encodes_timestamp_prefix() {
+        let at = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+        let ids = TimeOrdered::with_clock(Arc::new(FixedClock::new(at)));
+        let later = TimeOrdered::with_clock(Arc::new(FixedClock::new(at + Duration::seconds(1))));
+
+        let (a, b) = (ids.next_id(), later.next_id());
+        assert!(a < b);
+        assert_eq!(
+            &a[..10],
+            &encode_crockford((at.timestamp_millis() as u128) << 80)[..10]
+        );
+    }
+
+    #[test]
+    fn test_time_ordered_unique_across_threads() {
+        let ids = Arc::new(TimeOrdered::new());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let ids = ids.clone();
+                std::thread::spawn(move || (0..1000).map(|_| ids.next_id()).collect::<Vec<_>>())
+            })
+            .collect();
+        let mut seen = HashSet::new();
+        for handle in handles {
+            for id in handle.join().unwrap() {
+                assert!(seen.insert(id));
+            }
+        }
+    }
+
+    #[test]
+    fn test_sequence() {
+        let ids = Sequence::new();
+        assert_eq!(ids.next_id(), "00000000000000000000");
+        assert_eq!(ids.next_id(), "00000000000000000001");
+
+        let resumed = Sequence::starting_at(41);
+        assert_eq!(resumed.next_id(), "00000000000000000041");
+        assert_eq!(resumed.next_id(), "00000000000000000042");
+
+        let mut ledgers = Ledgers::new();
+        assert_eq!(Sequence::after(&ledgers).next_id(), "00000000000000000000");
+        for _ in 0..2 {
+            let ledger = Ledger::new(Action::Deposit("1".to_string()), &resumed).unwrap();
+            ledgers.add(ledger).unwrap();
+        }
+        assert_eq!(Sequence::after(&ledgers).next_id(), "00000000000000000045");
+    }
+
+    #[test]
+    fn test_seeded_is_reproducible() {
+        let (a, b) = (Seeded::new(7), Seeded::new(7));
+        let first: Vec<String> = (0..3).map(|_| a.next_id()).collect();
+        let second: Vec<String> = (0..3).map(|_| b.next_id()).collect();
+        assert_eq!(first, second);
+        assert_eq!(first[0].len(), 16);
+        assert_ne!(first[0], first[1]);
+        assert_ne!(Seeded::new(8).next_id(), first[0]);
+    }
+}
@@ -0,0 +1,498 @@
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+use crate::balance::{Balance, BalanceError};
+use crate::clock::Clock;
+use crate::fx::Quote;
+use crate::id::{time_ordered, IdGenerator};
+use crate::ledger::{Action, Ledger, LedgerError};
+use crate::policy::Policy;
+use crate::storage::{Storage, StorageError};
+
+#[derive(Debug, PartialEq)]
+pub enum JournalError {
+    TooFewPostings,
+    Unbalanced {
+        currency: String,
+        residual: Decimal,
+    },
+    /// A write that would change a balance without a counterpart, refused
+    /// by stores that keep balanced books.
+    SingleSided,
+}
+
+/// Accounts the books keep for themselves, one per currency. They carry the
+/// other side of customer movements, so they may go negative without limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InternalAccount {
+    /// Money held by the books, e.g. the counterpart of deposits.
+    Cash,
+    FeesIncome,
+    /// Funds that can't be attributed to an account yet.
+    Suspense,
+    /// The books' position from conversions: what customers converted out
+    /// of the currency less what they converted into it.
+    FxPosition,
+    /// What conversions dropped when rounding the credited amount.
+    FxResidue,
+}
+
+impl InternalAccount {
+    pub const ALL: [InternalAccount; 5] = [
+        InternalAccount::Cash,
+        InternalAccount::FeesIncome,
+        InternalAccount::Suspense,
+        InternalAccount::FxPosition,
+        InternalAccount::FxResidue,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InternalAccount::Cash => "cash",
+            InternalAccount::FeesIncome => "fees_income",
+            InternalAccount::Suspense => "suspense",
+            InternalAccount::FxPosition => "fx_position",
+            InternalAccount::FxResidue => "fx_residue",
+        }
+    }
+
+    /// Id of the account in `currency`, e.g. `internal:cash:USD`.
+    pub fn account_id(&self, currency: &str) -> String {
+        format!("internal:{}:{}", self.as_str(), currency.to_uppercase())
+    }
+
+    /// The FX accounts take amounts to the full precision of a quote rather
+    /// than the currency's minor units.
+    pub fn balance(&self, currency: &str) -> Result<Balance, BalanceError> {
+        let balance = Balance::new(currency)?;
+        let balance = match self {
+            InternalAccount::FxPosition | InternalAccount::FxResidue => {
+                Balance::restore_currency(&balance.currency, Decimal::MAX_SCALE)
+            }
+            _ => balance,
+        };
+        Ok(balance.with_policy(Policy::Overdraft {
+            limit: Decimal::MAX,
+        }))
+    }
+
+    /// Inserts the account in `currency` unless it already exists, and
+    /// returns its id.
+    pub fn open<S: Storage>(
+        &self,
+        storage: &mut S,
+        currency: &str,
+    ) -> Result<String, StorageError> {
+        let account_id = self.account_id(currency);
+        let balance = self.balance(currency).map_err(StorageError::Balance)?;
+        match storage.insert(&account_id, balance) {
+            Ok(()) | Err(StorageError::AccountAlreadyExists) => Ok(account_id),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// One leg of a `Transaction`: a ledger posted to the account's `currency`
+/// pocket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    account_id: String,
+    currency: String,
+    ledger: Ledger,
+}
+
+impl Posting {
+    pub fn new(account_id: &str, currency: &str, action: Action) -> Result<Posting, LedgerError> {
+        Ok(Posting {
+            account_id: account_id.to_string(),
+            currency: currency.to_uppercase(),
+            ledger: Ledger::new(action, time_ordered())?,
+        })
+    }
+
+    /// A `TransferIn` of `amount`.
+    pub fn credit(account_id: &str, currency: &str, amount: &str) -> Result<Posting, LedgerError> {
+        Posting::new(account_id, currency, Action::TransferIn(amount.to_string()))
+    }
+
+    /// A `TransferOut` of `amount`.
+    pub fn debit(account_id: &str, currency: &str, amount: &str) -> Result<Posting, LedgerError> {
+        Posting::new(
+            account_id,
+            currency,
+            Action::TransferOut(amount.to_string()),
+        )
+    }
+
+    /// A conversion leg of exactly `amount`; see `Ledger::conversion`.
+    pub(crate) fn conversion(
+        account_id: &str,
+        currency: &str,
+        amount: Decimal,
+        clock: &dyn Clock,
+    ) -> Posting {
+        Posting {
+            account_id: account_id.to_string(),
+            currency: currency.to_uppercase(),
+            ledger: Ledger::conversion(amount, time_ordered(), clock),
+        }
+    }
+
+    pub fn account_id(&self) -> &str {
+        &self.account_id
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// Signed change to the account's balance.
+    pub fn amount(&self) -> Decimal {
+        self.ledger.amount()
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Keys the posting's ledger, so posting it again is a retry.
+    pub fn with_idempotency_key(mut self, key: &str) -> Posting {
+        self.ledger = self.ledger.with_idempotency_key(key.to_string());
+        self
+    }
+
+    pub(crate) fn into_parts(self) -> (String, String, Ledger) {
+        (self.account_id, self.currency, self.ledger)
+    }
+}
+
+/// Postings that are applied together or not at all. Within each currency
+/// the amounts sum to zero, so value only ever moves between accounts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    id: String,
+    postings: Vec<Posting>,
+}
+
+impl Transaction {
+    pub fn new(postings: Vec<Posting>) -> Result<Transaction, JournalError> {
+        if postings.len() < 2 {
+            return Err(JournalError::TooFewPostings);
+        }
+        check_balanced(
+            postings
+                .iter()
+                .map(|posting| (posting.currency(), posting.amount())),
+        )?;
+        Ok(Transaction {
+            id: time_ordered().next_id(),
+            postings,
+        })
+    }
+
+    /// Credits `amount` to the account against the currency's cash account.
+    pub fn deposit(
+        account_id: &str,
+        currency: &str,
+        amount: &str,
+    ) -> Result<Transaction, LedgerError> {
+        let cash = InternalAccount::Cash.account_id(currency);
+        Transaction::build([
+            Posting::new(account_id, currency, Action::Deposit(amount.to_string())),
+            Posting::debit(&cash, currency, amount),
+        ])
+    }
+
+    /// Pays `amount` out of the account against the currency's cash account.
+    pub fn withdrawal(
+        account_id: &str,
+        currency: &str,
+        amount: &str,
+    ) -> Result<Transaction, LedgerError> {
+        let cash = InternalAccount::Cash.account_id(currency);
+        Transaction::build([
+            Posting::new(account_id, currency, Action::Withdrawal(amount.to_string())),
+            Posting::credit(&cash, currency, amount),
+        ])
+    }
+
+    /// Charges the account a fee of `amount`, booked as fees income.
+    pub fn fee(account_id: &str, currency: &str, amount: &str) -> Result<Transaction, LedgerError> {
+        let income = InternalAccount::FeesIncome.account_id(currency);
+        Transaction::build([
+            Posting::new(account_id, currency, Action::Fee(amount.to_string())),
+            Posting::credit(&income, currency, amount),
+        ])
+    }
+
+    /// Converts within the account at `quote`. Its `from` pocket is debited
+    /// the source amount into the `from` currency's FX position, and its
+    /// `to` pocket credited the target amount out of the `to` currency's,
+    /// which also pays the residue into the `to` currency's FX residue.
+    /// Every leg is booked at `clock`'s time.
+    pub fn conversion(account_id: &str, quote: &Quote, clock: &dyn Clock) -> Transaction {
+        let exact = quote.target_amount + quote.residue;
+        let (from, to) = (&quote.from, &quote.to);
+        let mut postings = vec![
+            Posting::conversion(account_id, from, -quote.source_amount, clock),
+            Posting::conversion(
+                &InternalAccount::FxPosition.account_id(from),
+                from,
+                quote.source_amount,
+                clock,
+            ),
+            Posting::conversion(account_id, to, quote.target_amount, clock),
+            Posting::conversion(
+                &InternalAccount::FxPosition.account_id(to),
+                to,
+                -exact,
+                clock,
+            ),
+        ];
+        if !quote.residue.is_zero() {
+            let residue = InternalAccount::FxResidue.account_id(to);
+            postings.push(Posting::conversion(&residue, to, quote.residue, clock));
+        }
+        // The legs balance by construction.
+        Transaction {
+            id: time_ordered().next_id(),
+            postings,
+        }
+    }
+
+    // Both legs parse the same amount, so they always balance.
+    fn build(postings: [Result<Posting, LedgerError>; 2]) -> Result<Transaction, LedgerError> {
+        Ok(Transaction {
+            id: time_ordered().next_id(),
+            postings: postings.into_iter().collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Keys the first posting, the account's for `deposit`, `withdrawal`
+    /// and `fee`; see `Storage::post`.
+    pub fn with_idempotency_key(mut self, key: &str) -> Transaction {
+        let first = self.postings.remove(0);
+        self.postings.insert(0, first.with_idempotency_key(key));
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    pub(crate) fn into_parts(self) -> (String, Vec<Posting>) {
+        (self.id, self.postings)
+    }
+}
+
+/// What `Storage::post` did with a transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posted {
+    /// The transfer id the posted ledgers carry.
+    pub transaction_id: String,
+    /// Balance of each posting's pocket right after it, in posting order.
+    pub totals: Vec<Decimal>,
+}
+
+/// Internal accounts `Transaction::conversion` posts to when converting
+/// `from` into `to`, with their currency.
+pub(crate) fn conversion_accounts<'a>(
+    from: &'a str,
+    to: &'a str,
+) -> [(InternalAccount, &'a str); 3] {
+    [
+        (InternalAccount::FxPosition, from),
+        (InternalAccount::FxPosition, to),
+        (InternalAccount::FxResidue, to),
+    ]
+}
+
+/// Checks that the `(currency, amount)` legs sum to zero in every currency.
+pub(crate) fn check_balanced<'a>(
+    legs: impl Iterator<Item = (&'a str, Decimal)>,
+) -> Result<(), JournalError> {
+    let mut sums: BTreeMap<&str, Decimal> = BTreeMap::new();
+    for (currency, amount) in legs {
+        *sums.entry(currency).or_default() += amount;
+    }
+    match sums.into_iter().find(|(_, sum)| !sum.is_zero()) {
+        Some((currency, residual)) => Err(JournalError::Unbalanced {
+            currency: currency.to_string(),
+            residual,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// One account's balance in a `TrialBalance`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrialBalanceLine {
+    pub account_id: String,
+    pub currency: String,
+    pub amount: Decimal,
+}
+
+/// Every balance in a store with its total per currency. Books kept purely
+/// through `Transaction`s total zero in every currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrialBalance {
+    lines: Vec<TrialBalanceLine>,
+    totals: BTreeMap<String, Decimal>,
+}
+
+impl TrialBalance {
+    pub(crate) fn new(mut lines: Vec<TrialBalanceLine>) -> TrialBalance {
+        lines.sort_by(|a, b| (&a.account_id, &a.currency).cmp(&(&b.account_id, &b.currency)));
+        let mut totals = BTreeMap::new();
+        for line in &lines {
+            *totals.entry(line.currency.clone()).or_default() += line.amount;
+        }
+        TrialBalance { lines, totals }
+    }
+
+    /// Lines sorted by account id, then currency.
+    pub fn lines(&self) -> &[TrialBalanceLine] {
+        &self.lines
+    }
+
+    pub fn totals(&self) -> &BTreeMap<String, Decimal> {
+        &self.totals
+    }
+
+    /// Currencies whose balances don't sum to zero, with their total.
+    pub fn discrepancies(&self) -> impl Iterator<Item = (&str, Decimal)> + '_ {
+        self.totals
+            .iter()
+            .filter(|(_, total)| !total.is_zero())
+            .map(|(currency, total)| (currency.as_str(), *total))
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.discrepancies().next().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_transaction_must_balance_per_currency() {
+        let postings = vec![
+            Posting::credit("account_1", "USD", "10").unwrap(),
+            Posting::debit("account_2", "USD", "10").unwrap(),
+            Posting::credit("account_1", "EUR", "5").unwrap(),
+            Posting::debit("account_3", "EUR", "4.99").unwrap(),
+        ];
+        assert_eq!(
+            Transaction::new(postings),
+            Err(JournalError::Unbalanced {
+                currency: "EUR".to_string(),
+                residual: dec!(0.01)
+            })
+        );
+        // Balanced in total, but not within each currency.
+        let postings = vec![
+            Posting::credit("account_1", "usd", "10").unwrap(),
+            Posting::debit("account_2", "EUR", "10").unwrap(),
+        ];
+        assert!(matches!(
+            Transaction::new(postings),
+            Err(JournalError::Unbalanced { .. })
+        ));
+        assert_eq!(
+            Transaction::new(vec![Posting::credit("account_1", "USD", "1").unwrap()]),
+            Err(JournalError::TooFewPostings)
+        );
+    }
+
+    #[test]
+    fn test_transaction_helpers_use_internal_accounts() {
+        let fee = Transaction::fee("account_1", "usd", "2.50").unwrap();
+        let legs: Vec<(&str, Decimal)> = fee
+            .postings()
+            .iter()
+            .map(|posting| (posting.account_id(), posting.amount()))
+            .collect();
+        assert_eq!(
+            legs,
+            [
+                ("account_1", dec!(-2.50)),
+                ("internal:fees_income:USD", dec!(2.50))
+            ]
+        );
+        assert!(matches!(
+            Transaction::deposit("account_1", "USD", "-1"),
+            Err(LedgerError::InvalidAmount(_))
+        ));
+    }
+
+    #[test]
+    fn test_conversion_balances_through_fx_accounts() {
+        let quote = Quote {
+            from: "EUR".to_string(),
+            to: "USD".to_string(),
+            mid_rate: dec!(1.0850),
+            rate: dec!(1.0795750),
+            source_amount: dec!(33.33),
+            target_amount: dec!(35.98),
+            residue: dec!(0.00223475),
+        };
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 10, 0, 0).unwrap();
+        let conversion = Transaction::conversion("account_1", &quote, &FixedClock::new(now));
+        assert!(conversion
+            .postings()
+            .iter()
+            .all(|posting| posting.ledger().booked_at() == now));
+        let legs: Vec<(&str, &str, Decimal)> = conversion
+            .postings()
+            .iter()
+            .map(|posting| (posting.account_id(), posting.currency(), posting.amount()))
+            .collect();
+        assert_eq!(
+            legs,
+            [
+                ("account_1", "EUR", dec!(-33.33)),
+                ("internal:fx_position:EUR", "EUR", dec!(33.33)),
+                ("account_1", "USD", dec!(35.98)),
+                ("internal:fx_position:USD", "USD", dec!(-35.98223475)),
+                ("internal:fx_residue:USD", "USD", dec!(0.00223475)),
+            ]
+        );
+        assert_eq!(
+            check_balanced(
+                legs.iter()
+                    .map(|(_, currency, amount)| (*currency, *amount))
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_trial_balance_totals() {
+        let line = |account_id: &str, currency: &str, amount| TrialBalanceLine {
+            account_id: account_id.to_string(),
+            currency: currency.to_string(),
+            amount,
+        };
+        let trial = TrialBalance::new(vec![
+            line("b", "USD", dec!(5)),
+            line("a", "USD", dec!(-5)),
+            line("a", "EUR", dec!(3)),
+        ]);
+        assert_eq!(trial.lines()[0], line("a", "EUR", dec!(3)));
+        assert_eq!(trial.totals()["USD"], dec!(0));
+        assert_eq!(
+            trial.discrepancies().collect::<Vec<_>>(),
+            [("EUR", dec!(3))]
+        );
+        assert!(!trial.is_balanced());
+    }
+}
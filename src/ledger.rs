@@ -1,40 +1,157 @@
-use rand::Rng;
-use rand::{distributions::Alphanumeric, thread_rng};
-use rust_decimal::prelude::FromPrimitive;
+use chrono::{DateTime, NaiveDate, SubsecRound, Utc};
 use rust_decimal::Decimal;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::fmt::Formatter;
 use std::ops::Add;
+use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::clock::{Clock, SystemClock};
+use crate::id::IdGenerator;
+
+/// Maximum number of fractional digits accepted in an amount string.
+pub const MAX_AMOUNT_SCALE: u32 = 18;
+
 #[derive(Debug, PartialEq)]
 pub enum LedgerError {
     EmptyAmount,
     ParseAmount,
     InvalidAmount(String),
+    InvalidCharacter(char),
+    LeadingZero,
+    ExponentNotAllowed,
+    NonFiniteAmount,
+    ScaleTooLarge(u32),
+    AmountOverflow,
     DuplicateLedger, // Add this line
+    UnknownAction(String),
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Action {
     Withdrawal(String),
     Deposit(String),
+    TransferIn(String),
+    TransferOut(String),
+    Fee(String),
+    Interest(String),
+    /// A manual correction. Credits the account, or debits it if the amount
+    /// has a leading `-`.
+    Adjustment(String),
+    /// Undoes an earlier entry. Credits the account, or debits it if the
+    /// amount has a leading `-`.
+    Reversal(String),
+}
+
+impl Action {
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            Action::Withdrawal(_) => ActionKind::Withdrawal,
+            Action::Deposit(_) => ActionKind::Deposit,
+            Action::TransferIn(_) => ActionKind::TransferIn,
+            Action::TransferOut(_) => ActionKind::TransferOut,
+            Action::Fee(_) => ActionKind::Fee,
+            Action::Interest(_) => ActionKind::Interest,
+            Action::Adjustment(_) => ActionKind::Adjustment,
+            Action::Reversal(_) => ActionKind::Reversal,
+        }
+    }
+
+    fn amount(&self) -> &str {
+        match self {
+            Action::Withdrawal(a)
+            | Action::Deposit(a)
+            | Action::TransferIn(a)
+            | Action::TransferOut(a)
+            | Action::Fee(a)
+            | Action::Interest(a)
+            | Action::Adjustment(a)
+            | Action::Reversal(a) => a,
+        }
+    }
 }
 
 impl fmt::Display for Action {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.kind().fmt(f)
+    }
+}
+
+/// What kind of movement a ledger entry records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActionKind {
+    Withdrawal,
+    Deposit,
+    TransferIn,
+    TransferOut,
+    Fee,
+    Interest,
+    Adjustment,
+    Reversal,
+    /// A leg of a currency conversion: a move between an account's pockets,
+    /// or the FX position or residue taking the other side of one.
+    Conversion,
+}
+
+impl ActionKind {
+    pub const ALL: [ActionKind; 9] = [
+        ActionKind::Withdrawal,
+        ActionKind::Deposit,
+        ActionKind::TransferIn,
+        ActionKind::TransferOut,
+        ActionKind::Fee,
+        ActionKind::Interest,
+        ActionKind::Adjustment,
+        ActionKind::Reversal,
+        ActionKind::Conversion,
+    ];
+
+    /// The name persisted by the storage backends.
+    pub fn as_str(&self) -> &'static str {
         match self {
-            Action::Deposit(_) => write!(f, "Deposit"),
-            Action::Withdrawal(_) => write!(f, "Withdrawal"),
+            ActionKind::Withdrawal => "Withdrawal",
+            ActionKind::Deposit => "Deposit",
+            ActionKind::TransferIn => "TransferIn",
+            ActionKind::TransferOut => "TransferOut",
+            ActionKind::Fee => "Fee",
+            ActionKind::Interest => "Interest",
+            ActionKind::Adjustment => "Adjustment",
+            ActionKind::Reversal => "Reversal",
+            ActionKind::Conversion => "Conversion",
         }
     }
 }
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ActionKind {
+    type Err = LedgerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ActionKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| LedgerError::UnknownAction(s.to_string()))
+    }
+}
 impl Clone for Ledgers {
     fn clone(&self) -> Self {
         Ledgers {
             index: self.index.clone(),
+            keys: self.keys.clone(),
+            compacted_keys: self.compacted_keys.clone(),
+            reversed: self.reversed.clone(),
+            reversible: self.reversible.clone(),
             collection: self.collection.clone(),
+            running: self.running.clone(),
+            booked_through: self.booked_through.clone(),
+            opening: self.opening,
+            opening_id: self.opening_id.clone(),
         }
     }
 }
@@ -42,153 +159,660 @@ impl Clone for Ledgers {
 #[derive(Debug, PartialEq)]
 pub struct Ledger {
     id: Arc<String>,
-    action: String,
+    action: ActionKind,
     amount: Decimal,
+    transfer_id: Option<Arc<String>>,
+    booked_at: DateTime<Utc>,
+    value_date: NaiveDate,
+    idempotency_key: Option<Arc<String>>,
+    // Id of the entry this one reverses or partially refunds.
+    reverses: Option<Arc<String>>,
 }
 impl Clone for Ledger {
     fn clone(&self) -> Self {
         Ledger {
             id: Arc::clone(&self.id),
-            action: self.action.clone(),
+            action: self.action,
             amount: self.amount,
+            transfer_id: self.transfer_id.clone(),
+            booked_at: self.booked_at,
+            value_date: self.value_date,
+            idempotency_key: self.idempotency_key.clone(),
+            reverses: self.reverses.clone(),
         }
     }
 }
 
+/// The optional references a persisted entry carries, for `Ledger::restore`.
+#[derive(Debug, Default)]
+pub(crate) struct Links {
+    pub transfer_id: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub reverses: Option<String>,
+}
+
 impl fmt::Display for Ledger {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {:?} {}", self.id, self.action, self.amount)
+        write!(f, "{} {} {}", self.id, self.action, self.amount)
     }
 }
 
 impl Ledger {
-    pub fn new(action: Action) -> Result<Ledger, LedgerError> {
+    /// Creates an entry whose id is drawn from `ids`.
+    pub fn new(action: Action, ids: &dyn IdGenerator) -> Result<Ledger, LedgerError> {
+        Ledger::new_with_clock(action, ids, &SystemClock)
+    }
+    /// Like `new`, but takes the booking timestamp from `clock`. The value
+    /// date defaults to the booking day (UTC).
+    pub fn new_with_clock(
+        action: Action,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Ledger, LedgerError> {
+        let input = action.amount();
         let amount = match &action {
-            Action::Withdrawal(a) | Action::Deposit(a) if a.is_empty() => {
-                return Err(LedgerError::EmptyAmount);
+            Action::Withdrawal(_) | Action::TransferOut(_) | Action::Fee(_) => {
+                -parse_amount(input)?
             }
-            Action::Withdrawal(a) | Action::Deposit(a) if a.starts_with("-") => {
-                let msg = "amount can't be negative".to_string();
-                return Err(LedgerError::InvalidAmount(msg));
+            Action::Deposit(_) | Action::TransferIn(_) | Action::Interest(_) => {
+                parse_amount(input)?
             }
-            Action::Withdrawal(a) | Action::Deposit(a) => a.parse::<f64>(),
-        }
-        .map_err(|_| LedgerError::ParseAmount)?;
+            Action::Adjustment(_) | Action::Reversal(_) => match input.strip_prefix('-') {
+                Some(debit) => -parse_amount(debit)?,
+                None => parse_amount(input)?,
+            },
+        };
 
-        if amount == 0.0 {
+        if amount.is_zero() {
             let msg = "amount can't zero".to_string();
             return Err(LedgerError::InvalidAmount(msg));
         }
 
-        let amount = match &action {
-            Action::Deposit(_) => amount,
-            Action::Withdrawal(_) => amount * -1.0,
-        };
-
+        // Stores keep microseconds, so drop anything finer up front.
+        let booked_at = clock.now().trunc_subsecs(6);
+        Ok(Ledger {
+            id: Arc::new(ids.next_id()),
+            action: action.kind(),
+            amount,
+            transfer_id: None,
+            booked_at,
+            value_date: booked_at.date_naive(),
+            idempotency_key: None,
+            reverses: None,
+        })
+    }
+    /// An entry undoing `amount` of the entry `original_id`, where `amount`
+    /// has the same sign as the original's amount.
+    pub(crate) fn reversing(
+        original_id: &str,
+        amount: Decimal,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Ledger {
+        let booked_at = clock.now().trunc_subsecs(6);
+        Ledger {
+            id: Arc::new(ids.next_id()),
+            action: ActionKind::Reversal,
+            amount: -amount,
+            transfer_id: None,
+            booked_at,
+            value_date: booked_at.date_naive(),
+            idempotency_key: None,
+            reverses: Some(Arc::new(original_id.to_string())),
+        }
+    }
+    /// A `Conversion` leg of exactly `amount`, which may be finer than
+    /// `parse_amount` accepts. `amount` must not be zero.
+    pub(crate) fn conversion(amount: Decimal, ids: &dyn IdGenerator, clock: &dyn Clock) -> Ledger {
+        let booked_at = clock.now().trunc_subsecs(6);
+        Ledger {
+            id: Arc::new(ids.next_id()),
+            action: ActionKind::Conversion,
+            amount,
+            transfer_id: None,
+            booked_at,
+            value_date: booked_at.date_naive(),
+            idempotency_key: None,
+            reverses: None,
+        }
+    }
+    /// A `Conversion` debiting `amount`, parsed as for a `TransferOut`.
+    pub(crate) fn conversion_debit(
+        amount: &str,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Ledger, LedgerError> {
+        let debit = Ledger::new_with_clock(Action::TransferOut(amount.to_string()), ids, clock)?;
         Ok(Ledger {
-            id: Arc::new(generate_random_string(16)),
-            action: action.to_string(),
-            amount: Decimal::from_f64(amount).unwrap(),
+            action: ActionKind::Conversion,
+            ..debit
         })
     }
+
+    pub(crate) fn restore(
+        id: String,
+        action: ActionKind,
+        amount: Decimal,
+        booked_at: DateTime<Utc>,
+        value_date: NaiveDate,
+        links: Links,
+    ) -> Ledger {
+        Ledger {
+            id: Arc::new(id),
+            action,
+            amount,
+            transfer_id: links.transfer_id.map(Arc::new),
+            booked_at,
+            value_date,
+            idempotency_key: links.idempotency_key.map(Arc::new),
+            reverses: links.reverses.map(Arc::new),
+        }
+    }
+    /// Links this entry to the other side of an account-to-account transfer.
+    pub fn with_transfer_id(mut self, transfer_id: Arc<String>) -> Ledger {
+        self.transfer_id = Some(transfer_id);
+        self
+    }
+    /// Sets the date the entry counts towards the balance, which may differ
+    /// from the booking day (back-valued or forward-dated entries).
+    pub fn with_value_date(mut self, value_date: NaiveDate) -> Ledger {
+        self.value_date = value_date;
+        self
+    }
+    /// Tags the entry with a caller-chosen key so a retried request is
+    /// recognised instead of being posted twice.
+    pub fn with_idempotency_key(mut self, key: String) -> Ledger {
+        self.idempotency_key = Some(Arc::new(key));
+        self
+    }
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    pub fn action(&self) -> ActionKind {
+        self.action
+    }
+    pub fn transfer_id(&self) -> Option<&str> {
+        self.transfer_id.as_deref().map(|id| id.as_str())
+    }
     pub fn amount(&self) -> Decimal {
-        self.amount.clone()
+        self.amount
+    }
+    pub fn booked_at(&self) -> DateTime<Utc> {
+        self.booked_at
+    }
+    pub fn value_date(&self) -> NaiveDate {
+        self.value_date
+    }
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref().map(|key| key.as_str())
+    }
+    /// Id of the entry this one reverses or refunds.
+    pub fn reverses(&self) -> Option<&str> {
+        self.reverses.as_deref().map(|id| id.as_str())
+    }
+    pub(crate) fn set_amount(&mut self, amount: Decimal) {
+        self.amount = amount;
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// The entry posted under an idempotency key, as far as a retry needs to
+/// know it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyedEntry {
+    pub ledger_id: String,
+    pub action: ActionKind,
+    pub amount: Decimal,
+    /// Balance right after the entry was posted.
+    pub total: Decimal,
+}
+
+/// What a compacted `Ledgers` still knows about the entries it dropped.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Carried {
+    /// Balance after the last compacted entry, and that entry's id.
+    pub opening: Decimal,
+    pub opening_id: Option<String>,
+    /// Amount of each compacted entry that isn't fully reversed yet, by id.
+    pub reversible: BTreeMap<String, Decimal>,
+    /// How much compacted reversals and refunds took off each entry, as a
+    /// magnitude.
+    pub reversed: BTreeMap<String, Decimal>,
+    /// Compacted entries posted under an idempotency key, by key.
+    pub keys: BTreeMap<String, KeyedEntry>,
+}
+
+#[derive(Debug, PartialEq, Default)]
 pub struct Ledgers {
-    index: HashSet<Arc<String>>,
-    pub collection: Vec<Ledger>,
+    // Position of each entry in `collection`, by id.
+    index: HashMap<Arc<String>, usize>,
+    // Position of each entry in `collection`, by idempotency key.
+    keys: HashMap<Arc<String>, usize>,
+    // Compacted entries posted under an idempotency key, by key.
+    compacted_keys: HashMap<Arc<String>, KeyedEntry>,
+    // Total already reversed or refunded of each entry, as a magnitude.
+    reversed: HashMap<Arc<String>, Decimal>,
+    // Amount of each compacted entry that can still be reversed, by id.
+    reversible: HashMap<Arc<String>, Decimal>,
+    collection: Vec<Ledger>,
+    // Balance after each entry of `collection`, so totals never need a
+    // re-sum.
+    running: Vec<Decimal>,
+    // Latest booking timestamp seen up to and including each entry. It never
+    // decreases, so point-in-time lookups can binary search it.
+    booked_through: Vec<DateTime<Utc>>,
+    // Balance carried forward from entries that were compacted away, and the
+    // id of the last of them.
+    opening: Decimal,
+    opening_id: Option<Arc<String>>,
 }
 
 impl Ledgers {
     pub fn new() -> Ledgers {
         Ledgers {
-            index: HashSet::new(),
+            index: HashMap::new(),
+            keys: HashMap::new(),
+            compacted_keys: HashMap::new(),
+            reversed: HashMap::new(),
+            reversible: HashMap::new(),
             collection: vec![],
+            running: vec![],
+            booked_through: vec![],
+            opening: Decimal::default(),
+            opening_id: None,
+        }
+    }
+    /// Starts a collection from what compaction carried forward instead of
+    /// replaying the entries that produced it. The entries compaction kept
+    /// are added afterwards.
+    pub(crate) fn carried_forward(carried: Carried) -> Ledgers {
+        let arc_keys = |map: BTreeMap<String, Decimal>| {
+            map.into_iter()
+                .map(|(id, amount)| (Arc::new(id), amount))
+                .collect()
+        };
+        Ledgers {
+            reversed: arc_keys(carried.reversed),
+            reversible: arc_keys(carried.reversible),
+            compacted_keys: carried
+                .keys
+                .into_iter()
+                .map(|(key, entry)| (Arc::new(key), entry))
+                .collect(),
+            opening: carried.opening,
+            opening_id: carried.opening_id.map(Arc::new),
+            ..Ledgers::new()
+        }
+    }
+    /// What `carried_forward` needs to rebuild this collection from its
+    /// entries.
+    pub(crate) fn carried(&self) -> Carried {
+        let mut reversed: BTreeMap<String, Decimal> = self
+            .reversed
+            .iter()
+            .map(|(id, amount)| (id.to_string(), *amount))
+            .collect();
+        for ledger in &self.collection {
+            if let Some(original) = ledger.reverses() {
+                let amount = reversed.get_mut(original).unwrap();
+                *amount -= ledger.amount.abs();
+                if amount.is_zero() {
+                    reversed.remove(original);
+                }
+            }
+        }
+        Carried {
+            opening: self.opening,
+            opening_id: self.opening_id().map(str::to_string),
+            reversible: self
+                .reversible
+                .iter()
+                .map(|(id, amount)| (id.to_string(), *amount))
+                .collect(),
+            reversed,
+            keys: self
+                .compacted_keys
+                .iter()
+                .map(|(key, entry)| (key.to_string(), entry.clone()))
+                .collect(),
+        }
+    }
+    /// A copy holding only the entries booked after `retain_after`. The
+    /// older ones are carried forward as an opening balance, plus whatever
+    /// of each is still left to reverse and the idempotency keys they were
+    /// posted under; once nothing is left, reversing them reports
+    /// `LedgerNotFound`.
+    pub(crate) fn compact(&self, retain_after: DateTime<Utc>) -> Ledgers {
+        // Everything before the partition point was booked at or before
+        // `retain_after`.
+        let start = self
+            .booked_through
+            .partition_point(|booked| *booked <= retain_after);
+        let mut carried = self.carried();
+        if let Some(last) = start.checked_sub(1) {
+            carried.opening = self.running[last];
+            carried.opening_id = Some(self.collection[last].id().to_string());
+        }
+        for (position, ledger) in self.collection[..start].iter().enumerate() {
+            if let Some(key) = ledger.idempotency_key() {
+                let entry = KeyedEntry {
+                    ledger_id: ledger.id().to_string(),
+                    action: ledger.action,
+                    amount: ledger.amount,
+                    total: self.running[position],
+                };
+                carried.keys.insert(key.to_string(), entry);
+            }
+            match ledger.reverses() {
+                None => {
+                    carried
+                        .reversible
+                        .insert(ledger.id().to_string(), ledger.amount);
+                }
+                Some(original) => {
+                    *carried.reversed.entry(original.to_string()).or_default() +=
+                        ledger.amount.abs();
+                }
+            }
+        }
+        carried
+            .reversible
+            .retain(|id, amount| amount.abs() > self.reversed_amount(id));
+        let retained = |id: &str| {
+            self.index
+                .get(&String::from(id))
+                .is_some_and(|&position| position >= start)
+        };
+        carried
+            .reversed
+            .retain(|id, _| carried.reversible.contains_key(id) || retained(id));
+
+        let mut ledgers = Ledgers::carried_forward(carried);
+        for ledger in &self.collection[start..] {
+            ledgers.push(ledger.clone());
+        }
+        ledgers
+    }
+    pub fn opening(&self) -> Decimal {
+        self.opening
+    }
+    pub fn opening_id(&self) -> Option<&str> {
+        self.opening_id.as_deref().map(|id| id.as_str())
+    }
+    /// Id of the most recent entry, including compacted ones.
+    pub fn last_id(&self) -> Option<&str> {
+        match self.collection.last() {
+            Some(ledger) => Some(ledger.id()),
+            None => self.opening_id(),
         }
     }
     pub fn add(&mut self, ledger: Ledger) -> Result<(), LedgerError> {
-        let id = ledger.id.clone();
-        if self.index.contains(&id) {
+        self.check(&ledger)?;
+        self.push(ledger);
+        Ok(())
+    }
+    /// Checks that `ledger` could be added: neither its id nor its
+    /// idempotency key may already be in use.
+    pub fn check(&self, ledger: &Ledger) -> Result<(), LedgerError> {
+        if self.index.contains_key(&ledger.id) || self.reversible.contains_key(&ledger.id) {
             return Err(LedgerError::DuplicateLedger);
         }
-        self.index.insert(id);
-        self.collection.push(ledger);
+        if let Some(key) = &ledger.idempotency_key {
+            if self.keys.contains_key(key) || self.compacted_keys.contains_key(key) {
+                return Err(LedgerError::DuplicateLedger);
+            }
+        }
         Ok(())
     }
+    // Adds an entry that already passed `check`.
+    fn push(&mut self, ledger: Ledger) {
+        let id = ledger.id.clone();
+        if let Some(key) = &ledger.idempotency_key {
+            self.keys.insert(key.clone(), self.collection.len());
+        }
+        if let Some(original) = &ledger.reverses {
+            *self.reversed.entry(original.clone()).or_default() += ledger.amount.abs();
+        }
+        self.index.insert(id, self.collection.len());
+        self.running.push(self.sum().add(ledger.amount()));
+        let booked_through = match self.booked_through.last() {
+            Some(last) => ledger.booked_at.max(*last),
+            None => ledger.booked_at,
+        };
+        self.booked_through.push(booked_through);
+        self.collection.push(ledger);
+    }
+    pub fn entries(&self) -> &[Ledger] {
+        &self.collection
+    }
+    /// Balance right after the entry at `index` was posted.
+    pub fn running_balance(&self, index: usize) -> Option<Decimal> {
+        self.running.get(index).copied()
+    }
     pub fn len(&self) -> usize {
         self.collection.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.collection.is_empty()
+    }
+    pub(crate) fn truncate(&mut self, len: usize) {
+        for ledger in self.collection.drain(len..) {
+            self.index.remove(&ledger.id);
+            if let Some(key) = &ledger.idempotency_key {
+                self.keys.remove(key);
+            }
+            if let Some(original) = &ledger.reverses {
+                let reversed = self.reversed.get_mut(original).unwrap();
+                *reversed -= ledger.amount.abs();
+                if reversed.is_zero() {
+                    self.reversed.remove(original);
+                }
+            }
+        }
+        self.running.truncate(len);
+        self.booked_through.truncate(len);
+    }
     pub fn sum(&self) -> Decimal {
-        let mut total = Decimal::default();
-        for l in &self.collection {
-            total = total.add(l.amount());
+        self.running.last().copied().unwrap_or(self.opening)
+    }
+    /// Balance the ledger showed at `at`.
+    ///
+    /// Entries count from their booking timestamp, except that one posted
+    /// after a later-stamped entry (e.g. after a clock step back) counts only
+    /// from that later timestamp, since it wasn't on the books before then.
+    /// A carried-forward opening balance counts towards every timestamp.
+    pub fn balance_at(&self, at: DateTime<Utc>) -> Decimal {
+        match self.booked_through.partition_point(|booked| *booked <= at) {
+            0 => self.opening,
+            n => self.running[n - 1],
         }
-        total
+    }
+    /// Balance right after the entry `id` was posted, or `None` if no such
+    /// entry exists. The id of the last compacted entry yields the opening
+    /// balance.
+    pub fn balance_after(&self, id: &str) -> Option<Decimal> {
+        match self.index.get(&String::from(id)) {
+            Some(&position) => self.running_balance(position),
+            None if self.opening_id() == Some(id) => Some(self.opening),
+            None => None,
+        }
+    }
+    /// The entry with the given id, unless it was compacted away.
+    pub fn get(&self, id: &str) -> Option<&Ledger> {
+        let &position = self.index.get(&String::from(id))?;
+        Some(&self.collection[position])
+    }
+    /// Amount of the compacted entry `id`, while some of it is still left to
+    /// reverse.
+    pub fn compacted_amount(&self, id: &str) -> Option<Decimal> {
+        self.reversible.get(&String::from(id)).copied()
+    }
+    /// How much of the entry `id` has been reversed or refunded so far.
+    pub fn reversed_amount(&self, id: &str) -> Decimal {
+        self.reversed
+            .get(&String::from(id))
+            .copied()
+            .unwrap_or_default()
+    }
+    /// The entry posted under idempotency `key`, compacted or not.
+    pub fn find_by_idempotency_key(&self, key: &str) -> Option<KeyedEntry> {
+        let key = String::from(key);
+        let Some(&position) = self.keys.get(&key) else {
+            return self.compacted_keys.get(&key).cloned();
+        };
+        let ledger = &self.collection[position];
+        Some(KeyedEntry {
+            ledger_id: ledger.id().to_string(),
+            action: ledger.action,
+            amount: ledger.amount,
+            total: self.running[position],
+        })
+    }
+    /// Entries booked after `at`, in posting order.
+    pub fn booked_after(&self, at: DateTime<Utc>) -> impl Iterator<Item = &Ledger> + '_ {
+        // Everything before the partition point was booked at or before `at`.
+        let start = self.booked_through.partition_point(|booked| *booked <= at);
+        self.collection[start..]
+            .iter()
+            .filter(move |ledger| ledger.booked_at > at)
+    }
+    /// Entries of the given kind, in posting order.
+    pub fn entries_of(&self, kind: ActionKind) -> impl Iterator<Item = &Ledger> + '_ {
+        self.collection
+            .iter()
+            .filter(move |ledger| ledger.action == kind)
+    }
+    /// Balance counting every entry whose value date is on or before `date`.
+    /// A carried-forward opening balance counts towards every date.
+    pub fn balance_as_of(&self, date: NaiveDate) -> Decimal {
+        self.collection
+            .iter()
+            .filter(|ledger| ledger.value_date <= date)
+            .fold(self.opening, |sum, ledger| sum + ledger.amount)
+    }
+    /// Entries whose value date falls within `from..=to`, in posting order.
+    pub fn entries_between(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> impl Iterator<Item = &Ledger> + '_ {
+        self.collection
+            .iter()
+            .filter(move |ledger| (from..=to).contains(&ledger.value_date))
     }
 }
 
-fn generate_random_string(len: usize) -> String {
-    let s: String = thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(len)
-        .map(char::from)
-        .collect::<String>();
-    return s;
+/// Parses an unsigned amount string into an exact `Decimal`.
+///
+/// The accepted grammar is `0 | [1-9][0-9]*` optionally followed by `.` and
+/// between 1 and `MAX_AMOUNT_SCALE` digits. Signs, exponents, whitespace and
+/// non-finite values are rejected, so every accepted string is reproduced
+/// exactly by the resulting `Decimal`'s `to_string`.
+pub fn parse_amount(input: &str) -> Result<Decimal, LedgerError> {
+    if input.is_empty() {
+        return Err(LedgerError::EmptyAmount);
+    }
+    if input.starts_with('-') {
+        let msg = "amount can't be negative".to_string();
+        return Err(LedgerError::InvalidAmount(msg));
+    }
+    let lower = input.to_ascii_lowercase();
+    if matches!(lower.as_str(), "nan" | "inf" | "infinity") {
+        return Err(LedgerError::NonFiniteAmount);
+    }
+
+    let (integer, fraction) = match input.split_once('.') {
+        Some((i, f)) => (i, Some(f)),
+        None => (input, None),
+    };
+    if integer.is_empty() || fraction.is_some_and(|f| f.is_empty() || f.contains('.')) {
+        return Err(LedgerError::ParseAmount);
+    }
+    for c in integer.chars().chain(fraction.unwrap_or_default().chars()) {
+        match c {
+            '0'..='9' => {}
+            'e' | 'E' => return Err(LedgerError::ExponentNotAllowed),
+            _ => return Err(LedgerError::InvalidCharacter(c)),
+        }
+    }
+    if integer.len() > 1 && integer.starts_with('0') {
+        return Err(LedgerError::LeadingZero);
+    }
+
+    let fraction = fraction.unwrap_or_default();
+    let scale = fraction.len() as u32;
+    if scale > MAX_AMOUNT_SCALE {
+        return Err(LedgerError::ScaleTooLarge(scale));
+    }
+
+    let mut mantissa: i128 = 0;
+    for b in integer.bytes().chain(fraction.bytes()) {
+        mantissa = mantissa
+            .checked_mul(10)
+            .and_then(|m| m.checked_add(i128::from(b - b'0')))
+            .ok_or(LedgerError::AmountOverflow)?;
+    }
+    Decimal::try_from_i128_with_scale(mantissa, scale).map_err(|_| LedgerError::AmountOverflow)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::FixedClock;
+    use crate::id::{time_ordered, Seeded, Sequence};
+    use chrono::TimeZone;
+    use proptest::prelude::*;
+    use rust_decimal::prelude::FromPrimitive;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
 
     #[test]
     fn test_ledger_new_withdrawal_positive_amount() {
         let action = Action::Withdrawal("100.0".to_string());
-        let ledger = Ledger::new(action).unwrap();
+        let ledger = Ledger::new(action, time_ordered()).unwrap();
         assert_eq!(ledger.amount(), Decimal::from_f64(-100.0).unwrap());
     }
 
     #[test]
     fn test_ledger_new_deposit_positive_amount() {
         let action = Action::Deposit("200.0".to_string());
-        let ledger = Ledger::new(action).unwrap();
+        let ledger = Ledger::new(action, time_ordered()).unwrap();
         assert_eq!(ledger.amount(), Decimal::from_f64(200.0).unwrap());
     }
 
     #[test]
     fn test_ledger_new_withdrawal_empty_amount() {
         let action = Action::Withdrawal("".to_string());
-        let result = Ledger::new(action);
+        let result = Ledger::new(action, time_ordered());
         assert!(matches!(result, Err(LedgerError::EmptyAmount)));
     }
 
     #[test]
     fn test_ledger_new_deposit_empty_amount() {
         let action = Action::Deposit("".to_string());
-        let result = Ledger::new(action);
+        let result = Ledger::new(action, time_ordered());
         assert!(matches!(result, Err(LedgerError::EmptyAmount)));
     }
 
     #[test]
     fn test_ledger_new_withdrawal_negative_amount() {
         let action = Action::Withdrawal("-50.0".to_string());
-        let result = Ledger::new(action);
+        let result = Ledger::new(action, time_ordered());
         assert!(matches!(result, Err(LedgerError::InvalidAmount(_))));
     }
 
     #[test]
     fn test_ledger_new_deposit_negative_amount() {
         let action = Action::Deposit("-50.0".to_string());
-        let result = Ledger::new(action);
+        let result = Ledger::new(action, time_ordered());
         assert!(matches!(result, Err(LedgerError::InvalidAmount(_))));
     }
 
     #[test]
     fn test_ledger_new_zero_amount() {
         let action = Action::Deposit("0.0".to_string());
-        let result = Ledger::new(action);
+        let result = Ledger::new(action, time_ordered());
         assert!(matches!(result, Err(LedgerError::InvalidAmount(_))));
     }
 
@@ -202,7 +826,7 @@ mod tests {
     fn test_ledgers_add() {
         let mut ledgers = Ledgers::new();
         let action = Action::Deposit("100.0".to_string());
-        let ledger = Ledger::new(action).unwrap();
+        let ledger = Ledger::new(action, time_ordered()).unwrap();
         let _ = ledgers.add(ledger);
         assert_eq!(ledgers.len(), 1);
     }
@@ -211,26 +835,445 @@ mod tests {
     fn test_ledgers_sum() {
         let mut ledgers = Ledgers::new();
         let action_deposit = Action::Deposit("100.0".to_string());
-        let ledger_deposit = Ledger::new(action_deposit).unwrap();
+        let ledger_deposit = Ledger::new(action_deposit, time_ordered()).unwrap();
         let _ = ledgers.add(ledger_deposit);
 
         let action_withdrawal = Action::Withdrawal("50.0".to_string());
-        let ledger_withdrawal = Ledger::new(action_withdrawal).unwrap();
+        let ledger_withdrawal = Ledger::new(action_withdrawal, time_ordered()).unwrap();
         let _ = ledgers.add(ledger_withdrawal);
 
         assert_eq!(ledgers.sum(), Decimal::from_f64(50.0).unwrap());
     }
 
+    #[test]
+    fn test_ledgers_running_balance() {
+        let mut ledgers = Ledgers::new();
+        for action in [
+            Action::Deposit("100".to_string()),
+            Action::Withdrawal("30.5".to_string()),
+            Action::Deposit("0.5".to_string()),
+        ] {
+            ledgers
+                .add(Ledger::new(action, time_ordered()).unwrap())
+                .unwrap();
+        }
+        assert_eq!(ledgers.running_balance(0), Some(Decimal::new(100, 0)));
+        assert_eq!(ledgers.running_balance(1), Some(Decimal::new(695, 1)));
+        assert_eq!(ledgers.running_balance(2), Some(Decimal::new(70, 0)));
+        assert_eq!(ledgers.running_balance(3), None);
+        assert_eq!(ledgers.sum(), Decimal::new(70, 0));
+
+        ledgers.truncate(1);
+        assert_eq!(ledgers.sum(), Decimal::new(100, 0));
+        assert_eq!(ledgers.running_balance(1), None);
+    }
+
+    #[test]
+    fn test_ledgers_duplicate_does_not_change_sum() {
+        let mut ledgers = Ledgers::new();
+        let ledger = Ledger::new(Action::Deposit("10".to_string()), time_ordered()).unwrap();
+        ledgers.add(ledger.clone()).unwrap();
+        assert_eq!(ledgers.add(ledger), Err(LedgerError::DuplicateLedger));
+        assert_eq!(ledgers.sum(), Decimal::new(10, 0));
+        assert_eq!(ledgers.len(), 1);
+    }
+
+    fn carried(opening: Decimal, opening_id: Option<&str>) -> Ledgers {
+        Ledgers::carried_forward(Carried {
+            opening,
+            opening_id: opening_id.map(str::to_string),
+            ..Carried::default()
+        })
+    }
+
+    #[test]
+    fn test_ledgers_carried_forward() {
+        let mut ledgers = carried(Decimal::new(50, 0), Some("last"));
+        assert_eq!(ledgers.sum(), Decimal::new(50, 0));
+        assert_eq!(ledgers.last_id(), Some("last"));
+
+        let ledger = Ledger::new(Action::Withdrawal("20".to_string()), time_ordered()).unwrap();
+        ledgers.add(ledger).unwrap();
+        assert_eq!(ledgers.running_balance(0), Some(Decimal::new(30, 0)));
+        assert_eq!(ledgers.sum(), Decimal::new(30, 0));
+    }
+
+    #[test]
+    fn test_ledgers_compact() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let clock = FixedClock::new(start);
+        let mut ledgers = Ledgers::new();
+        let mut post = |ledger: Ledger| {
+            let id = ledger.id().to_string();
+            ledgers.add(ledger).unwrap();
+            clock.advance(chrono::Duration::days(1));
+            id
+        };
+        let new = |action: Action| Ledger::new_with_clock(action, time_ordered(), &clock).unwrap();
+        let refunded = post(new(Action::Deposit("10".to_string())));
+        let reversed = post(new(Action::Deposit("5".to_string())));
+        post(Ledger::reversing(
+            &reversed,
+            Decimal::new(5, 0),
+            time_ordered(),
+            &clock,
+        ));
+        post(Ledger::reversing(
+            &refunded,
+            Decimal::new(4, 0),
+            time_ordered(),
+            &clock,
+        ));
+        let kept = post(new(Action::Withdrawal("1".to_string())));
+        post(Ledger::reversing(
+            &refunded,
+            Decimal::new(2, 0),
+            time_ordered(),
+            &clock,
+        ));
+
+        let compacted = ledgers.compact(start + chrono::Duration::days(3));
+        assert_eq!(compacted.len(), 2);
+        assert_eq!(compacted.entries()[0].id(), kept);
+        assert_eq!(compacted.opening(), Decimal::new(6, 0));
+        assert_eq!(compacted.sum(), ledgers.sum());
+        // The partly refunded deposit can still be refunded; the fully
+        // reversed one is forgotten.
+        assert_eq!(
+            compacted.compacted_amount(&refunded),
+            Some(Decimal::new(10, 0))
+        );
+        assert_eq!(compacted.reversed_amount(&refunded), Decimal::new(6, 0));
+        assert_eq!(compacted.compacted_amount(&reversed), None);
+
+        let mut rebuilt = Ledgers::carried_forward(compacted.carried());
+        for ledger in compacted.entries() {
+            rebuilt.add(ledger.clone()).unwrap();
+        }
+        assert_eq!(rebuilt, compacted);
+    }
+
+    #[test]
+    fn test_ledger_booked_at_from_clock() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 10, 23, 30, 0).unwrap()
+            + chrono::Duration::nanoseconds(1_234_567);
+        let clock = FixedClock::new(now);
+        let ledger =
+            Ledger::new_with_clock(Action::Deposit("1".to_string()), time_ordered(), &clock)
+                .unwrap();
+        assert_eq!(ledger.booked_at(), now.trunc_subsecs(6));
+        assert_eq!(ledger.value_date(), date(2024, 3, 10));
+
+        let ledger = ledger.with_value_date(date(2024, 3, 1));
+        assert_eq!(ledger.value_date(), date(2024, 3, 1));
+        assert_eq!(ledger.booked_at(), now.trunc_subsecs(6));
+    }
+
+    #[test]
+    fn test_ledgers_balance_as_of_and_entries_between() {
+        let clock = FixedClock::new(Utc.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap());
+        let mut ledgers = carried(Decimal::new(5, 0), None);
+        let mut post = |action: Action, value_date: Option<NaiveDate>| {
+            let mut ledger = Ledger::new_with_clock(action, time_ordered(), &clock).unwrap();
+            if let Some(value_date) = value_date {
+                ledger = ledger.with_value_date(value_date);
+            }
+            ledgers.add(ledger).unwrap();
+            clock.advance(chrono::Duration::days(1));
+        };
+        post(Action::Deposit("100".to_string()), None);
+        post(Action::Withdrawal("30".to_string()), None);
+        // Booked on the 3rd but back-valued to the 1st.
+        post(Action::Deposit("7".to_string()), Some(date(2024, 3, 1)));
+        post(Action::Deposit("1".to_string()), Some(date(2024, 3, 20)));
+
+        assert_eq!(ledgers.balance_as_of(date(2024, 2, 29)), Decimal::new(5, 0));
+        assert_eq!(
+            ledgers.balance_as_of(date(2024, 3, 1)),
+            Decimal::new(112, 0)
+        );
+        assert_eq!(ledgers.balance_as_of(date(2024, 3, 2)), Decimal::new(82, 0));
+        assert_eq!(
+            ledgers.balance_as_of(date(2024, 3, 19)),
+            Decimal::new(82, 0)
+        );
+        assert_eq!(ledgers.balance_as_of(date(2024, 3, 20)), ledgers.sum());
+
+        let amounts: Vec<Decimal> = ledgers
+            .entries_between(date(2024, 3, 1), date(2024, 3, 1))
+            .map(|ledger| ledger.amount())
+            .collect();
+        assert_eq!(amounts, vec![Decimal::new(100, 0), Decimal::new(7, 0)]);
+        assert_eq!(
+            ledgers
+                .entries_between(date(2024, 3, 2), date(2024, 3, 31))
+                .count(),
+            2
+        );
+        assert_eq!(
+            ledgers
+                .entries_between(date(2024, 3, 5), date(2024, 3, 4))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_ledgers_point_in_time_after_truncate_and_carry_forward() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 30, 0, 0, 0).unwrap();
+        let clock = FixedClock::new(start);
+        let mut ledgers = carried(Decimal::new(50, 0), Some("last"));
+        let mut ids = vec![];
+        for _ in 0..3 {
+            let ledger =
+                Ledger::new_with_clock(Action::Deposit("1".to_string()), time_ordered(), &clock)
+                    .unwrap();
+            ids.push(ledger.id().to_string());
+            ledgers.add(ledger).unwrap();
+            clock.advance(chrono::Duration::minutes(1));
+        }
+        assert_eq!(ledgers.balance_after("last"), Some(Decimal::new(50, 0)));
+        assert_eq!(ledgers.balance_after(&ids[2]), Some(Decimal::new(53, 0)));
+        assert_eq!(ledgers.balance_at(start), Decimal::new(51, 0));
+
+        ledgers.truncate(1);
+        assert_eq!(ledgers.balance_after(&ids[2]), None);
+        assert_eq!(ledgers.balance_at(clock.now()), Decimal::new(51, 0));
+        assert_eq!(
+            ledgers.balance_at(start - chrono::Duration::days(1)),
+            Decimal::new(50, 0)
+        );
+    }
+
+    #[test]
+    fn test_ledgers_booked_after() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 30, 12, 0, 0).unwrap();
+        let clock = FixedClock::new(start);
+        let mut ledgers = Ledgers::new();
+        // The last entry is stamped before its predecessor, as after a clock
+        // step back.
+        for step in [0, 10, 10, -15] {
+            clock.advance(chrono::Duration::minutes(step));
+            let ledger =
+                Ledger::new_with_clock(Action::Deposit("1".to_string()), time_ordered(), &clock)
+                    .unwrap();
+            ledgers.add(ledger).unwrap();
+        }
+        let booked = |at| {
+            ledgers
+                .booked_after(at)
+                .map(Ledger::booked_at)
+                .collect::<Vec<_>>()
+        };
+        let minutes = |n| start + chrono::Duration::minutes(n);
+        assert_eq!(booked(minutes(-1)).len(), 4);
+        assert_eq!(booked(minutes(0)), [minutes(10), minutes(20), minutes(5)]);
+        assert_eq!(booked(minutes(10)), [minutes(20)]);
+        assert!(booked(minutes(20)).is_empty());
+    }
+
+    #[test]
+    fn test_ledger_id_from_generator() {
+        let ids = Sequence::starting_at(7);
+        let first = Ledger::new(Action::Deposit("1".to_string()), &ids).unwrap();
+        let second = Ledger::new(Action::Deposit("1".to_string()), &ids).unwrap();
+        assert_eq!(first.id(), "00000000000000000007");
+        assert_eq!(second.id(), "00000000000000000008");
+
+        // Equal seeds replay the same ids, which the collection refuses twice.
+        let replayed = Ledger::new(Action::Deposit("1".to_string()), &Seeded::new(1)).unwrap();
+        let again = Ledger::new(Action::Deposit("1".to_string()), &Seeded::new(1)).unwrap();
+        assert_eq!(replayed.id(), again.id());
+        let mut ledgers = Ledgers::new();
+        ledgers.add(replayed).unwrap();
+        assert_eq!(ledgers.add(again), Err(LedgerError::DuplicateLedger));
+    }
+
+    #[test]
+    fn test_ledger_new_sign_per_kind() {
+        let amount = |action: Action| Ledger::new(action, time_ordered()).unwrap().amount();
+        assert_eq!(
+            amount(Action::TransferIn("5".to_string())),
+            Decimal::new(5, 0)
+        );
+        assert_eq!(
+            amount(Action::Interest("0.5".to_string())),
+            Decimal::new(5, 1)
+        );
+        assert_eq!(
+            amount(Action::TransferOut("5".to_string())),
+            Decimal::new(-5, 0)
+        );
+        assert_eq!(
+            amount(Action::Fee("1.25".to_string())),
+            Decimal::new(-125, 2)
+        );
+        assert_eq!(
+            amount(Action::Adjustment("2".to_string())),
+            Decimal::new(2, 0)
+        );
+        assert_eq!(
+            amount(Action::Adjustment("-2".to_string())),
+            Decimal::new(-2, 0)
+        );
+        assert_eq!(
+            amount(Action::Reversal("-3".to_string())),
+            Decimal::new(-3, 0)
+        );
+
+        for action in [
+            Action::Fee("-1".to_string()),
+            Action::Interest("-1".to_string()),
+            Action::Adjustment("-0".to_string()),
+            Action::Reversal("--1".to_string()),
+        ] {
+            assert!(matches!(
+                Ledger::new(action, time_ordered()),
+                Err(LedgerError::InvalidAmount(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_action_kind_round_trip() {
+        for kind in ActionKind::ALL {
+            assert_eq!(kind.as_str().parse(), Ok(kind));
+        }
+        assert_eq!(
+            "Refund".parse::<ActionKind>(),
+            Err(LedgerError::UnknownAction("Refund".to_string()))
+        );
+        assert_eq!(Action::Fee("1".to_string()).to_string(), "Fee");
+    }
+
+    #[test]
+    fn test_ledgers_entries_of_kind() {
+        let mut ledgers = Ledgers::new();
+        for action in [
+            Action::Deposit("100".to_string()),
+            Action::Fee("1".to_string()),
+            Action::Interest("2".to_string()),
+            Action::Fee("3".to_string()),
+        ] {
+            ledgers
+                .add(Ledger::new(action, time_ordered()).unwrap())
+                .unwrap();
+        }
+        let fees: Vec<Decimal> = ledgers
+            .entries_of(ActionKind::Fee)
+            .map(|ledger| ledger.amount())
+            .collect();
+        assert_eq!(fees, vec![Decimal::new(-1, 0), Decimal::new(-3, 0)]);
+        assert_eq!(ledgers.entries_of(ActionKind::Withdrawal).count(), 0);
+    }
+
+    #[test]
+    fn test_ledgers_truncate_forgets_reversals() {
+        let mut ledgers = Ledgers::new();
+        let original = Ledger::new(Action::Deposit("10".to_string()), time_ordered()).unwrap();
+        let refund = Ledger::reversing(
+            original.id(),
+            Decimal::new(4, 0),
+            time_ordered(),
+            &SystemClock,
+        );
+        assert_eq!(refund.amount(), Decimal::new(-4, 0));
+        let id = original.id().to_string();
+        ledgers.add(original).unwrap();
+        ledgers.add(refund).unwrap();
+        assert_eq!(ledgers.reversed_amount(&id), Decimal::new(4, 0));
+
+        ledgers.truncate(1);
+        assert_eq!(ledgers.reversed_amount(&id), Decimal::ZERO);
+        assert!(ledgers.get(&id).is_some());
+    }
+
     #[test]
     fn test_ledgers_add_duplicate() {
         let mut ledgers = Ledgers::new();
         let action1 = Action::Deposit("100.0".to_string());
         let action2 = Action::Deposit("100.0".to_string());
-        let ledger1 = Ledger::new(action1).unwrap();
-        let mut ledger2 = Ledger::new(action2).unwrap();
+        let ledger1 = Ledger::new(action1, time_ordered()).unwrap();
+        let mut ledger2 = Ledger::new(action2, time_ordered()).unwrap();
         ledger2.id = ledger1.id.clone();
 
         assert_eq!(ledgers.add(ledger1), Ok(()));
         assert_eq!(ledgers.add(ledger2), Err(LedgerError::DuplicateLedger));
     }
+
+    #[test]
+    fn test_ledger_new_keeps_exact_decimal() {
+        let action = Action::Deposit("0.1".to_string());
+        let ledger = Ledger::new(action, time_ordered()).unwrap();
+        assert_eq!(ledger.amount(), Decimal::new(1, 1));
+
+        let action = Action::Deposit("12345678901234567.89".to_string());
+        let ledger = Ledger::new(action, time_ordered()).unwrap();
+        assert_eq!(ledger.amount().to_string(), "12345678901234567.89");
+    }
+
+    #[test]
+    fn test_parse_amount_rejections() {
+        assert_eq!(parse_amount(""), Err(LedgerError::EmptyAmount));
+        assert!(matches!(
+            parse_amount("-1"),
+            Err(LedgerError::InvalidAmount(_))
+        ));
+        assert_eq!(parse_amount("NaN"), Err(LedgerError::NonFiniteAmount));
+        assert_eq!(parse_amount("inf"), Err(LedgerError::NonFiniteAmount));
+        assert_eq!(parse_amount("Infinity"), Err(LedgerError::NonFiniteAmount));
+        assert_eq!(parse_amount("1e5"), Err(LedgerError::ExponentNotAllowed));
+        assert_eq!(parse_amount("1.5E2"), Err(LedgerError::ExponentNotAllowed));
+        assert_eq!(parse_amount("+1"), Err(LedgerError::InvalidCharacter('+')));
+        assert_eq!(parse_amount(" 1"), Err(LedgerError::InvalidCharacter(' ')));
+        assert_eq!(
+            parse_amount("1,000"),
+            Err(LedgerError::InvalidCharacter(','))
+        );
+        assert_eq!(parse_amount("007"), Err(LedgerError::LeadingZero));
+        assert_eq!(parse_amount(".5"), Err(LedgerError::ParseAmount));
+        assert_eq!(parse_amount("5."), Err(LedgerError::ParseAmount));
+        assert_eq!(parse_amount("1.2.3"), Err(LedgerError::ParseAmount));
+        assert_eq!(
+            parse_amount("0.1234567890123456789"),
+            Err(LedgerError::ScaleTooLarge(19))
+        );
+        assert_eq!(
+            parse_amount("79228162514264337593543950336"),
+            Err(LedgerError::AmountOverflow)
+        );
+    }
+
+    #[test]
+    fn test_parse_amount_bounds() {
+        assert_eq!(
+            parse_amount("79228162514264337593543950335").unwrap(),
+            Decimal::MAX
+        );
+        assert_eq!(
+            parse_amount("0.000000000000000001").unwrap(),
+            Decimal::new(1, MAX_AMOUNT_SCALE)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_amount_accepts_grammar(s in "(0|[1-9][0-9]{0,9})(\\.[0-9]{1,18})?") {
+            let amount = parse_amount(&s).unwrap();
+            prop_assert_eq!(amount.to_string(), s);
+        }
+
+        #[test]
+        fn prop_parse_amount_accepted_strings_round_trip(s in "\\PC{0,32}") {
+            if let Ok(amount) = parse_amount(&s) {
+                prop_assert_eq!(amount.to_string(), s);
+            }
+        }
+
+        #[test]
+        fn prop_decimal_display_is_accepted(mantissa in 0i64.., scale in 0u32..=MAX_AMOUNT_SCALE) {
+            let amount = Decimal::new(mantissa, scale);
+            prop_assert_eq!(parse_amount(&amount.to_string()), Ok(amount));
+        }
+    }
 }
@@ -0,0 +1,16 @@
+pub mod balance;
+pub mod chart;
+pub mod clock;
+pub mod currency;
+pub mod fx;
+pub mod hold;
+pub mod id;
+pub mod journal;
+pub mod ledger;
+pub mod limits;
+pub mod policy;
+pub mod service;
+pub mod statement;
+pub mod status;
+pub mod storage;
+pub mod wallet;
@@ -0,0 +1,255 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use std::fmt;
+
+use crate::ledger::{ActionKind, Ledgers};
+
+/// Caps on the outflows booked within one rolling window. `None` leaves that
+/// dimension unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limit {
+    pub max_amount: Option<Decimal>,
+    pub max_count: Option<u32>,
+}
+
+/// Rolling limits on customer outflows, checked by `Balance::mutate`: see
+/// `VelocityLimits::counts` for which entries those are. The daily window
+/// covers the 24 hours up to an outflow's booking time, the monthly one the
+/// 30 days up to it. Reversed or refunded outflows still count. The default
+/// is unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct VelocityLimits {
+    pub daily: Limit,
+    pub monthly: Limit,
+}
+
+/// Names the limit an outflow would breach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VelocityLimit {
+    DailyAmount,
+    DailyCount,
+    MonthlyAmount,
+    MonthlyCount,
+}
+
+impl VelocityLimits {
+    /// How far back the longest window reaches. Compaction keeps every entry
+    /// booked within it, so limits are still enforced afterwards.
+    pub fn longest_window() -> Duration {
+        Duration::days(30)
+    }
+
+    /// Whether entries of `kind` count towards the limits: only withdrawals
+    /// and outgoing transfers, which take funds out of the account, do.
+    /// Conversions move funds between the account's own pockets and are
+    /// exempt, as are fees, interest, adjustments and reversals, which the
+    /// institution books rather than the customer.
+    pub fn counts(kind: ActionKind) -> bool {
+        matches!(kind, ActionKind::Withdrawal | ActionKind::TransferOut)
+    }
+
+    /// Checks an outflow of `amount` (a magnitude) booked at `at` against
+    /// the outflows already in `ledgers`.
+    pub(crate) fn check(
+        &self,
+        ledgers: &Ledgers,
+        at: DateTime<Utc>,
+        amount: Decimal,
+    ) -> Result<(), VelocityLimit> {
+        let windows = [
+            (
+                self.daily,
+                Duration::days(1),
+                VelocityLimit::DailyAmount,
+                VelocityLimit::DailyCount,
+            ),
+            (
+                self.monthly,
+                VelocityLimits::longest_window(),
+                VelocityLimit::MonthlyAmount,
+                VelocityLimit::MonthlyCount,
+            ),
+        ];
+        for (limit, length, amount_limit, count_limit) in windows {
+            if limit == Limit::default() {
+                continue;
+            }
+            let (total, count) = ledgers
+                .booked_after(at - length)
+                .filter(|ledger| VelocityLimits::counts(ledger.action()))
+                .filter(|ledger| ledger.booked_at() <= at)
+                .fold((amount, 1), |(total, count), ledger| {
+                    (total + ledger.amount().abs(), count + 1)
+                });
+            if limit.max_amount.is_some_and(|max| total > max) {
+                return Err(amount_limit);
+            }
+            if limit.max_count.is_some_and(|max| count > max) {
+                return Err(count_limit);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for VelocityLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            VelocityLimit::DailyAmount => "daily outflow amount",
+            VelocityLimit::DailyCount => "daily outflow count",
+            VelocityLimit::MonthlyAmount => "monthly outflow amount",
+            VelocityLimit::MonthlyCount => "monthly outflow count",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::id::time_ordered;
+    use crate::ledger::{Action, Ledger};
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    fn at() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 7, 1, 10, 0, 0).unwrap()
+    }
+
+    fn entry(action: Action, booked_at: DateTime<Utc>) -> Ledger {
+        Ledger::new_with_clock(action, time_ordered(), &FixedClock::new(booked_at)).unwrap()
+    }
+
+    fn withdrawal(amount: &str, booked_at: DateTime<Utc>) -> Ledger {
+        entry(Action::Withdrawal(amount.to_string()), booked_at)
+    }
+
+    fn ledgers(entries: impl IntoIterator<Item = Ledger>) -> Ledgers {
+        let mut ledgers = Ledgers::new();
+        for ledger in entries {
+            ledgers.add(ledger).unwrap();
+        }
+        ledgers
+    }
+
+    fn daily(max_amount: Option<Decimal>, max_count: Option<u32>) -> VelocityLimits {
+        VelocityLimits {
+            daily: Limit {
+                max_amount,
+                max_count,
+            },
+            monthly: Limit::default(),
+        }
+    }
+
+    #[test]
+    fn test_only_withdrawals_and_transfers_out_count() {
+        let counted: Vec<ActionKind> = ActionKind::ALL
+            .into_iter()
+            .filter(|kind| VelocityLimits::counts(*kind))
+            .collect();
+        assert_eq!(counted, [ActionKind::Withdrawal, ActionKind::TransferOut]);
+
+        let limits = daily(None, Some(1));
+        let exempt = ledgers([
+            Ledger::conversion(dec!(-50), time_ordered(), &FixedClock::new(at())),
+            entry(Action::Fee("5".to_string()), at()),
+            entry(Action::Adjustment("-5".to_string()), at()),
+        ]);
+        assert_eq!(limits.check(&exempt, at(), dec!(10)), Ok(()));
+        let transfer = ledgers([entry(Action::TransferOut("5".to_string()), at())]);
+        assert_eq!(
+            limits.check(&transfer, at(), dec!(10)),
+            Err(VelocityLimit::DailyCount)
+        );
+    }
+
+    #[test]
+    fn test_amount_and_count_breaches() {
+        let entries = ledgers([
+            withdrawal("40", at() - Duration::hours(2)),
+            withdrawal("50", at() - Duration::hours(1)),
+        ]);
+
+        let limits = daily(Some(dec!(100)), None);
+        assert_eq!(limits.check(&entries, at(), dec!(10)), Ok(()));
+        assert_eq!(
+            limits.check(&entries, at(), dec!(10.01)),
+            Err(VelocityLimit::DailyAmount)
+        );
+
+        let limits = daily(None, Some(3));
+        assert_eq!(limits.check(&entries, at(), dec!(500)), Ok(()));
+        let limits = daily(None, Some(2));
+        assert_eq!(
+            limits.check(&entries, at(), dec!(0.01)),
+            Err(VelocityLimit::DailyCount)
+        );
+
+        let limits = VelocityLimits {
+            daily: Limit::default(),
+            monthly: Limit {
+                max_amount: Some(dec!(95)),
+                max_count: Some(2),
+            },
+        };
+        assert_eq!(
+            limits.check(&entries, at(), dec!(5.01)),
+            Err(VelocityLimit::MonthlyAmount)
+        );
+        assert_eq!(
+            limits.check(&entries, at(), dec!(5)),
+            Err(VelocityLimit::MonthlyCount)
+        );
+    }
+
+    #[test]
+    fn test_daily_window_edges() {
+        let limits = daily(None, Some(1));
+        // The window is open at its start: an outflow exactly 24 hours back
+        // has dropped out of it.
+        let edge = ledgers([withdrawal("10", at() - Duration::days(1))]);
+        assert_eq!(limits.check(&edge, at(), dec!(10)), Ok(()));
+        let inside = ledgers([withdrawal(
+            "10",
+            at() - Duration::days(1) + Duration::seconds(1),
+        )]);
+        assert_eq!(
+            limits.check(&inside, at(), dec!(10)),
+            Err(VelocityLimit::DailyCount)
+        );
+    }
+
+    #[test]
+    fn test_monthly_window_edges() {
+        let limits = VelocityLimits {
+            daily: Limit::default(),
+            monthly: Limit {
+                max_amount: Some(dec!(100)),
+                max_count: None,
+            },
+        };
+        let window = VelocityLimits::longest_window();
+        let edge = ledgers([withdrawal("95", at() - window)]);
+        assert_eq!(limits.check(&edge, at(), dec!(10)), Ok(()));
+        let inside = ledgers([withdrawal("95", at() - window + Duration::seconds(1))]);
+        assert_eq!(
+            limits.check(&inside, at(), dec!(10)),
+            Err(VelocityLimit::MonthlyAmount)
+        );
+    }
+
+    #[test]
+    fn test_entries_booked_after_the_outflow_are_ignored() {
+        // A back-dated outflow is only checked against what was booked up to
+        // its own time.
+        let limits = daily(None, Some(1));
+        let entries = ledgers([withdrawal("10", at() + Duration::hours(1))]);
+        assert_eq!(limits.check(&entries, at(), dec!(10)), Ok(()));
+        assert_eq!(
+            limits.check(&entries, at() + Duration::hours(1), dec!(10)),
+            Err(VelocityLimit::DailyCount)
+        );
+    }
+}
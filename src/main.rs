@@ -1,10 +1,6 @@
-mod balance;
-mod ledger;
-mod storage;
-
-use balance::Balance;
-use ledger::{Action, Ledger};
-use storage::InMemory;
+use rust_pg::balance::Balance;
+use rust_pg::service;
+use rust_pg::storage::{InMemory, Storage};
 
 fn main() {
     let mut storage = InMemory::new();
@@ -25,63 +21,49 @@ fn main() {
     }
 
     // Simulate deposit
-    match simulate_deposit(&mut storage, account_id, 100.0) {
+    match simulate_deposit(&mut storage, account_id, 100.0, "deposit-1") {
         Ok(_) => println!("Deposit successful"),
         Err(e) => println!("Error during deposit: {:?}", e),
     }
 
     // Simulate withdrawal
-    match simulate_withdrawal(&mut storage, account_id, 50.0) {
+    match simulate_withdrawal(&mut storage, account_id, 50.0, "withdrawal-1") {
         Ok(_) => println!("Withdrawal successful"),
         Err(e) => println!("Error during withdrawal: {:?}", e),
     }
 
     // Simulate deposit
-    match simulate_deposit(&mut storage, account_id, 100.0) {
+    match simulate_deposit(&mut storage, account_id, 100.0, "deposit-2") {
         Ok(_) => println!("Deposit successful"),
         Err(e) => println!("Error during deposit: {:?}", e),
     }
 
     // Display final balance
-    if let Some(balance) = storage.get(account_id) {
+    if let Ok(balance) = storage.get(account_id) {
         println!("Final balance: {}", balance);
     } else {
         println!("Account not found");
     }
 }
 
-fn simulate_deposit(storage: &mut InMemory, account_id: &str, amount: f64) -> Result<(), String> {
-    let action = Action::Deposit(amount.to_string());
-    let ledger = Ledger::new(action).map_err(|e| format!("Error creating ledger: {:?}", e))?;
-    if let Some(mut balance) = storage.get(account_id) {
-        balance.
-            mutate(ledger)
-            .map_err(|e| format!("Error mutating balance: {:?}", e))?;
-        storage.
-            update(account_id, balance.clone()).
-            map_err(|e| format!("Error updating balance: {:?}", e))?;
-    } else {
-        return Err("Account not found".to_string());
-    }
+fn simulate_deposit<S: Storage>(
+    storage: &mut S,
+    account_id: &str,
+    amount: f64,
+    idempotency_key: &str,
+) -> Result<(), String> {
+    service::deposit_with_key(storage, account_id, &amount.to_string(), idempotency_key)
+        .map_err(|e| format!("Error during deposit: {:?}", e))?;
     Ok(())
 }
 
-fn simulate_withdrawal(
-    storage: &mut InMemory,
+fn simulate_withdrawal<S: Storage>(
+    storage: &mut S,
     account_id: &str,
     amount: f64,
+    idempotency_key: &str,
 ) -> Result<(), String> {
-    let action = Action::Withdrawal(amount.to_string());
-    let ledger = Ledger::new(action).map_err(|e| format!("Error creating ledger: {:?}", e))?;
-    if let Some(mut balance) = storage.get(account_id) {
-        balance
-            .mutate(ledger)
-            .map_err(|e| format!("Error mutating balance: {:?}", e))?;
-        storage.
-            update(account_id, balance.clone()).
-            map_err(|e| format!("Error updating balance: {:?}", e))?;
-    } else {
-        return Err("Account not found".to_string());
-    }
+    service::withdraw_with_key(storage, account_id, &amount.to_string(), idempotency_key)
+        .map_err(|e| format!("Error during withdrawal: {:?}", e))?;
     Ok(())
 }
@@ -0,0 +1,104 @@
+use rust_decimal::Decimal;
+
+use crate::ledger::{parse_amount, LedgerError};
+
+/// How far a balance may be drawn down. Debits and holds that would take
+/// the available balance below the policy's floor are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Policy {
+    /// The balance may not go below zero.
+    #[default]
+    NoOverdraft,
+    /// The balance may go negative, down to `-limit`.
+    Overdraft { limit: Decimal },
+    /// The balance must stay at or above `required`, e.g. for savings
+    /// products.
+    MinimumBalance { required: Decimal },
+}
+
+impl Policy {
+    pub fn overdraft(limit: &str) -> Result<Policy, LedgerError> {
+        Ok(Policy::Overdraft {
+            limit: parse_amount(limit)?,
+        })
+    }
+
+    pub fn minimum_balance(required: &str) -> Result<Policy, LedgerError> {
+        Ok(Policy::MinimumBalance {
+            required: parse_amount(required)?,
+        })
+    }
+
+    /// The lowest balance this policy allows.
+    pub fn floor(&self) -> Decimal {
+        match self {
+            Policy::NoOverdraft => Decimal::ZERO,
+            Policy::Overdraft { limit } => -limit.abs(),
+            Policy::MinimumBalance { required } => required.abs(),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Policy::NoOverdraft => "no_overdraft",
+            Policy::Overdraft { .. } => "overdraft",
+            Policy::MinimumBalance { .. } => "minimum_balance",
+        }
+    }
+
+    /// The policy's limit or required balance, if it has one.
+    pub fn amount(&self) -> Option<Decimal> {
+        match self {
+            Policy::NoOverdraft => None,
+            Policy::Overdraft { limit } => Some(*limit),
+            Policy::MinimumBalance { required } => Some(*required),
+        }
+    }
+
+    pub(crate) fn restore(name: &str, amount: Option<Decimal>) -> Option<Policy> {
+        match (name, amount) {
+            ("no_overdraft", None) => Some(Policy::NoOverdraft),
+            ("overdraft", Some(limit)) => Some(Policy::Overdraft { limit }),
+            ("minimum_balance", Some(required)) => Some(Policy::MinimumBalance { required }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_policy_floor() {
+        assert_eq!(Policy::default().floor(), dec!(0));
+        assert_eq!(Policy::overdraft("250").unwrap().floor(), dec!(-250));
+        assert_eq!(Policy::minimum_balance("100").unwrap().floor(), dec!(100));
+    }
+
+    #[test]
+    fn test_policy_rejects_negative_amounts() {
+        assert!(matches!(
+            Policy::overdraft("-5"),
+            Err(LedgerError::InvalidAmount(_))
+        ));
+        assert_eq!(Policy::minimum_balance(""), Err(LedgerError::EmptyAmount));
+    }
+
+    #[test]
+    fn test_policy_restore_round_trip() {
+        for policy in [
+            Policy::NoOverdraft,
+            Policy::overdraft("50.5").unwrap(),
+            Policy::minimum_balance("10").unwrap(),
+        ] {
+            assert_eq!(
+                Policy::restore(policy.as_str(), policy.amount()),
+                Some(policy)
+            );
+        }
+        assert_eq!(Policy::restore("overdraft", None), None);
+        assert_eq!(Policy::restore("unknown", Some(dec!(1))), None);
+    }
+}
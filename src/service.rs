@@ -0,0 +1,361 @@
+use rust_decimal::Decimal;
+
+use crate::journal::{InternalAccount, Transaction};
+use crate::ledger::{parse_amount, LedgerError};
+use crate::storage::{Storage, StorageError};
+
+/// Credits `amount` to the account against its currency's cash account and
+/// returns the new total.
+pub fn deposit<S: Storage>(
+    storage: &mut S,
+    account_id: &str,
+    amount: &str,
+) -> Result<Decimal, StorageError> {
+    post_against_cash(storage, account_id, amount, None, Transaction::deposit)
+}
+
+/// Pays `amount` out of the account against its currency's cash account and
+/// returns the new total.
+pub fn withdraw<S: Storage>(
+    storage: &mut S,
+    account_id: &str,
+    amount: &str,
+) -> Result<Decimal, StorageError> {
+    post_against_cash(storage, account_id, amount, None, Transaction::withdrawal)
+}
+
+/// Like `deposit`, but a retry carrying the same `idempotency_key` returns
+/// the original result instead of crediting the account again.
+pub fn deposit_with_key<S: Storage>(
+    storage: &mut S,
+    account_id: &str,
+    amount: &str,
+    idempotency_key: &str,
+) -> Result<Decimal, StorageError> {
+    let key = Some(idempotency_key);
+    post_against_cash(storage, account_id, amount, key, Transaction::deposit)
+}
+
+/// Like `withdraw`, but idempotent per `idempotency_key`; see
+/// `deposit_with_key`.
+pub fn withdraw_with_key<S: Storage>(
+    storage: &mut S,
+    account_id: &str,
+    amount: &str,
+    idempotency_key: &str,
+) -> Result<Decimal, StorageError> {
+    let key = Some(idempotency_key);
+    post_against_cash(storage, account_id, amount, key, Transaction::withdrawal)
+}
+
+// Posts the transaction `build` makes for `amount` in the account's
+// currency, opening that currency's cash account if needed, and returns the
+// account's new total.
+fn post_against_cash<S: Storage>(
+    storage: &mut S,
+    account_id: &str,
+    amount: &str,
+    idempotency_key: Option<&str>,
+    build: fn(&str, &str, &str) -> Result<Transaction, LedgerError>,
+) -> Result<Decimal, StorageError> {
+    // A malformed amount is reported before the account is looked up.
+    parse_amount(amount).map_err(StorageError::Ledger)?;
+    let currency = storage.currency(account_id)?;
+    let mut transaction = build(account_id, &currency, amount).map_err(StorageError::Ledger)?;
+    if let Some(key) = idempotency_key {
+        transaction = transaction.with_idempotency_key(key);
+    }
+    InternalAccount::Cash.open(storage, &currency)?;
+    Ok(storage.post(transaction)?.totals[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::{Balance, BalanceError};
+    use crate::clock::SystemClock;
+    use crate::fx::{Conversion, Converter, FxError, RateProvider};
+    use crate::hold::Hold;
+    use crate::id::time_ordered;
+    use crate::journal::{JournalError, Posted, TrialBalance, TrialBalanceLine};
+    use crate::ledger::Ledger;
+    use crate::status::{AccountStatus, StatusChange};
+    use crate::storage::InMemory;
+    use crate::wallet::Wallet;
+    use rust_decimal_macros::dec;
+    use std::collections::BTreeMap;
+
+    // Test double holding one single-currency balance per account, that
+    // records every ledger posted to them.
+    #[derive(Default)]
+    struct Recording {
+        balances: BTreeMap<String, Balance>,
+        appended: Vec<Ledger>,
+    }
+
+    impl Storage for Recording {
+        fn insert(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError> {
+            if self.balances.contains_key(account_id) {
+                return Err(StorageError::AccountAlreadyExists);
+            }
+            self.balances.insert(account_id.to_string(), balance);
+            Ok(())
+        }
+
+        fn get(&self, account_id: &str) -> Result<Balance, StorageError> {
+            self.balances
+                .get(account_id)
+                .cloned()
+                .ok_or(StorageError::AccountNotExists)
+        }
+
+        fn currency(&self, account_id: &str) -> Result<String, StorageError> {
+            Ok(self.get(account_id)?.currency)
+        }
+
+        fn update(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError> {
+            *self.balance_mut(account_id)? = balance;
+            Ok(())
+        }
+
+        fn list(&self) -> Result<Vec<String>, StorageError> {
+            Ok(self.balances.keys().cloned().collect())
+        }
+
+        fn delete(&mut self, account_id: &str) -> Result<Balance, StorageError> {
+            self.balances
+                .remove(account_id)
+                .ok_or(StorageError::AccountNotExists)
+        }
+
+        fn append(&mut self, account_id: &str, ledger: Ledger) -> Result<Decimal, StorageError> {
+            let balance = self
+                .balances
+                .get_mut(account_id)
+                .ok_or(StorageError::AccountNotExists)?;
+            self.appended.push(ledger.clone());
+            balance.mutate(ledger).map_err(StorageError::Balance)
+        }
+
+        fn place_hold(&mut self, account_id: &str, hold: Hold) -> Result<Decimal, StorageError> {
+            let balance = self.balance_mut(account_id)?;
+            balance.place_hold(hold).map_err(StorageError::Balance)
+        }
+
+        fn capture_hold(
+            &mut self,
+            account_id: &str,
+            hold_id: &str,
+            amount: Option<&str>,
+        ) -> Result<Decimal, StorageError> {
+            let balance = self.balance_mut(account_id)?;
+            balance
+                .capture(hold_id, amount, time_ordered(), &SystemClock)
+                .map_err(StorageError::Balance)
+        }
+
+        fn release_hold(&mut self, account_id: &str, hold_id: &str) -> Result<Hold, StorageError> {
+            let balance = self.balance_mut(account_id)?;
+            balance.release(hold_id).map_err(StorageError::Balance)
+        }
+
+        fn set_status(
+            &mut self,
+            account_id: &str,
+            status: AccountStatus,
+            reason: &str,
+        ) -> Result<StatusChange, StorageError> {
+            let balance = self.balance_mut(account_id)?;
+            balance
+                .transition(status, reason)
+                .map_err(StorageError::Balance)
+        }
+
+        fn open_pocket(&mut self, _: &str, _: &str) -> Result<(), StorageError> {
+            Err(StorageError::Backend(
+                "recording storage holds a single currency".to_string(),
+            ))
+        }
+
+        fn pockets(&self, account_id: &str) -> Result<Vec<String>, StorageError> {
+            Ok(vec![self.get(account_id)?.currency])
+        }
+
+        fn get_pocket(&self, account_id: &str, currency: &str) -> Result<Balance, StorageError> {
+            let balance = self.get(account_id)?;
+            if !balance.currency.eq_ignore_ascii_case(currency) {
+                return Err(StorageError::PocketNotExists);
+            }
+            Ok(balance)
+        }
+
+        fn append_to_pocket(
+            &mut self,
+            account_id: &str,
+            currency: &str,
+            ledger: Ledger,
+        ) -> Result<Decimal, StorageError> {
+            self.get_pocket(account_id, currency)?;
+            self.append(account_id, ledger)
+        }
+
+        // Posts leg by leg, so a rejected leg leaves the ones before it.
+        fn post(&mut self, transaction: Transaction) -> Result<Posted, StorageError> {
+            let mut totals = vec![];
+            for posting in transaction.postings() {
+                self.get_pocket(posting.account_id(), posting.currency())?;
+                totals.push(self.append(posting.account_id(), posting.ledger().clone())?);
+            }
+            Ok(Posted {
+                transaction_id: transaction.id().to_string(),
+                totals,
+            })
+        }
+
+        fn trial_balance(&self) -> Result<TrialBalance, StorageError> {
+            let lines = self
+                .balances
+                .iter()
+                .map(|(account_id, balance)| TrialBalanceLine {
+                    account_id: account_id.clone(),
+                    currency: balance.currency.clone(),
+                    amount: balance.amount(),
+                })
+                .collect();
+            Ok(TrialBalance::new(lines))
+        }
+
+        fn wallet(&self, account_id: &str) -> Result<Wallet, StorageError> {
+            Ok(Wallet::new(account_id, vec![self.get(account_id)?]))
+        }
+
+        // Both pockets can only be the one balance held.
+        fn convert<R: RateProvider>(
+            &mut self,
+            account_id: &str,
+            from: &str,
+            to: &str,
+            _: &str,
+            _: &Converter<R>,
+        ) -> Result<Conversion, StorageError> {
+            self.get_pocket(account_id, from)?;
+            self.get_pocket(account_id, to)?;
+            Err(StorageError::Fx(FxError::SameCurrency))
+        }
+    }
+
+    impl Recording {
+        fn balance_mut(&mut self, account_id: &str) -> Result<&mut Balance, StorageError> {
+            self.balances
+                .get_mut(account_id)
+                .ok_or(StorageError::AccountNotExists)
+        }
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_in_memory() {
+        let mut storage = InMemory::new();
+        storage
+            .insert("account_1", Balance::new("USD").unwrap())
+            .unwrap();
+
+        assert_eq!(deposit(&mut storage, "account_1", "100"), Ok(dec!(100)));
+        assert_eq!(withdraw(&mut storage, "account_1", "30.5"), Ok(dec!(69.5)));
+        assert_eq!(
+            withdraw(&mut storage, "account_1", "70"),
+            Err(StorageError::Balance(BalanceError::BalanceNotEnough))
+        );
+        assert_eq!(storage.get("account_1").unwrap().amount(), dec!(69.5));
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_keep_books_balanced() {
+        let mut storage = InMemory::new().with_double_entry();
+        storage
+            .insert("account_1", Balance::new("USD").unwrap())
+            .unwrap();
+
+        deposit(&mut storage, "account_1", "100").unwrap();
+        withdraw_with_key(&mut storage, "account_1", "40", "req-1").unwrap();
+        withdraw_with_key(&mut storage, "account_1", "40", "req-1").unwrap();
+        let cash = storage
+            .get(&InternalAccount::Cash.account_id("USD"))
+            .unwrap();
+        assert_eq!(cash.amount(), dec!(-60));
+        let trial = storage.trial_balance().unwrap();
+        assert!(trial.is_balanced());
+        assert_eq!(trial.lines().len(), 2);
+        assert_eq!(
+            storage.append(
+                "account_1",
+                Ledger::new(
+                    crate::ledger::Action::Deposit("1".to_string()),
+                    time_ordered()
+                )
+                .unwrap()
+            ),
+            Err(StorageError::Journal(JournalError::SingleSided))
+        );
+    }
+
+    #[test]
+    fn test_deposit_invalid_amount() {
+        let mut storage = InMemory::new();
+        assert_eq!(
+            deposit(&mut storage, "account_1", "1e3"),
+            Err(StorageError::Ledger(LedgerError::ExponentNotAllowed))
+        );
+    }
+
+    #[test]
+    fn test_deposit_with_test_double() {
+        let mut storage = Recording::default();
+        assert_eq!(
+            deposit(&mut storage, "account_1", "5"),
+            Err(StorageError::AccountNotExists)
+        );
+
+        storage
+            .insert("account_1", Balance::new("EUR").unwrap())
+            .unwrap();
+        assert_eq!(deposit(&mut storage, "account_1", "5"), Ok(dec!(5)));
+        // The cash account takes the other side.
+        let amounts: Vec<Decimal> = storage.appended.iter().map(Ledger::amount).collect();
+        assert_eq!(amounts, [dec!(5), dec!(-5)]);
+        assert_eq!(
+            storage.list().unwrap(),
+            ["account_1", &InternalAccount::Cash.account_id("EUR")]
+        );
+    }
+
+    #[test]
+    fn test_deposit_with_key_retry_credits_once() {
+        let mut storage = InMemory::new();
+        storage
+            .insert("account_1", Balance::new("USD").unwrap())
+            .unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(
+                deposit_with_key(&mut storage, "account_1", "100", "req-1"),
+                Ok(dec!(100))
+            );
+        }
+        assert_eq!(
+            withdraw_with_key(&mut storage, "account_1", "40", "req-2"),
+            Ok(dec!(60))
+        );
+        assert_eq!(
+            withdraw_with_key(&mut storage, "account_1", "40", "req-2"),
+            Ok(dec!(60))
+        );
+        assert_eq!(
+            withdraw_with_key(&mut storage, "account_1", "41", "req-2"),
+            Err(StorageError::Balance(BalanceError::IdempotencyConflict))
+        );
+
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.amount(), dec!(60));
+        assert_eq!(balance.ledgers().len(), 2);
+    }
+}
@@ -0,0 +1,362 @@
+use chrono::{Days, NaiveDate};
+use rust_decimal::Decimal;
+use std::fmt::Write;
+
+use crate::balance::Balance;
+use crate::ledger::ActionKind;
+
+#[derive(Debug, PartialEq)]
+pub enum StatementError {
+    InvalidPeriod,
+}
+
+/// One entry on a statement, with the balance right after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementLine {
+    pub date: NaiveDate,
+    pub id: String,
+    pub kind: ActionKind,
+    pub amount: Decimal,
+    pub balance: Decimal,
+}
+
+/// An account's activity over `from..=to`, by value date.
+///
+/// Entries are listed in value-date order, so a back-valued entry shows up
+/// on the day it counts from. Entries compacted away are only reflected in
+/// the opening balance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub account_id: String,
+    pub currency: String,
+    pub minor_units: u32,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub opening: Decimal,
+    pub lines: Vec<StatementLine>,
+    pub total_credits: Decimal,
+    /// Sum of the debits, as a magnitude.
+    pub total_debits: Decimal,
+    pub closing: Decimal,
+}
+
+impl Statement {
+    pub fn new(
+        account_id: &str,
+        balance: &Balance,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Statement, StatementError> {
+        if from > to {
+            return Err(StatementError::InvalidPeriod);
+        }
+        let ledgers = balance.ledgers();
+        let opening = match from.checked_sub_days(Days::new(1)) {
+            Some(day_before) => ledgers.balance_as_of(day_before),
+            None => ledgers.opening(),
+        };
+        let mut entries: Vec<_> = ledgers.entries_between(from, to).collect();
+        entries.sort_by_key(|ledger| ledger.value_date());
+
+        let mut running = opening;
+        let (mut total_credits, mut total_debits) = (Decimal::ZERO, Decimal::ZERO);
+        let lines = entries
+            .into_iter()
+            .map(|ledger| {
+                let amount = ledger.amount();
+                running += amount;
+                if amount.is_sign_negative() {
+                    total_debits -= amount;
+                } else {
+                    total_credits += amount;
+                }
+                StatementLine {
+                    date: ledger.value_date(),
+                    id: ledger.id().to_string(),
+                    kind: ledger.action(),
+                    amount,
+                    balance: running,
+                }
+            })
+            .collect();
+        Ok(Statement {
+            account_id: account_id.to_string(),
+            currency: balance.currency.clone(),
+            minor_units: balance.minor_units(),
+            from,
+            to,
+            opening,
+            lines,
+            total_credits,
+            total_debits,
+            closing: running,
+        })
+    }
+
+    // Formats `amount` with exactly the currency's minor units.
+    fn money(&self, amount: Decimal) -> String {
+        format!("{:.*}", self.minor_units as usize, amount)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let row = |out: &mut String, date: &str, description: &str, amount: &str, balance: &str| {
+            let _ = writeln!(
+                out,
+                "{date:<10}  {description:<16}{amount:>14}{balance:>14}"
+            );
+        };
+        let _ = writeln!(out, "Statement for {} ({})", self.account_id, self.currency);
+        let _ = writeln!(out, "Period: {} to {}", self.from, self.to);
+        out.push('\n');
+        row(&mut out, "Date", "Description", "Amount", "Balance");
+        row(
+            &mut out,
+            "",
+            "Opening balance",
+            "",
+            &self.money(self.opening),
+        );
+        for line in &self.lines {
+            row(
+                &mut out,
+                &line.date.to_string(),
+                line.kind.as_str(),
+                &self.money(line.amount),
+                &self.money(line.balance),
+            );
+        }
+        row(
+            &mut out,
+            "",
+            "Closing balance",
+            "",
+            &self.money(self.closing),
+        );
+        out.push('\n');
+        let _ = writeln!(out, "Total credits: {}", self.money(self.total_credits));
+        let _ = writeln!(out, "Total debits: {}", self.money(self.total_debits));
+        out
+    }
+
+    /// One row per entry between an opening and a closing balance row.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("date,id,type,amount,balance\n");
+        let _ = writeln!(
+            out,
+            "{},,Opening balance,,{}",
+            self.from,
+            self.money(self.opening)
+        );
+        for line in &self.lines {
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{}",
+                line.date,
+                line.id,
+                line.kind.as_str(),
+                self.money(line.amount),
+                self.money(line.balance)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{},,Closing balance,,{}",
+            self.to,
+            self.money(self.closing)
+        );
+        out
+    }
+
+    /// Amounts are strings so they keep their exact decimal digits.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\n");
+        let field = |out: &mut String, name: &str, value: &str| {
+            let _ = writeln!(out, "  \"{name}\": {},", json_string(value));
+        };
+        field(&mut out, "account_id", &self.account_id);
+        field(&mut out, "currency", &self.currency);
+        field(&mut out, "from", &self.from.to_string());
+        field(&mut out, "to", &self.to.to_string());
+        field(&mut out, "opening_balance", &self.money(self.opening));
+        out.push_str("  \"entries\": [");
+        for (i, line) in self.lines.iter().enumerate() {
+            let separator = if i == 0 { "\n" } else { ",\n" };
+            let _ = write!(
+                out,
+                "{separator}    {{\"date\": {}, \"id\": {}, \"type\": {}, \"amount\": {}, \"balance\": {}}}",
+                json_string(&line.date.to_string()),
+                json_string(&line.id),
+                json_string(line.kind.as_str()),
+                json_string(&self.money(line.amount)),
+                json_string(&self.money(line.balance)),
+            );
+        }
+        out.push_str(if self.lines.is_empty() {
+            "],\n"
+        } else {
+            "\n  ],\n"
+        });
+        field(&mut out, "total_credits", &self.money(self.total_credits));
+        field(&mut out, "total_debits", &self.money(self.total_debits));
+        let _ = writeln!(
+            out,
+            "  \"closing_balance\": {}",
+            json_string(&self.money(self.closing))
+        );
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::id::Sequence;
+    use crate::ledger::{Action, Ledger};
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use std::path::PathBuf;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    // Posts `action` booked at noon on `booked`, counting from `value`.
+    fn post(
+        balance: &mut Balance,
+        ids: &Sequence,
+        action: Action,
+        booked: NaiveDate,
+        value: NaiveDate,
+    ) {
+        let clock = FixedClock::new(Utc.from_utc_datetime(&booked.and_hms_opt(12, 0, 0).unwrap()));
+        let ledger = Ledger::new_with_clock(action, ids, &clock)
+            .unwrap()
+            .with_value_date(value);
+        balance.mutate(ledger).unwrap();
+    }
+
+    fn fixture() -> Balance {
+        let ids = Sequence::starting_at(1);
+        let mut balance = Balance::new("USD").unwrap();
+        let entries = [
+            (Action::Deposit("100".to_string()), date(2023, 12, 20), None),
+            (Action::Deposit("50.25".to_string()), date(2024, 1, 3), None),
+            (Action::Withdrawal("20".to_string()), date(2024, 1, 5), None),
+            (Action::Fee("1.5".to_string()), date(2024, 1, 10), None),
+            // Booked late but back-valued into the period.
+            (
+                Action::Interest("0.75".to_string()),
+                date(2024, 1, 20),
+                Some(date(2024, 1, 8)),
+            ),
+            (Action::Deposit("10".to_string()), date(2024, 2, 2), None),
+        ];
+        for (action, booked, value) in entries {
+            post(&mut balance, &ids, action, booked, value.unwrap_or(booked));
+        }
+        balance
+    }
+
+    // Compares `actual` with the golden file `name`, or rewrites the file
+    // when UPDATE_GOLDEN is set.
+    fn assert_golden(name: &str, actual: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/statements")
+            .join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, actual).unwrap();
+        }
+        let expected = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(actual, expected, "golden file {name} differs");
+    }
+
+    fn january() -> Statement {
+        Statement::new("account_1", &fixture(), date(2024, 1, 1), date(2024, 1, 31)).unwrap()
+    }
+
+    #[test]
+    fn test_statement_totals() {
+        let balance = fixture();
+        let statement = january();
+        assert_eq!(statement.opening, dec!(100));
+        assert_eq!(statement.total_credits, dec!(51));
+        assert_eq!(statement.total_debits, dec!(21.5));
+        assert_eq!(statement.closing, dec!(129.5));
+        assert_eq!(
+            statement.closing,
+            balance.ledgers().balance_as_of(date(2024, 1, 31))
+        );
+        let kinds: Vec<ActionKind> = statement.lines.iter().map(|line| line.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                ActionKind::Deposit,
+                ActionKind::Withdrawal,
+                ActionKind::Interest,
+                ActionKind::Fee
+            ]
+        );
+        assert_eq!(statement.lines[2].balance, dec!(131));
+    }
+
+    #[test]
+    fn test_statement_golden_text() {
+        assert_golden("january.txt", &january().to_text());
+    }
+
+    #[test]
+    fn test_statement_golden_csv() {
+        assert_golden("january.csv", &january().to_csv());
+    }
+
+    #[test]
+    fn test_statement_golden_json() {
+        assert_golden("january.json", &january().to_json());
+    }
+
+    #[test]
+    fn test_statement_without_entries() {
+        let statement = Statement::new(
+            "quiet \"one\"",
+            &fixture(),
+            date(2024, 3, 1),
+            date(2024, 3, 31),
+        )
+        .unwrap();
+        assert_eq!(statement.opening, dec!(139.5));
+        assert_eq!(statement.closing, dec!(139.5));
+        assert_golden("empty.json", &statement.to_json());
+        assert_eq!(
+            Statement::new("account_1", &fixture(), date(2024, 2, 1), date(2024, 1, 31)),
+            Err(StatementError::InvalidPeriod)
+        );
+    }
+
+    #[test]
+    fn test_json_string_escaping() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+}
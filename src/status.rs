@@ -0,0 +1,143 @@
+use chrono::{DateTime, SubsecRound, Utc};
+use std::fmt;
+
+use crate::clock::Clock;
+
+/// Where an account is in its lifecycle.
+///
+/// Frozen and dormant accounts accept credits but reject debits and new
+/// holds until they are reactivated. Closed accounts reject everything and
+/// can't be reopened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    Frozen,
+    Closed,
+    Dormant,
+}
+
+impl AccountStatus {
+    pub const ALL: [AccountStatus; 4] = [
+        AccountStatus::Active,
+        AccountStatus::Frozen,
+        AccountStatus::Closed,
+        AccountStatus::Dormant,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Closed => "closed",
+            AccountStatus::Dormant => "dormant",
+        }
+    }
+
+    pub(crate) fn restore(name: &str) -> Option<AccountStatus> {
+        AccountStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == name)
+    }
+
+    /// Whether an account may move from this status to `to`. Closing is
+    /// final, and a frozen account must be reactivated before it can go
+    /// dormant.
+    pub fn can_become(&self, to: AccountStatus) -> bool {
+        use AccountStatus::*;
+        matches!(
+            (self, to),
+            (Active, Frozen | Closed | Dormant)
+                | (Frozen, Active | Closed)
+                | (Dormant, Active | Frozen | Closed)
+        )
+    }
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One recorded status transition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusChange {
+    from: AccountStatus,
+    to: AccountStatus,
+    reason: String,
+    changed_at: DateTime<Utc>,
+}
+
+impl StatusChange {
+    pub(crate) fn new(
+        from: AccountStatus,
+        to: AccountStatus,
+        reason: &str,
+        clock: &dyn Clock,
+    ) -> StatusChange {
+        StatusChange {
+            from,
+            to,
+            reason: reason.to_string(),
+            changed_at: clock.now().trunc_subsecs(6),
+        }
+    }
+
+    pub(crate) fn restore(
+        from: AccountStatus,
+        to: AccountStatus,
+        reason: String,
+        changed_at: DateTime<Utc>,
+    ) -> StatusChange {
+        StatusChange {
+            from,
+            to,
+            reason,
+            changed_at,
+        }
+    }
+
+    pub fn from(&self) -> AccountStatus {
+        self.from
+    }
+
+    pub fn to(&self) -> AccountStatus {
+        self.to
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub fn changed_at(&self) -> DateTime<Utc> {
+        self.changed_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_transitions() {
+        use AccountStatus::*;
+        assert!(Active.can_become(Frozen));
+        assert!(Frozen.can_become(Active));
+        assert!(Dormant.can_become(Active));
+        assert!(Dormant.can_become(Closed));
+        assert!(!Frozen.can_become(Dormant));
+        assert!(!Active.can_become(Active));
+        for status in AccountStatus::ALL {
+            assert!(!Closed.can_become(status));
+        }
+    }
+
+    #[test]
+    fn test_status_restore_round_trip() {
+        for status in AccountStatus::ALL {
+            assert_eq!(AccountStatus::restore(status.as_str()), Some(status));
+        }
+        assert_eq!(AccountStatus::restore("suspended"), None);
+    }
+}
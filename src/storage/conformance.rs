@@ -0,0 +1,1035 @@
+//! Behaviour every `Storage` backend must share. Backends run the suite with
+//! `storage_conformance!(<expr returning impl Storage>)`, optionally followed
+//! by attributes for every generated test, e.g. `#[ignore = "..."]` when an
+//! external database is needed.
+
+use chrono::{Duration, NaiveDate, Utc};
+use rust_decimal::RoundingStrategy;
+use rust_decimal_macros::dec;
+
+use super::test_util::generate_random_string;
+use super::{pocket_key, Storage, StorageError};
+use crate::balance::{Balance, BalanceError};
+use crate::clock::{Clock, FixedClock, SystemClock};
+use crate::currency::{self, Currency, Rounding};
+use crate::fx::{Converter, FxError, StaticRates};
+use crate::hold::Hold;
+use crate::id::{time_ordered, Sequence};
+use crate::journal::{InternalAccount, Posting, Transaction};
+use crate::ledger::{Action, ActionKind, Ledger, LedgerError};
+use crate::limits::{Limit, VelocityLimit, VelocityLimits};
+use crate::policy::Policy;
+use crate::status::AccountStatus;
+
+macro_rules! storage_conformance {
+    ($make:expr $(, #[$attr:meta])*) => {
+        mod conformance {
+            use super::*;
+            use $crate::storage::conformance as suite;
+
+            #[test]
+            $(#[$attr])*
+            fn insert_and_get() {
+                suite::insert_and_get($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn insert_existing_account() {
+                suite::insert_existing_account($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn get_not_exist_account() {
+                suite::get_not_exist_account($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn update() {
+                suite::update($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn list_and_delete() {
+                suite::list_and_delete($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn append() {
+                suite::append($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn append_reversal() {
+                suite::append_reversal($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn old_entries() {
+                suite::old_entries($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn holds() {
+                suite::holds($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn policy() {
+                suite::policy($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn currency() {
+                suite::currency($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn velocity_limits() {
+                suite::velocity_limits($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn status() {
+                suite::status($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn pockets() {
+                suite::pockets($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn pocket_controls() {
+                suite::pocket_controls($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn append_idempotent() {
+                suite::append_idempotent($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn post() {
+                suite::post($make);
+            }
+
+            #[test]
+            $(#[$attr])*
+            fn convert() {
+                suite::convert($make);
+            }
+        }
+    };
+}
+pub(crate) use storage_conformance;
+
+fn account_id() -> String {
+    format!("account_{}", generate_random_string(12))
+}
+
+fn ledger(action: Action) -> Ledger {
+    Ledger::new(action, time_ordered()).unwrap()
+}
+
+pub(crate) fn insert_and_get<S: Storage>(mut storage: S) {
+    let id = account_id();
+    let mut balance = Balance::new("USD").unwrap();
+    balance
+        .mutate(ledger(Action::Deposit("12.34".to_string())))
+        .unwrap();
+    balance
+        .mutate(
+            ledger(Action::Withdrawal("0.34".to_string()))
+                .with_value_date(NaiveDate::from_ymd_opt(2023, 12, 31).unwrap()),
+        )
+        .unwrap();
+    for action in [
+        Action::TransferIn("3".to_string()),
+        Action::TransferOut("1".to_string()),
+        Action::Fee("0.5".to_string()),
+        Action::Interest("0.25".to_string()),
+        Action::Adjustment("-0.75".to_string()),
+        Action::Reversal("1".to_string()),
+    ] {
+        balance.mutate(ledger(action)).unwrap();
+    }
+    storage.insert(&id, balance.clone()).unwrap();
+
+    let stored = storage.get(&id).unwrap();
+    assert_eq!(stored, balance);
+    assert_eq!(stored.amount(), dec!(14));
+}
+
+pub(crate) fn insert_existing_account<S: Storage>(mut storage: S) {
+    let id = account_id();
+    let balance = Balance::new("USD").unwrap();
+    storage.insert(&id, balance.clone()).unwrap();
+    assert_eq!(
+        storage.insert(&id, balance),
+        Err(StorageError::AccountAlreadyExists)
+    );
+}
+
+pub(crate) fn get_not_exist_account<S: Storage>(storage: S) {
+    assert_eq!(
+        storage.get(&account_id()),
+        Err(StorageError::AccountNotExists)
+    );
+}
+
+pub(crate) fn update<S: Storage>(mut storage: S) {
+    let id = account_id();
+    let balance = Balance::new("USD").unwrap();
+    assert_eq!(
+        storage.update(&id, balance.clone()),
+        Err(StorageError::AccountNotExists)
+    );
+    storage.insert(&id, balance).unwrap();
+
+    let mut balance = storage.get(&id).unwrap();
+    balance
+        .mutate(ledger(Action::Deposit("100".to_string())))
+        .unwrap();
+    storage.update(&id, balance.clone()).unwrap();
+    assert_eq!(storage.get(&id), Ok(balance));
+}
+
+pub(crate) fn list_and_delete<S: Storage>(mut storage: S) {
+    let first = account_id();
+    let second = account_id();
+    storage
+        .insert(&first, Balance::new("EUR").unwrap())
+        .unwrap();
+    storage
+        .insert(&second, Balance::new("USD").unwrap())
+        .unwrap();
+
+    let ids = storage.list().unwrap();
+    assert!(ids.contains(&first) && ids.contains(&second));
+    assert!(ids.windows(2).all(|w| w[0] <= w[1]));
+
+    assert_eq!(storage.delete(&first).unwrap().currency, "EUR");
+    assert!(!storage.list().unwrap().contains(&first));
+    assert_eq!(storage.delete(&first), Err(StorageError::AccountNotExists));
+}
+
+pub(crate) fn append<S: Storage>(mut storage: S) {
+    let id = account_id();
+    storage.insert(&id, Balance::new("USD").unwrap()).unwrap();
+
+    let deposit = ledger(Action::Deposit("10".to_string()));
+    assert_eq!(storage.append(&id, deposit), Ok(dec!(10)));
+    let withdrawal = ledger(Action::Withdrawal("10.01".to_string()));
+    assert_eq!(
+        storage.append(&id, withdrawal),
+        Err(StorageError::Balance(BalanceError::BalanceNotEnough))
+    );
+    assert_eq!(storage.get(&id).unwrap().ledgers().len(), 1);
+
+    // A sequence restarted from zero reuses the stored id.
+    let deposit = || Ledger::new(Action::Deposit("5".to_string()), &Sequence::new()).unwrap();
+    assert_eq!(storage.append(&id, deposit()), Ok(dec!(15)));
+    assert_eq!(
+        storage.append(&id, deposit()),
+        Err(StorageError::Balance(BalanceError::Ledger(
+            LedgerError::DuplicateLedger
+        )))
+    );
+    let ids = Sequence::after(storage.get(&id).unwrap().ledgers());
+    let deposit = Ledger::new(Action::Deposit("5".to_string()), &ids).unwrap();
+    assert_eq!(storage.append(&id, deposit), Ok(dec!(20)));
+
+    let deposit = ledger(Action::Deposit("1".to_string()));
+    assert_eq!(
+        storage.append(&account_id(), deposit),
+        Err(StorageError::AccountNotExists)
+    );
+}
+
+pub(crate) fn append_idempotent<S: Storage>(mut storage: S) {
+    let id = account_id();
+    storage.insert(&id, Balance::new("USD").unwrap()).unwrap();
+    let keyed = |action: Action| ledger(action).with_idempotency_key("req-1".to_string());
+
+    assert_eq!(
+        storage.append(&id, keyed(Action::Deposit("10".to_string()))),
+        Ok(dec!(10))
+    );
+    storage
+        .append(&id, ledger(Action::Deposit("5".to_string())))
+        .unwrap();
+    assert_eq!(
+        storage.append(&id, keyed(Action::Deposit("10".to_string()))),
+        Ok(dec!(10))
+    );
+    assert_eq!(
+        storage.append(&id, keyed(Action::Withdrawal("10".to_string()))),
+        Err(StorageError::Balance(BalanceError::IdempotencyConflict))
+    );
+
+    let stored = storage.get(&id).unwrap();
+    assert_eq!(stored.amount(), dec!(15));
+    assert_eq!(stored.ledgers().len(), 2);
+    assert_eq!(
+        stored.ledgers().entries()[0].idempotency_key(),
+        Some("req-1")
+    );
+}
+
+pub(crate) fn append_reversal<S: Storage>(mut storage: S) {
+    let id = account_id();
+    storage.insert(&id, Balance::new("USD").unwrap()).unwrap();
+    let deposit = ledger(Action::Deposit("10".to_string()));
+    let deposit_id = deposit.id().to_string();
+    storage.append(&id, deposit).unwrap();
+
+    // Both refunds are built from the same snapshot; the store must still
+    // refuse the one that would over-refund.
+    let balance = storage.get(&id).unwrap();
+    let first = balance
+        .reversal_of(&deposit_id, Some("6"), time_ordered(), &SystemClock)
+        .unwrap();
+    let second = balance
+        .reversal_of(&deposit_id, Some("6"), time_ordered(), &SystemClock)
+        .unwrap();
+    assert_eq!(storage.append(&id, first), Ok(dec!(4)));
+    assert_eq!(
+        storage.append(&id, second),
+        Err(StorageError::Balance(BalanceError::RefundExceedsOriginal {
+            remaining: dec!(4)
+        }))
+    );
+
+    let stored = storage.get(&id).unwrap();
+    assert_eq!(stored.ledgers().reversed_amount(&deposit_id), dec!(6));
+    assert_eq!(
+        stored.ledgers().entries()[1].reverses(),
+        Some(deposit_id.as_str())
+    );
+}
+
+// Entries booked long before a write still govern it, even where backends
+// only read back the recent ones.
+pub(crate) fn old_entries<S: Storage>(mut storage: S) {
+    let id = account_id();
+    let limits = VelocityLimits {
+        daily: Limit {
+            max_amount: None,
+            max_count: Some(1),
+        },
+        monthly: Limit::default(),
+    };
+    let balance = Balance::new("USD").unwrap().with_limits(limits);
+    storage.insert(&id, balance).unwrap();
+    let old = FixedClock::new(Utc::now() - Duration::days(90));
+    let booked = |action: Action, clock: &dyn Clock| {
+        Ledger::new_with_clock(action, time_ordered(), clock).unwrap()
+    };
+    let deposit =
+        booked(Action::Deposit("100".to_string()), &old).with_idempotency_key("req-1".to_string());
+    storage.append(&id, deposit).unwrap();
+    let purchase = booked(Action::Withdrawal("30".to_string()), &old);
+    let purchase_id = purchase.id().to_string();
+    storage.append(&id, purchase.clone()).unwrap();
+    let refund = storage
+        .get(&id)
+        .unwrap()
+        .reversal_of(&purchase_id, Some("10"), time_ordered(), &old)
+        .unwrap();
+    assert_eq!(storage.append(&id, refund), Ok(dec!(80)));
+
+    let keyed =
+        |action: Action| booked(action, &SystemClock).with_idempotency_key("req-1".to_string());
+    assert_eq!(
+        storage.append(&id, keyed(Action::Deposit("100".to_string()))),
+        Ok(dec!(100))
+    );
+    assert_eq!(
+        storage.append(&id, keyed(Action::Deposit("5".to_string()))),
+        Err(StorageError::Balance(BalanceError::IdempotencyConflict))
+    );
+    assert_eq!(
+        storage.append(&id, purchase),
+        Err(StorageError::Balance(BalanceError::Ledger(
+            LedgerError::DuplicateLedger
+        )))
+    );
+
+    let balance = storage.get(&id).unwrap();
+    let refund = |amount: &str| {
+        balance
+            .reversal_of(&purchase_id, Some(amount), time_ordered(), &SystemClock)
+            .unwrap()
+    };
+    assert_eq!(
+        storage.append(&id, refund("20.01")),
+        Err(StorageError::Balance(BalanceError::RefundExceedsOriginal {
+            remaining: dec!(20)
+        }))
+    );
+    assert_eq!(storage.append(&id, refund("20")), Ok(dec!(100)));
+    assert_eq!(
+        storage.append(&id, refund("1")),
+        Err(StorageError::Balance(BalanceError::AlreadyReversed))
+    );
+
+    // Only the recent outflows count towards the limits.
+    let withdrawal = || booked(Action::Withdrawal("1".to_string()), &SystemClock);
+    assert_eq!(storage.append(&id, withdrawal()), Ok(dec!(99)));
+    assert_eq!(
+        storage.append(&id, withdrawal()),
+        Err(StorageError::Balance(BalanceError::VelocityLimitExceeded(
+            VelocityLimit::DailyCount
+        )))
+    );
+
+    let stored = storage.get(&id).unwrap();
+    assert_eq!(stored.amount(), dec!(99));
+    assert_eq!(stored.ledgers().len(), 5);
+    assert_eq!(stored.ledgers().reversed_amount(&purchase_id), dec!(30));
+}
+
+pub(crate) fn holds<S: Storage>(mut storage: S) {
+    let id = account_id();
+    let mut balance = Balance::new("USD").unwrap();
+    balance
+        .mutate(ledger(Action::Deposit("100".to_string())))
+        .unwrap();
+    let expiring = Hold::new("5", time_ordered())
+        .unwrap()
+        .with_expiry(Utc::now() + Duration::days(30));
+    balance.place_hold(expiring).unwrap();
+    storage.insert(&id, balance.clone()).unwrap();
+    assert_eq!(storage.get(&id).unwrap(), balance);
+
+    let card = Hold::new("70", time_ordered()).unwrap();
+    let card_id = card.id().to_string();
+    assert_eq!(storage.place_hold(&id, card), Ok(dec!(25)));
+    assert_eq!(
+        storage.append(&id, ledger(Action::Withdrawal("30".to_string()))),
+        Err(StorageError::Balance(BalanceError::BalanceNotEnough))
+    );
+    let stored = storage.get(&id).unwrap();
+    assert_eq!(stored.holds().len(), 2);
+    assert_eq!(stored.available(), dec!(25));
+
+    assert_eq!(
+        storage.capture_hold(&id, &card_id, Some("65")),
+        Ok(dec!(35))
+    );
+    assert_eq!(
+        storage.capture_hold(&id, &card_id, None),
+        Err(StorageError::Balance(BalanceError::HoldNotFound))
+    );
+    let stored = storage.get(&id).unwrap();
+    assert_eq!(stored.ledgers().len(), 2);
+    assert_eq!(stored.available(), dec!(30));
+
+    let released = storage.release_hold(&id, stored.holds()[0].id()).unwrap();
+    assert_eq!(released.amount(), dec!(5));
+    assert!(storage.get(&id).unwrap().holds().is_empty());
+    assert_eq!(
+        storage.place_hold(&account_id(), Hold::new("1", time_ordered()).unwrap()),
+        Err(StorageError::AccountNotExists)
+    );
+}
+
+pub(crate) fn policy<S: Storage>(mut storage: S) {
+    let id = account_id();
+    let balance = Balance::new("USD")
+        .unwrap()
+        .with_policy(Policy::overdraft("50.25").unwrap());
+    storage.insert(&id, balance.clone()).unwrap();
+    assert_eq!(storage.get(&id), Ok(balance));
+
+    assert_eq!(
+        storage.append(&id, ledger(Action::Withdrawal("50".to_string()))),
+        Ok(dec!(-50))
+    );
+    assert_eq!(
+        storage.append(&id, ledger(Action::Withdrawal("1".to_string()))),
+        Err(StorageError::Balance(
+            BalanceError::OverdraftLimitExceeded {
+                headroom: dec!(0.25)
+            }
+        ))
+    );
+
+    let balance = storage
+        .get(&id)
+        .unwrap()
+        .with_policy(Policy::minimum_balance("10").unwrap());
+    storage.update(&id, balance.clone()).unwrap();
+    assert_eq!(storage.get(&id), Ok(balance));
+    storage
+        .append(&id, ledger(Action::Deposit("70".to_string())))
+        .unwrap();
+    assert_eq!(
+        storage.append(&id, ledger(Action::Withdrawal("10.5".to_string()))),
+        Err(StorageError::Balance(BalanceError::BelowMinimumBalance {
+            headroom: dec!(10)
+        }))
+    );
+}
+
+pub(crate) fn currency<S: Storage>(mut storage: S) {
+    // Registered once per process; every backend's run shares it.
+    let _ = currency::register(Currency::new("MLS", 4, "mls").unwrap());
+    let id = account_id();
+    let balance = Balance::new("MLS")
+        .unwrap()
+        .with_rounding(Rounding::Round(RoundingStrategy::ToZero));
+    storage.insert(&id, balance.clone()).unwrap();
+    assert_eq!(storage.get(&id), Ok(balance));
+
+    assert_eq!(
+        storage.append(&id, ledger(Action::Deposit("1.23456".to_string()))),
+        Ok(dec!(1.2345))
+    );
+    let stored = storage.get(&id).unwrap();
+    assert_eq!(stored.minor_units(), 4);
+    assert_eq!(stored.rounding(), Rounding::Round(RoundingStrategy::ToZero));
+
+    storage
+        .update(&id, stored.with_rounding(Rounding::Reject))
+        .unwrap();
+    assert_eq!(
+        storage.append(&id, ledger(Action::Deposit("0.00001".to_string()))),
+        Err(StorageError::Balance(BalanceError::ExcessPrecision {
+            scale: 5,
+            minor_units: 4
+        }))
+    );
+}
+
+pub(crate) fn velocity_limits<S: Storage>(mut storage: S) {
+    let id = account_id();
+    let limits = VelocityLimits {
+        daily: Limit {
+            max_amount: Some(dec!(75.5)),
+            max_count: None,
+        },
+        monthly: Limit {
+            max_amount: None,
+            max_count: Some(2),
+        },
+    };
+    let mut balance = Balance::new("USD").unwrap().with_limits(limits);
+    balance
+        .mutate(ledger(Action::Deposit("100".to_string())))
+        .unwrap();
+    storage.insert(&id, balance.clone()).unwrap();
+    assert_eq!(storage.get(&id), Ok(balance));
+
+    storage
+        .append(&id, ledger(Action::Withdrawal("75".to_string())))
+        .unwrap();
+    assert_eq!(
+        storage.append(&id, ledger(Action::Withdrawal("0.51".to_string()))),
+        Err(StorageError::Balance(BalanceError::VelocityLimitExceeded(
+            VelocityLimit::DailyAmount
+        )))
+    );
+
+    let balance = storage.get(&id).unwrap().with_limits(VelocityLimits {
+        daily: Limit::default(),
+        ..limits
+    });
+    storage.update(&id, balance.clone()).unwrap();
+    assert_eq!(storage.get(&id), Ok(balance));
+    storage
+        .append(&id, ledger(Action::Withdrawal("1".to_string())))
+        .unwrap();
+    assert_eq!(
+        storage.append(&id, ledger(Action::Withdrawal("1".to_string()))),
+        Err(StorageError::Balance(BalanceError::VelocityLimitExceeded(
+            VelocityLimit::MonthlyCount
+        )))
+    );
+}
+
+pub(crate) fn status<S: Storage>(mut storage: S) {
+    let id = account_id();
+    let mut balance = Balance::new("USD").unwrap();
+    balance
+        .mutate(ledger(Action::Deposit("30".to_string())))
+        .unwrap();
+    balance
+        .transition(AccountStatus::Dormant, "no activity")
+        .unwrap();
+    storage.insert(&id, balance.clone()).unwrap();
+    assert_eq!(storage.get(&id), Ok(balance));
+
+    let change = storage
+        .set_status(&id, AccountStatus::Frozen, "chargeback dispute")
+        .unwrap();
+    assert_eq!(change.from(), AccountStatus::Dormant);
+    assert_eq!(
+        storage.append(&id, ledger(Action::Withdrawal("1".to_string()))),
+        Err(StorageError::Balance(BalanceError::AccountFrozen))
+    );
+    assert_eq!(
+        storage.append(&id, ledger(Action::Deposit("1".to_string()))),
+        Ok(dec!(31))
+    );
+    assert_eq!(
+        storage.set_status(&id, AccountStatus::Closed, "customer request"),
+        Err(StorageError::Balance(BalanceError::NonZeroBalance))
+    );
+
+    // Updates may add transitions, but not drop or skip them.
+    assert_eq!(
+        storage.update(&id, Balance::new("USD").unwrap()),
+        Err(StorageError::Balance(BalanceError::StatusHistoryRewritten))
+    );
+    let mut resolved = storage.get(&id).unwrap();
+    resolved
+        .transition(AccountStatus::Active, "dispute resolved")
+        .unwrap();
+    storage.update(&id, resolved).unwrap();
+    storage
+        .append(&id, ledger(Action::Withdrawal("31".to_string())))
+        .unwrap();
+    storage
+        .set_status(&id, AccountStatus::Closed, "customer request")
+        .unwrap();
+    assert_eq!(
+        storage.append(&id, ledger(Action::Deposit("1".to_string()))),
+        Err(StorageError::Balance(BalanceError::AccountClosed))
+    );
+
+    let stored = storage.get(&id).unwrap();
+    assert_eq!(stored.status(), AccountStatus::Closed);
+    let reasons: Vec<&str> = stored
+        .status_history()
+        .iter()
+        .map(|change| change.reason())
+        .collect();
+    assert_eq!(
+        reasons,
+        [
+            "no activity",
+            "chargeback dispute",
+            "dispute resolved",
+            "customer request"
+        ]
+    );
+    assert_eq!(stored.status_history()[1], change);
+
+    // A closed account can't be rewritten, not even unchanged.
+    assert_eq!(
+        storage.update(&id, stored.clone()),
+        Err(StorageError::Balance(BalanceError::AccountClosed))
+    );
+    assert_eq!(storage.get(&id), Ok(stored));
+    assert_eq!(
+        storage.set_status(&account_id(), AccountStatus::Frozen, "x"),
+        Err(StorageError::AccountNotExists)
+    );
+}
+
+pub(crate) fn pockets<S: Storage>(mut storage: S) {
+    let id = account_id();
+    storage.insert(&id, Balance::new("USD").unwrap()).unwrap();
+    storage.open_pocket(&id, "eur").unwrap();
+    storage.open_pocket(&id, "JPY").unwrap();
+    assert_eq!(
+        storage.open_pocket(&id, "EUR"),
+        Err(StorageError::PocketAlreadyExists)
+    );
+    assert_eq!(
+        storage.open_pocket(&id, "USD"),
+        Err(StorageError::PocketAlreadyExists)
+    );
+    assert!(matches!(
+        storage.open_pocket(&id, "XXX"),
+        Err(StorageError::Balance(_))
+    ));
+    assert_eq!(
+        storage.open_pocket(&account_id(), "EUR"),
+        Err(StorageError::AccountNotExists)
+    );
+    assert_eq!(storage.pockets(&id).unwrap(), ["EUR", "JPY", "USD"]);
+
+    storage
+        .append_to_pocket(&id, "usd", ledger(Action::Deposit("10".to_string())))
+        .unwrap();
+    assert_eq!(
+        storage.append_to_pocket(&id, "EUR", ledger(Action::Deposit("20.5".to_string()))),
+        Ok(dec!(20.5))
+    );
+    assert_eq!(
+        storage.append_to_pocket(&id, "JPY", ledger(Action::Deposit("1.5".to_string()))),
+        Err(StorageError::Balance(BalanceError::ExcessPrecision {
+            scale: 1,
+            minor_units: 0
+        }))
+    );
+    assert_eq!(
+        storage.append_to_pocket(&id, "GBP", ledger(Action::Deposit("1".to_string()))),
+        Err(StorageError::PocketNotExists)
+    );
+    // Each pocket keeps its own balance.
+    assert_eq!(
+        storage.append_to_pocket(&id, "EUR", ledger(Action::Withdrawal("21".to_string()))),
+        Err(StorageError::Balance(BalanceError::BalanceNotEnough))
+    );
+    assert_eq!(storage.get(&id).unwrap().amount(), dec!(10));
+    let eur = storage.get_pocket(&id, "eur").unwrap();
+    assert_eq!((eur.currency.as_str(), eur.amount()), ("EUR", dec!(20.5)));
+    assert_eq!(
+        storage.get_pocket(&id, "GBP"),
+        Err(StorageError::PocketNotExists)
+    );
+
+    let wallet = storage.wallet(&id).unwrap();
+    assert_eq!(wallet.account_id(), id);
+    assert_eq!(
+        wallet.currencies().collect::<Vec<_>>(),
+        ["EUR", "JPY", "USD"]
+    );
+    assert_eq!(wallet.pocket("EUR"), Some(&eur));
+    let rate = |from: &str, to: &str| match (from, to) {
+        ("EUR", "USD") => Some(dec!(1.1)),
+        ("JPY", "USD") => Some(dec!(0.0067)),
+        _ => None,
+    };
+    assert_eq!(wallet.value_in("USD", &rate), Ok(dec!(32.55)));
+
+    // Pockets aren't accounts of their own, and go with their account.
+    let pocket_id = pocket_key(&id, "EUR");
+    let listed = storage.list().unwrap();
+    assert!(listed.contains(&id));
+    assert!(!listed.contains(&pocket_id));
+    assert_eq!(
+        storage.pockets(&pocket_id),
+        Err(StorageError::AccountNotExists)
+    );
+    let not_exists = StorageError::AccountNotExists;
+    assert_eq!(storage.get(&pocket_id).unwrap_err(), not_exists);
+    let deposit = ledger(Action::Deposit("1".to_string()));
+    assert_eq!(storage.append(&pocket_id, deposit).unwrap_err(), not_exists);
+    let hold = Hold::new("1", time_ordered()).unwrap();
+    assert_eq!(
+        storage.place_hold(&pocket_id, hold).unwrap_err(),
+        not_exists
+    );
+    assert_eq!(
+        storage
+            .set_status(&pocket_id, AccountStatus::Frozen, "review")
+            .unwrap_err(),
+        not_exists
+    );
+    let fresh = Balance::new("EUR").unwrap();
+    assert_eq!(
+        storage.update(&pocket_id, fresh.clone()).unwrap_err(),
+        not_exists
+    );
+    assert_eq!(storage.delete(&pocket_id).unwrap_err(), not_exists);
+    assert_eq!(storage.get_pocket(&id, "EUR"), Ok(eur.clone()));
+    // Nor can an account take a pocket's key or balance.
+    assert_eq!(
+        storage.insert(&pocket_key(&id, "GBP"), fresh),
+        Err(StorageError::InvalidAccountId)
+    );
+    assert_eq!(
+        storage.insert(&account_id(), eur.clone()),
+        Err(StorageError::PocketBalance)
+    );
+    assert_eq!(storage.update(&id, eur), Err(StorageError::PocketBalance));
+    storage.delete(&id).unwrap();
+    assert_eq!(storage.get(&pocket_id), Err(StorageError::AccountNotExists));
+    assert_eq!(storage.wallet(&id), Err(StorageError::AccountNotExists));
+}
+
+pub(crate) fn pocket_controls<S: Storage>(mut storage: S) {
+    let rates = StaticRates::new().with_rate("EUR", "USD", "1.1").unwrap();
+    let converter = Converter::new(rates);
+    let id = account_id();
+    let limits = VelocityLimits {
+        daily: Limit {
+            max_amount: None,
+            max_count: Some(2),
+        },
+        monthly: Limit::default(),
+    };
+    let balance = Balance::new("USD")
+        .unwrap()
+        .with_policy(Policy::overdraft("5").unwrap())
+        .with_limits(limits);
+    storage.insert(&id, balance).unwrap();
+    storage.open_pocket(&id, "EUR").unwrap();
+    storage
+        .append_to_pocket(&id, "EUR", ledger(Action::Deposit("50".to_string())))
+        .unwrap();
+
+    // The account's policy applies to its pockets' balances.
+    assert_eq!(
+        storage.append_to_pocket(&id, "EUR", ledger(Action::Withdrawal("56".to_string()))),
+        Err(StorageError::Balance(
+            BalanceError::OverdraftLimitExceeded { headroom: dec!(55) }
+        ))
+    );
+
+    // So does its status, however the pocket is reached.
+    storage
+        .set_status(&id, AccountStatus::Frozen, "fraud review")
+        .unwrap();
+    let frozen = StorageError::Balance(BalanceError::AccountFrozen);
+    assert_eq!(
+        storage
+            .append_to_pocket(&id, "EUR", ledger(Action::Withdrawal("20".to_string())))
+            .unwrap_err(),
+        frozen
+    );
+    assert_eq!(
+        storage.append_to_pocket(&id, "EUR", ledger(Action::Deposit("1".to_string()))),
+        Ok(dec!(51))
+    );
+    assert_eq!(
+        storage
+            .convert(&id, "EUR", "USD", "10", &converter)
+            .unwrap_err(),
+        frozen
+    );
+    assert_eq!(storage.open_pocket(&id, "GBP").unwrap_err(), frozen);
+    assert_eq!(storage.pockets(&id).unwrap(), ["EUR", "USD"]);
+    storage
+        .set_status(&id, AccountStatus::Active, "review cleared")
+        .unwrap();
+
+    // An account can't be closed while a pocket holds funds.
+    let non_zero = StorageError::Balance(BalanceError::NonZeroBalance);
+    assert_eq!(
+        storage
+            .set_status(&id, AccountStatus::Closed, "customer request")
+            .unwrap_err(),
+        non_zero
+    );
+    let mut closed = storage.get(&id).unwrap();
+    closed
+        .transition(AccountStatus::Closed, "customer request")
+        .unwrap();
+    assert_eq!(storage.update(&id, closed).unwrap_err(), non_zero);
+    assert_eq!(storage.get(&id).unwrap().status(), AccountStatus::Active);
+
+    // And its velocity limits count the pocket's outflows.
+    storage
+        .append_to_pocket(&id, "EUR", ledger(Action::Withdrawal("40".to_string())))
+        .unwrap();
+    storage
+        .append_to_pocket(&id, "EUR", ledger(Action::Withdrawal("11".to_string())))
+        .unwrap();
+    assert_eq!(
+        storage.append_to_pocket(&id, "EUR", ledger(Action::Withdrawal("1".to_string()))),
+        Err(StorageError::Balance(BalanceError::VelocityLimitExceeded(
+            VelocityLimit::DailyCount
+        )))
+    );
+
+    storage
+        .set_status(&id, AccountStatus::Closed, "customer request")
+        .unwrap();
+    let closed = StorageError::Balance(BalanceError::AccountClosed);
+    assert_eq!(
+        storage
+            .append_to_pocket(&id, "EUR", ledger(Action::Deposit("1".to_string())))
+            .unwrap_err(),
+        closed
+    );
+    assert_eq!(
+        storage
+            .convert(&id, "USD", "EUR", "1", &converter)
+            .unwrap_err(),
+        closed
+    );
+    assert_eq!(storage.open_pocket(&id, "GBP").unwrap_err(), closed);
+    assert_eq!(storage.get_pocket(&id, "EUR").unwrap().amount(), dec!(0));
+}
+
+pub(crate) fn post<S: Storage>(mut storage: S) {
+    let id = account_id();
+    storage.insert(&id, Balance::new("USD").unwrap()).unwrap();
+    // Other tests may share the cash account.
+    let cash = InternalAccount::Cash.open(&mut storage, "USD").unwrap();
+    let cash_before = storage.get(&cash).unwrap().amount();
+
+    let deposit = Transaction::deposit(&id, "usd", "100").unwrap();
+    let deposit_id = deposit.id().to_string();
+    let posted = storage.post(deposit).unwrap();
+    assert_eq!(posted.transaction_id, deposit_id);
+    assert_eq!(posted.totals[0], dec!(100));
+    let account = storage.get(&id).unwrap();
+    assert_eq!(
+        account.ledgers().entries()[0].transfer_id(),
+        Some(deposit_id.as_str())
+    );
+
+    // A retry under the same idempotency key posts nothing.
+    let withdrawal = |amount| {
+        Transaction::withdrawal(&id, "USD", amount)
+            .unwrap()
+            .with_idempotency_key("req-1")
+    };
+    assert_eq!(storage.post(withdrawal("40")).unwrap().totals[0], dec!(60));
+    assert_eq!(storage.post(withdrawal("40")).unwrap().totals[0], dec!(60));
+    assert_eq!(
+        storage.post(withdrawal("41")),
+        Err(StorageError::Balance(BalanceError::IdempotencyConflict))
+    );
+
+    // A rejected posting undoes the ones before it.
+    let overdraw = Transaction::new(vec![
+        Posting::new(&id, "USD", Action::Withdrawal("50".to_string())).unwrap(),
+        Posting::new(&id, "USD", Action::Withdrawal("50".to_string())).unwrap(),
+        Posting::credit(&cash, "USD", "100").unwrap(),
+    ])
+    .unwrap();
+    assert_eq!(
+        storage.post(overdraw),
+        Err(StorageError::Balance(BalanceError::BalanceNotEnough))
+    );
+    assert_eq!(
+        storage.post(Transaction::deposit(&id, "EUR", "1").unwrap()),
+        Err(StorageError::PocketNotExists)
+    );
+    let account = storage.get(&id).unwrap();
+    assert_eq!((account.amount(), account.ledgers().len()), (dec!(60), 2));
+    assert_eq!(
+        storage.get(&cash).unwrap().amount() - cash_before,
+        dec!(-60)
+    );
+
+    let trial = storage.trial_balance().unwrap();
+    let line = trial
+        .lines()
+        .iter()
+        .find(|line| line.account_id == id)
+        .unwrap();
+    assert_eq!((line.currency.as_str(), line.amount), ("USD", dec!(60)));
+}
+
+pub(crate) fn convert<S: Storage>(mut storage: S) {
+    let rates = StaticRates::new()
+        .with_rate("EUR", "USD", "1.0850")
+        .unwrap();
+    let converter = Converter::new(rates).with_markup("0.005").unwrap();
+    let id = account_id();
+    storage.insert(&id, Balance::new("EUR").unwrap()).unwrap();
+    storage.open_pocket(&id, "USD").unwrap();
+    storage.open_pocket(&id, "GBP").unwrap();
+    storage
+        .append(&id, ledger(Action::Deposit("100".to_string())))
+        .unwrap();
+    // Other tests may share the internal accounts.
+    let internal = |storage: &S, account: InternalAccount, currency| {
+        storage
+            .get(&account.account_id(currency))
+            .map_or(dec!(0), |balance| balance.amount())
+    };
+    let residue_id = InternalAccount::FxResidue.account_id("USD");
+    let residue_before = internal(&storage, InternalAccount::FxResidue, "USD");
+    let eur_position = internal(&storage, InternalAccount::FxPosition, "EUR");
+    let usd_position = internal(&storage, InternalAccount::FxPosition, "USD");
+
+    let conversion = storage
+        .convert(&id, "eur", "USD", "33.33", &converter)
+        .unwrap();
+    let quote = &conversion.quote;
+    assert_eq!((quote.from.as_str(), quote.to.as_str()), ("EUR", "USD"));
+    assert_eq!(quote.rate, dec!(1.0795750));
+    // 33.33 * 1.079575 = 35.98223475
+    assert_eq!(quote.target_amount, dec!(35.98));
+    assert_eq!(quote.residue, dec!(0.00223475));
+    let eur = storage.get_pocket(&id, "EUR").unwrap();
+    let usd = storage.get_pocket(&id, "USD").unwrap();
+    let residue = storage.get(&residue_id).unwrap();
+    assert_eq!(eur.amount(), dec!(66.67));
+    assert_eq!(usd.amount(), dec!(35.98));
+    assert_eq!(residue.amount() - residue_before, dec!(0.00223475));
+    // The FX positions take the other side of both pockets' legs.
+    assert_eq!(
+        internal(&storage, InternalAccount::FxPosition, "EUR") - eur_position,
+        dec!(33.33)
+    );
+    assert_eq!(
+        internal(&storage, InternalAccount::FxPosition, "USD") - usd_position,
+        dec!(-35.98223475)
+    );
+    let debit = eur.ledgers().entries().last().unwrap();
+    let credit = usd.ledgers().entries().last().unwrap();
+    assert_eq!(debit.action(), ActionKind::Conversion);
+    assert_eq!(credit.action(), ActionKind::Conversion);
+    let transfer_id = Some(conversion.transfer_id.as_str());
+    assert_eq!(debit.transfer_id(), transfer_id);
+    assert_eq!(credit.transfer_id(), transfer_id);
+    assert!(residue
+        .ledgers()
+        .entries()
+        .iter()
+        .any(|ledger| ledger.transfer_id() == transfer_id));
+
+    // The inverse rate is used the other way round.
+    let back = storage
+        .convert(&id, "USD", "EUR", "10", &converter)
+        .unwrap();
+    assert_eq!(back.quote.target_amount, dec!(9.17));
+    assert_eq!(
+        storage.get_pocket(&id, "USD").unwrap().amount(),
+        dec!(25.98)
+    );
+
+    // Rejected conversions post nothing.
+    let wallet = storage.wallet(&id).unwrap();
+    assert_eq!(
+        storage.convert(&id, "EUR", "USD", "75.85", &converter),
+        Err(StorageError::Balance(BalanceError::BalanceNotEnough))
+    );
+    assert_eq!(
+        storage.convert(&id, "EUR", "GBP", "1", &converter),
+        Err(StorageError::Fx(FxError::MissingRate {
+            from: "EUR".to_string(),
+            to: "GBP".to_string()
+        }))
+    );
+    assert_eq!(
+        storage.convert(&id, "EUR", "eur", "1", &converter),
+        Err(StorageError::Fx(FxError::SameCurrency))
+    );
+    assert_eq!(
+        storage.convert(&id, "EUR", "JPY", "1", &converter),
+        Err(StorageError::PocketNotExists)
+    );
+    assert_eq!(
+        storage.convert(&id, "EUR", "USD", "1.001", &converter),
+        Err(StorageError::Balance(BalanceError::ExcessPrecision {
+            scale: 3,
+            minor_units: 2
+        }))
+    );
+    assert_eq!(
+        storage.convert(&account_id(), "EUR", "USD", "1", &converter),
+        Err(StorageError::AccountNotExists)
+    );
+    assert_eq!(
+        storage.convert(&pocket_key(&id, "USD"), "USD", "EUR", "1", &converter),
+        Err(StorageError::AccountNotExists)
+    );
+    assert_eq!(storage.wallet(&id).unwrap(), wallet);
+}
@@ -0,0 +1,1078 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+use chrono::Utc;
+use rust_decimal::Decimal;
+
+use super::wal::{AccountSnapshot, Journal, Record};
+use super::{
+    check_account_balance, check_double_entry, check_new_account, check_pockets_closable,
+    pocket_key, replayed, Storage, StorageError,
+};
+use crate::balance::{Balance, Controls};
+use crate::clock::SystemClock;
+use crate::fx::{Conversion, Converter, FxError, RateProvider};
+use crate::hold::Hold;
+use crate::id::{time_ordered, IdGenerator};
+use crate::journal::{
+    check_balanced, conversion_accounts, Posted, Transaction, TrialBalance, TrialBalanceLine,
+};
+use crate::ledger::{Action, Ledger};
+use crate::limits::VelocityLimits;
+use crate::status::{AccountStatus, StatusChange};
+use crate::wallet::Wallet;
+
+pub struct InMemory {
+    balances: Arc<RwLock<HashMap<String, Balance>>>,
+    journal: Option<Arc<Mutex<Journal>>>,
+    double_entry: bool,
+}
+
+impl Clone for InMemory {
+    fn clone(&self) -> Self {
+        InMemory {
+            balances: self.balances.clone(),
+            journal: self.journal.clone(),
+            double_entry: self.double_entry,
+        }
+    }
+}
+
+impl Default for InMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemory {
+    pub fn new() -> Self {
+        Self {
+            balances: Arc::new(RwLock::new(HashMap::new())),
+            journal: None,
+            double_entry: false,
+        }
+    }
+
+    /// Opens a store that journals every write to the append-only file at
+    /// `path`, rebuilding the balances by replaying it first.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        let (journal, balances) = Journal::open(path)?;
+        Ok(Self {
+            balances: Arc::new(RwLock::new(balances)),
+            journal: Some(Arc::new(Mutex::new(journal))),
+            double_entry: false,
+        })
+    }
+
+    /// Compacts the journal automatically once the history written since
+    /// the last snapshot grows past `bytes`. Has no effect without a journal.
+    pub fn with_compaction_threshold(self, bytes: u64) -> Self {
+        if let Some(journal) = &self.journal {
+            journal.lock().unwrap().set_compact_after(Some(bytes));
+        }
+        self
+    }
+
+    /// Keeps the books balanced, so `trial_balance` always is: amounts
+    /// only change through `post`, `transfer` and `convert`. Writes that
+    /// would change a single balance, such as `append`, capturing a hold or
+    /// deleting a funded account, fail with `JournalError::SingleSided`.
+    pub fn with_double_entry(mut self) -> Self {
+        self.double_entry = true;
+        self
+    }
+
+    /// Point-in-time view of every account: currency, running balance, last
+    /// ledger id and the entries compaction would keep, sorted by account id.
+    pub fn snapshot(&self) -> Vec<AccountSnapshot> {
+        let bal = self.balances.read().unwrap();
+        snapshot(&bal)
+    }
+
+    /// Replaces the journal's history with a snapshot so startup no longer
+    /// replays every ledger. The snapshot keeps the entries booked within the
+    /// longest velocity window and what is left to reverse of older ones.
+    /// The history is kept for `export_ledgers`.
+    pub fn compact(&self) -> Result<(), StorageError> {
+        let bal = self.balances.write().unwrap();
+        match &self.journal {
+            Some(journal) => journal.lock().unwrap().compact(snapshot(&bal)),
+            None => Ok(()),
+        }
+    }
+
+    /// Every ledger ever posted, including compacted history, as
+    /// `(account_id, ledger)` pairs in posting order.
+    pub fn export_ledgers(&self) -> Result<Vec<(String, Ledger)>, StorageError> {
+        let bal = self.balances.read().unwrap();
+        match &self.journal {
+            Some(journal) => journal.lock().unwrap().export_ledgers(),
+            None => {
+                let mut ids: Vec<&String> = bal.keys().collect();
+                ids.sort();
+                Ok(ids
+                    .into_iter()
+                    .flat_map(|id| {
+                        bal[id]
+                            .ledgers()
+                            .entries()
+                            .iter()
+                            .map(move |ledger| (id.clone(), ledger.clone()))
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    // Callers hold the balances write lock, so journal order matches the
+    // order writes become visible.
+    fn journal(&self, records: &[Record]) -> Result<(), StorageError> {
+        match &self.journal {
+            Some(journal) if !records.is_empty() => journal.lock().unwrap().write(records),
+            _ => Ok(()),
+        }
+    }
+
+    // Runs before a write is applied, while `balances` still matches what
+    // the journal replays to.
+    fn compact_if_due(&self, balances: &HashMap<String, Balance>) -> Result<(), StorageError> {
+        match &self.journal {
+            Some(journal) => {
+                let mut journal = journal.lock().unwrap();
+                if journal.compaction_due() {
+                    journal.compact(snapshot(balances))?;
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `f` against the stored balance while holding the write lock, so
+    /// concurrent callers can't lose each other's updates.
+    pub fn with_account_mut<T>(
+        &mut self,
+        account_id: &str,
+        f: impl FnOnce(&mut Balance) -> T,
+    ) -> Result<T, StorageError> {
+        let resolve =
+            |bal: &HashMap<String, Balance>| owner(bal, account_id).map(|_| account_id.to_string());
+        self.with_balance_mut(resolve, |balance, _| f(balance))
+    }
+
+    // Like `with_account_mut`, for the balance stored under the key
+    // `resolve` picks once the write lock is held. `f` also gets the
+    // controls that balance is governed by.
+    fn with_balance_mut<T>(
+        &mut self,
+        resolve: impl FnOnce(&HashMap<String, Balance>) -> Result<String, StorageError>,
+        f: impl FnOnce(&mut Balance, Controls) -> T,
+    ) -> Result<T, StorageError> {
+        let mut bal = self.balances.write().unwrap();
+        self.compact_if_due(&bal)?;
+        let account_id = &resolve(&bal)?;
+        let controls = governing(&bal, account_id);
+        let balance = bal.get_mut(account_id).unwrap();
+        let before = balance.ledgers().len();
+        let amount_before = balance.amount();
+        let holds_before = balance.holds().to_vec();
+        let history_before = balance.status_history().len();
+        let result = f(balance, controls);
+
+        let mut records: Vec<Record> = balance.ledgers().entries()[before..]
+            .iter()
+            .map(|ledger| Record::append(account_id, ledger))
+            .collect();
+        if balance.holds() != holds_before {
+            records.push(Record::holds(account_id, balance.holds()));
+        }
+        for change in &balance.status_history()[history_before..] {
+            records.push(Record::status(account_id, change));
+        }
+        let written = check_double_entry(self.double_entry, balance.amount() - amount_before)
+            .and_then(|()| self.journal(&records));
+        if let Err(e) = written {
+            balance.truncate(before);
+            balance.restore_holds(holds_before);
+            let mut history = balance.status_history().to_vec();
+            history.truncate(history_before);
+            balance.restore_status_history(history);
+            return Err(e);
+        }
+        Ok(result)
+    }
+
+    /// Moves `amount` from one account to another under a single write lock.
+    ///
+    /// Both sides are validated before either is posted, so a rejected
+    /// transfer leaves both balances untouched. The debit and credit ledgers
+    /// share the returned transfer id.
+    pub fn transfer(&mut self, from: &str, to: &str, amount: &str) -> Result<String, StorageError> {
+        if from == to {
+            return Err(StorageError::SameAccount);
+        }
+        let debit = Ledger::new(Action::TransferOut(amount.to_string()), time_ordered())
+            .map_err(StorageError::Ledger)?;
+        let credit = Ledger::new(Action::TransferIn(amount.to_string()), time_ordered())
+            .map_err(StorageError::Ledger)?;
+
+        let mut bal = self.balances.write().unwrap();
+        self.compact_if_due(&bal)?;
+        let (source, target) = (owner(&bal, from)?, owner(&bal, to)?);
+        if source.currency != target.currency {
+            return Err(StorageError::CurrencyMismatch);
+        }
+
+        let transfer_id = Arc::new(time_ordered().next_id());
+        let debit = source
+            .prepare(debit.with_transfer_id(transfer_id.clone()))
+            .map_err(StorageError::Balance)?;
+        let mut credit = credit.with_transfer_id(transfer_id.clone());
+        credit.set_amount(-debit.amount());
+        let credit = target.prepare(credit).map_err(StorageError::Balance)?;
+
+        self.journal(&[Record::append(from, &debit), Record::append(to, &credit)])?;
+        bal.get_mut(from).unwrap().commit(debit);
+        bal.get_mut(to).unwrap().commit(credit);
+        Ok(transfer_id.to_string())
+    }
+
+    // `post` for callers already holding the write lock. Postings are
+    // committed in place as they are validated, so each sees the ones
+    // before it, and rolled back if the transaction is rejected.
+    fn apply(
+        &self,
+        balances: &mut HashMap<String, Balance>,
+        transaction: Transaction,
+    ) -> Result<Posted, StorageError> {
+        let (transaction_id, postings) = transaction.into_parts();
+        let keys = postings
+            .iter()
+            .map(|posting| pocket(balances, posting.account_id(), posting.currency()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let legs = keys.iter().zip(&postings);
+        if let Some(totals) =
+            replayed(legs.map(|(key, posting)| (&balances[key], posting.ledger())))?
+        {
+            return Ok(Posted {
+                transaction_id,
+                totals,
+            });
+        }
+
+        let transfer_id = Arc::new(transaction_id.clone());
+        // Ledger count of every touched balance before the transaction.
+        let mut lengths: HashMap<String, usize> = HashMap::new();
+        let mut records = Vec::with_capacity(postings.len());
+        let mut legs = Vec::with_capacity(postings.len());
+        let mut totals = Vec::with_capacity(postings.len());
+        let written = keys
+            .into_iter()
+            .zip(postings)
+            .try_for_each(|(key, posting)| {
+                let (_, currency, ledger) = posting.into_parts();
+                let controls = governing(balances, &key);
+                let balance = balances.get_mut(&key).unwrap();
+                lengths
+                    .entry(key.clone())
+                    .or_insert(balance.ledgers().len());
+                let ledger = balance
+                    .prepare_under(ledger.with_transfer_id(transfer_id.clone()), controls)
+                    .map_err(StorageError::Balance)?;
+                records.push(Record::append(&key, &ledger));
+                legs.push((currency, ledger.amount()));
+                totals.push(balance.commit(ledger));
+                Ok(())
+            })
+            .and_then(|()| {
+                check_balanced(
+                    legs.iter()
+                        .map(|(currency, amount)| (currency.as_str(), *amount)),
+                )
+                .map_err(StorageError::Journal)
+            })
+            .and_then(|()| self.journal(&records));
+        if let Err(e) = written {
+            for (key, len) in lengths {
+                balances.get_mut(&key).unwrap().truncate(len);
+            }
+            return Err(e);
+        }
+        Ok(Posted {
+            transaction_id,
+            totals,
+        })
+    }
+}
+
+// The account `account_id`, unless it doesn't exist or is itself a pocket.
+fn owner<'a>(
+    balances: &'a HashMap<String, Balance>,
+    account_id: &str,
+) -> Result<&'a Balance, StorageError> {
+    balances
+        .get(account_id)
+        .filter(|balance| balance.pocket_of().is_none())
+        .ok_or(StorageError::AccountNotExists)
+}
+
+// Key the account's `currency` pocket is stored under.
+fn pocket(
+    balances: &HashMap<String, Balance>,
+    account_id: &str,
+    currency: &str,
+) -> Result<String, StorageError> {
+    let currency = currency.to_uppercase();
+    if owner(balances, account_id)?.currency == currency {
+        return Ok(account_id.to_string());
+    }
+    let key = pocket_key(account_id, &currency);
+    match balances.get(&key) {
+        Some(balance) if balance.pocket_of() == Some(account_id) => Ok(key),
+        _ => Err(StorageError::PocketNotExists),
+    }
+}
+
+// The controls postings on the balance stored under `key` are checked
+// against: its account's if it is a pocket.
+fn governing(balances: &HashMap<String, Balance>, key: &str) -> Controls {
+    let balance = &balances[key];
+    match balance.pocket_of() {
+        Some(account_id) => balances[account_id].controls(),
+        None => balance.controls(),
+    }
+}
+
+// Keys of the account's pockets other than its primary balance.
+fn pocket_keys(balances: &HashMap<String, Balance>, account_id: &str) -> Vec<String> {
+    balances
+        .iter()
+        .filter(|(_, balance)| balance.pocket_of() == Some(account_id))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+fn snapshot(balances: &HashMap<String, Balance>) -> Vec<AccountSnapshot> {
+    let retain_after = Utc::now() - VelocityLimits::longest_window();
+    let mut accounts: Vec<AccountSnapshot> = balances
+        .iter()
+        .map(|(id, balance)| AccountSnapshot::new(id, balance, retain_after))
+        .collect();
+    accounts.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+    accounts
+}
+
+impl Storage for InMemory {
+    fn insert(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError> {
+        check_new_account(account_id, &balance)?;
+        let mut bal = self.balances.write().unwrap();
+        self.compact_if_due(&bal)?;
+        if bal.contains_key(account_id) {
+            return Err(StorageError::AccountAlreadyExists);
+        }
+        check_double_entry(self.double_entry, balance.amount())?;
+        self.journal(&[Record::insert(account_id, &balance)])?;
+        bal.insert(account_id.to_string(), balance);
+        Ok(())
+    }
+
+    fn get(&self, account_id: &str) -> Result<Balance, StorageError> {
+        let bal = self.balances.read().unwrap();
+        owner(&bal, account_id).cloned()
+    }
+
+    fn currency(&self, account_id: &str) -> Result<String, StorageError> {
+        let bal = self.balances.read().unwrap();
+        Ok(owner(&bal, account_id)?.currency.clone())
+    }
+
+    /// Overwrites the stored balance. Prefer `append` or `with_account_mut`
+    /// for read-modify-write sequences, this does not detect concurrent
+    /// writers.
+    fn update(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError> {
+        check_account_balance(&balance)?;
+        let mut bal = self.balances.write().unwrap();
+        self.compact_if_due(&bal)?;
+        let stored = owner(&bal, account_id)?;
+        balance
+            .check_replaces(stored)
+            .map_err(StorageError::Balance)?;
+        let pockets = pocket_keys(&bal, account_id);
+        check_pockets_closable(
+            balance.status(),
+            pockets.iter().map(|key| bal[key].amount()),
+        )?;
+        check_double_entry(self.double_entry, balance.amount() - stored.amount())?;
+        self.journal(&[Record::update(account_id, &balance)])?;
+        bal.insert(account_id.to_string(), balance);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>, StorageError> {
+        let bal = self.balances.read().unwrap();
+        let mut ids: Vec<String> = bal
+            .iter()
+            .filter(|(_, balance)| balance.pocket_of().is_none())
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn delete(&mut self, account_id: &str) -> Result<Balance, StorageError> {
+        let mut bal = self.balances.write().unwrap();
+        self.compact_if_due(&bal)?;
+        owner(&bal, account_id)?;
+        let pockets = pocket_keys(&bal, account_id);
+        for key in pockets.iter().map(String::as_str).chain([account_id]) {
+            check_double_entry(self.double_entry, bal[key].amount())?;
+        }
+        let mut records: Vec<Record> = pockets.iter().map(|key| Record::delete(key)).collect();
+        records.push(Record::delete(account_id));
+        self.journal(&records)?;
+        for key in &pockets {
+            bal.remove(key);
+        }
+        Ok(bal.remove(account_id).unwrap())
+    }
+
+    fn append(&mut self, account_id: &str, ledger: Ledger) -> Result<Decimal, StorageError> {
+        self.with_account_mut(account_id, |balance| balance.mutate(ledger))?
+            .map_err(StorageError::Balance)
+    }
+
+    fn place_hold(&mut self, account_id: &str, hold: Hold) -> Result<Decimal, StorageError> {
+        self.with_account_mut(account_id, |balance| balance.place_hold(hold))?
+            .map_err(StorageError::Balance)
+    }
+
+    fn capture_hold(
+        &mut self,
+        account_id: &str,
+        hold_id: &str,
+        amount: Option<&str>,
+    ) -> Result<Decimal, StorageError> {
+        self.with_account_mut(account_id, |balance| {
+            balance.capture(hold_id, amount, time_ordered(), &SystemClock)
+        })?
+        .map_err(StorageError::Balance)
+    }
+
+    fn release_hold(&mut self, account_id: &str, hold_id: &str) -> Result<Hold, StorageError> {
+        self.with_account_mut(account_id, |balance| balance.release(hold_id))?
+            .map_err(StorageError::Balance)
+    }
+
+    fn set_status(
+        &mut self,
+        account_id: &str,
+        status: AccountStatus,
+        reason: &str,
+    ) -> Result<StatusChange, StorageError> {
+        let resolve = |bal: &HashMap<String, Balance>| {
+            owner(bal, account_id)?;
+            let pockets = pocket_keys(bal, account_id);
+            check_pockets_closable(status, pockets.iter().map(|key| bal[key].amount()))?;
+            Ok(account_id.to_string())
+        };
+        self.with_balance_mut(resolve, |balance, _| balance.transition(status, reason))?
+            .map_err(StorageError::Balance)
+    }
+
+    fn open_pocket(&mut self, account_id: &str, currency: &str) -> Result<(), StorageError> {
+        let mut bal = self.balances.write().unwrap();
+        self.compact_if_due(&bal)?;
+        let primary = owner(&bal, account_id)?;
+        primary
+            .controls()
+            .check_status(true)
+            .map_err(StorageError::Balance)?;
+        let balance = Balance::new(currency)
+            .map_err(StorageError::Balance)?
+            .with_pocket_of(Some(account_id.to_string()));
+        let key = pocket_key(account_id, &balance.currency);
+        if primary.currency == balance.currency || bal.contains_key(&key) {
+            return Err(StorageError::PocketAlreadyExists);
+        }
+        self.journal(&[Record::insert(&key, &balance)])?;
+        bal.insert(key, balance);
+        Ok(())
+    }
+
+    fn pockets(&self, account_id: &str) -> Result<Vec<String>, StorageError> {
+        let bal = self.balances.read().unwrap();
+        let mut currencies = vec![owner(&bal, account_id)?.currency.clone()];
+        for key in pocket_keys(&bal, account_id) {
+            currencies.push(bal[&key].currency.clone());
+        }
+        currencies.sort();
+        Ok(currencies)
+    }
+
+    fn get_pocket(&self, account_id: &str, currency: &str) -> Result<Balance, StorageError> {
+        let bal = self.balances.read().unwrap();
+        Ok(bal[&pocket(&bal, account_id, currency)?].clone())
+    }
+
+    fn append_to_pocket(
+        &mut self,
+        account_id: &str,
+        currency: &str,
+        ledger: Ledger,
+    ) -> Result<Decimal, StorageError> {
+        let resolve = |bal: &HashMap<String, Balance>| pocket(bal, account_id, currency);
+        self.with_balance_mut(resolve, |balance, controls| {
+            balance.mutate_under(ledger, controls)
+        })?
+        .map_err(StorageError::Balance)
+    }
+
+    fn post(&mut self, transaction: Transaction) -> Result<Posted, StorageError> {
+        let mut bal = self.balances.write().unwrap();
+        self.compact_if_due(&bal)?;
+        self.apply(&mut bal, transaction)
+    }
+
+    fn trial_balance(&self) -> Result<TrialBalance, StorageError> {
+        let bal = self.balances.read().unwrap();
+        let lines = bal
+            .iter()
+            .map(|(key, balance)| TrialBalanceLine {
+                account_id: balance.pocket_of().unwrap_or(key).to_string(),
+                currency: balance.currency.clone(),
+                amount: balance.amount(),
+            })
+            .collect();
+        Ok(TrialBalance::new(lines))
+    }
+
+    fn wallet(&self, account_id: &str) -> Result<Wallet, StorageError> {
+        let bal = self.balances.read().unwrap();
+        let mut pockets = vec![owner(&bal, account_id)?.clone()];
+        for key in pocket_keys(&bal, account_id) {
+            pockets.push(bal[&key].clone());
+        }
+        Ok(Wallet::new(account_id, pockets))
+    }
+
+    fn convert<R: RateProvider>(
+        &mut self,
+        account_id: &str,
+        from: &str,
+        to: &str,
+        amount: &str,
+        converter: &Converter<R>,
+    ) -> Result<Conversion, StorageError> {
+        let debit = Ledger::conversion_debit(amount, time_ordered(), &SystemClock)
+            .map_err(StorageError::Ledger)?;
+
+        let mut bal = self.balances.write().unwrap();
+        self.compact_if_due(&bal)?;
+        let source = pocket(&bal, account_id, from)?;
+        let target = pocket(&bal, account_id, to)?;
+        if source == target {
+            return Err(StorageError::Fx(FxError::SameCurrency));
+        }
+        // Priced on what the source would actually be debited.
+        let debit = bal[&source]
+            .prepare_under(debit, governing(&bal, &source))
+            .map_err(StorageError::Balance)?;
+        let quote = converter
+            .quote(
+                &bal[&source].currency,
+                &bal[&target].currency,
+                debit.amount(),
+            )
+            .map_err(StorageError::Fx)?;
+
+        for (account, currency) in conversion_accounts(&quote.from, &quote.to) {
+            if let Entry::Vacant(entry) = bal.entry(account.account_id(currency)) {
+                let balance = account.balance(currency).map_err(StorageError::Balance)?;
+                self.journal(&[Record::insert(entry.key(), &balance)])?;
+                entry.insert(balance);
+            }
+        }
+        let posted = self.apply(
+            &mut bal,
+            Transaction::conversion(account_id, &quote, &SystemClock),
+        )?;
+        Ok(Conversion {
+            transfer_id: posted.transaction_id,
+            quote,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::BalanceError;
+    use crate::currency::Rounding;
+    use crate::fx::StaticRates;
+    use crate::journal::{InternalAccount, JournalError, Posting};
+    use crate::ledger::ActionKind;
+    use crate::ledger::LedgerError;
+    use crate::storage::conformance::storage_conformance;
+    use rust_decimal::prelude::FromPrimitive;
+    use rust_decimal::RoundingStrategy;
+    use rust_decimal_macros::dec;
+
+    storage_conformance!(InMemory::new());
+
+    #[test]
+    fn test_get_balance() {
+        let mut storage = InMemory::new();
+        let balance = Balance::new("USD").unwrap();
+        storage.insert("account_1", balance.clone()).unwrap();
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.currency, "USD");
+    }
+
+    #[test]
+    fn test_new_in_memory() {
+        let storage = InMemory::new();
+        assert!(storage.balances.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_insert_existing_account() {
+        let mut storage = InMemory::new();
+        let balance = Balance::new("USD").unwrap();
+        assert!(storage.insert("account_1", balance.clone()).is_ok());
+        assert!(matches!(
+            storage.insert("account_1", balance),
+            Err(StorageError::AccountAlreadyExists)
+        ));
+    }
+
+    #[test]
+    fn test_insert_multiple_account_using_multiple_threads() {
+        let storage = InMemory::new();
+
+        let balance = Balance::new("USD").unwrap();
+        let balance_clone = balance.clone();
+        let mut storage_clone = storage.clone();
+        let handle = std::thread::spawn(move || {
+            storage_clone.insert("account_1", balance_clone).unwrap();
+        });
+
+        let balance_clone = balance.clone();
+        let mut storage_clone = storage.clone();
+        let handle2 = std::thread::spawn(move || {
+            storage_clone.insert("account_2", balance_clone).unwrap();
+        });
+
+        handle.join().unwrap();
+        handle2.join().unwrap();
+
+        assert_eq!(storage.get("account_1").unwrap().currency, "USD");
+        assert_eq!(storage.get("account_2").unwrap().currency, "USD");
+    }
+
+    #[test]
+    fn test_update_balance_with_exist_account() {
+        let mut storage = InMemory::new();
+        let balance = Balance::new("USD").unwrap();
+        storage.insert("account_1", balance.clone()).unwrap();
+
+        let mut balance = storage.get("account_1").unwrap();
+        let ledger = Ledger::new(Action::Deposit("100.0".to_string()), time_ordered()).unwrap();
+        balance.mutate(ledger).unwrap();
+
+        storage.update("account_1", balance).unwrap();
+
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.amount(), Decimal::from_f64(100.0).unwrap());
+    }
+
+    #[test]
+    fn test_update_balance_with_not_exist_account() {
+        let mut storage = InMemory::new();
+        let balance = Balance::new("USD").unwrap();
+        assert!(matches!(
+            storage.update("account_1", balance),
+            Err(StorageError::AccountNotExists)
+        ));
+    }
+
+    fn funded(storage: &mut InMemory, account_id: &str, currency: &str, amount: &str) {
+        let mut balance = Balance::new(currency).unwrap();
+        if amount != "0" {
+            let ledger = Ledger::new(Action::Deposit(amount.to_string()), time_ordered()).unwrap();
+            balance.mutate(ledger).unwrap();
+        }
+        storage.insert(account_id, balance).unwrap();
+    }
+
+    #[test]
+    fn test_transfer_moves_funds() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_1", "USD", "100");
+        funded(&mut storage, "account_2", "USD", "0");
+
+        let transfer_id = storage.transfer("account_1", "account_2", "40.25").unwrap();
+
+        let source = storage.get("account_1").unwrap();
+        let target = storage.get("account_2").unwrap();
+        assert_eq!(source.amount(), dec!(59.75));
+        assert_eq!(target.amount(), dec!(40.25));
+
+        let debit = source.ledgers().entries().last().unwrap();
+        let credit = target.ledgers().entries().last().unwrap();
+        assert_eq!(debit.transfer_id(), Some(transfer_id.as_str()));
+        assert_eq!(credit.transfer_id(), Some(transfer_id.as_str()));
+        assert_eq!(debit.amount(), -credit.amount());
+        assert_eq!(debit.action(), ActionKind::TransferOut);
+        assert_eq!(credit.action(), ActionKind::TransferIn);
+    }
+
+    #[test]
+    fn test_transfer_insufficient_funds_has_no_effect() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_1", "USD", "10");
+        funded(&mut storage, "account_2", "USD", "5");
+
+        assert_eq!(
+            storage.transfer("account_1", "account_2", "10.01"),
+            Err(StorageError::Balance(BalanceError::BalanceNotEnough))
+        );
+        assert_eq!(storage.get("account_1").unwrap().amount(), dec!(10));
+        assert_eq!(storage.get("account_2").unwrap().amount(), dec!(5));
+        assert_eq!(storage.get("account_2").unwrap().ledgers().len(), 1);
+    }
+
+    #[test]
+    fn test_transfer_currency_mismatch() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_1", "USD", "10");
+        funded(&mut storage, "account_2", "EUR", "0");
+
+        assert_eq!(
+            storage.transfer("account_1", "account_2", "1"),
+            Err(StorageError::CurrencyMismatch)
+        );
+        assert_eq!(storage.get("account_1").unwrap().amount(), dec!(10));
+    }
+
+    #[test]
+    fn test_transfer_rejects_invalid_requests() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_1", "USD", "10");
+
+        assert_eq!(
+            storage.transfer("account_1", "account_1", "1"),
+            Err(StorageError::SameAccount)
+        );
+        assert_eq!(
+            storage.transfer("account_1", "account_2", "1"),
+            Err(StorageError::AccountNotExists)
+        );
+        assert_eq!(
+            storage.transfer("account_1", "account_2", "-1"),
+            Err(StorageError::Ledger(LedgerError::InvalidAmount(
+                "amount can't be negative".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn test_post_keeps_books_balanced() {
+        let mut storage = InMemory::new().with_double_entry();
+        funded(&mut storage, "account_1", "USD", "0");
+        storage.open_pocket("account_1", "EUR").unwrap();
+        for currency in ["USD", "EUR"] {
+            for account in InternalAccount::ALL {
+                account.open(&mut storage, currency).unwrap();
+            }
+        }
+
+        let deposit = Transaction::deposit("account_1", "USD", "100").unwrap();
+        let deposit_id = deposit.id().to_string();
+        let posted = storage.post(deposit).unwrap();
+        assert_eq!(posted.transaction_id, deposit_id);
+        assert_eq!(posted.totals, [dec!(100), dec!(-100)]);
+        storage
+            .post(Transaction::fee("account_1", "USD", "2.5").unwrap())
+            .unwrap();
+        storage
+            .post(Transaction::withdrawal("account_1", "USD", "20").unwrap())
+            .unwrap();
+        // Unidentified funds parked in suspense, then matched to the EUR
+        // pocket.
+        let suspense = InternalAccount::Suspense.account_id("EUR");
+        let cash = InternalAccount::Cash.account_id("EUR");
+        storage
+            .post(
+                Transaction::new(vec![
+                    Posting::credit(&suspense, "EUR", "40").unwrap(),
+                    Posting::debit(&cash, "EUR", "40").unwrap(),
+                ])
+                .unwrap(),
+            )
+            .unwrap();
+        storage
+            .post(
+                Transaction::new(vec![
+                    Posting::debit(&suspense, "EUR", "40").unwrap(),
+                    Posting::credit("account_1", "eur", "40").unwrap(),
+                ])
+                .unwrap(),
+            )
+            .unwrap();
+
+        let account = storage.get("account_1").unwrap();
+        assert_eq!(account.amount(), dec!(77.5));
+        assert_eq!(
+            account.ledgers().entries()[0].transfer_id(),
+            Some(deposit_id.as_str())
+        );
+        assert_eq!(
+            storage.get_pocket("account_1", "EUR").unwrap().amount(),
+            dec!(40)
+        );
+        let income = InternalAccount::FeesIncome.account_id("USD");
+        assert_eq!(storage.get(&income).unwrap().amount(), dec!(2.5));
+        assert_eq!(storage.get(&cash).unwrap().amount(), dec!(-40));
+
+        let trial = storage.trial_balance().unwrap();
+        assert!(trial.is_balanced());
+        assert_eq!(trial.lines().len(), 12);
+        assert_eq!(trial.totals().keys().collect::<Vec<_>>(), ["EUR", "USD"]);
+
+        // Conversions go through the FX accounts, so they balance too.
+        let rates = StaticRates::new()
+            .with_rate("EUR", "USD", "1.0850")
+            .unwrap();
+        let converter = Converter::new(rates).with_markup("0.005").unwrap();
+        storage
+            .convert("account_1", "EUR", "USD", "33.33", &converter)
+            .unwrap();
+        let amount = |storage: &InMemory, account: InternalAccount, currency| {
+            let id = account.account_id(currency);
+            storage.get(&id).unwrap().amount()
+        };
+        assert_eq!(
+            amount(&storage, InternalAccount::FxPosition, "EUR"),
+            dec!(33.33)
+        );
+        assert_eq!(
+            amount(&storage, InternalAccount::FxPosition, "USD"),
+            dec!(-35.98223475)
+        );
+        assert_eq!(
+            amount(&storage, InternalAccount::FxResidue, "USD"),
+            dec!(0.00223475)
+        );
+        assert!(storage.trial_balance().unwrap().is_balanced());
+
+        // Writes that would change a single balance are refused.
+        let single_sided = StorageError::Journal(JournalError::SingleSided);
+        let deposit = Ledger::new(Action::Deposit("1".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            storage.append("account_1", deposit).unwrap_err(),
+            single_sided
+        );
+        let hold = Hold::new("10", time_ordered()).unwrap();
+        let hold_id = hold.id().to_string();
+        storage.place_hold("account_1", hold).unwrap();
+        assert_eq!(
+            storage
+                .capture_hold("account_1", &hold_id, None)
+                .unwrap_err(),
+            single_sided
+        );
+        storage.release_hold("account_1", &hold_id).unwrap();
+        let funded = storage.get("account_1").unwrap();
+        assert_eq!(
+            storage.insert("account_2", funded.clone()).unwrap_err(),
+            single_sided
+        );
+        assert_eq!(storage.delete("account_1").unwrap_err(), single_sided);
+        storage
+            .insert("account_2", Balance::new("USD").unwrap())
+            .unwrap();
+        assert_eq!(
+            storage.update("account_2", funded).unwrap_err(),
+            single_sided
+        );
+        // Transfers move funds between accounts in one currency.
+        storage.transfer("account_1", "account_2", "7.5").unwrap();
+        // 77.5 + 35.98 converted - 7.5
+        assert_eq!(storage.get("account_1").unwrap().amount(), dec!(105.98));
+        assert!(storage.trial_balance().unwrap().is_balanced());
+    }
+
+    #[test]
+    fn test_post_rejections_have_no_effect() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_1", "USD", "10");
+        let cash = InternalAccount::Cash.open(&mut storage, "USD").unwrap();
+        let yen = Balance::new("JPY")
+            .unwrap()
+            .with_rounding(Rounding::Round(RoundingStrategy::MidpointNearestEven));
+        storage.insert("account_2", yen).unwrap();
+        let yen_cash = InternalAccount::Cash.open(&mut storage, "JPY").unwrap();
+
+        // The second withdrawal overdraws what the first one left.
+        let transaction = Transaction::new(vec![
+            Posting::new("account_1", "USD", Action::Withdrawal("6".to_string())).unwrap(),
+            Posting::new("account_1", "USD", Action::Withdrawal("6".to_string())).unwrap(),
+            Posting::credit(&cash, "USD", "12").unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(
+            storage.post(transaction),
+            Err(StorageError::Balance(BalanceError::BalanceNotEnough))
+        );
+        assert_eq!(
+            storage.post(Transaction::deposit("account_1", "EUR", "1").unwrap()),
+            Err(StorageError::PocketNotExists)
+        );
+        assert_eq!(
+            storage.post(Transaction::deposit("account_9", "USD", "1").unwrap()),
+            Err(StorageError::AccountNotExists)
+        );
+        // Both halves round up to 2 yen while the debit stays whole.
+        let transaction = Transaction::new(vec![
+            Posting::credit("account_2", "JPY", "1.5").unwrap(),
+            Posting::credit("account_2", "JPY", "1.5").unwrap(),
+            Posting::debit(&yen_cash, "JPY", "3").unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(
+            storage.post(transaction),
+            Err(StorageError::Journal(JournalError::Unbalanced {
+                currency: "JPY".to_string(),
+                residual: dec!(1)
+            }))
+        );
+
+        assert_eq!(storage.get("account_1").unwrap().ledgers().len(), 1);
+        assert_eq!(storage.get(&cash).unwrap().ledgers().len(), 0);
+        assert_eq!(storage.get("account_2").unwrap().amount(), dec!(0));
+    }
+
+    #[test]
+    fn test_transfer_concurrently_conserves_total() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_1", "USD", "1000");
+        funded(&mut storage, "account_2", "USD", "1000");
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let mut storage = storage.clone();
+                let (from, to) = if i % 2 == 0 {
+                    ("account_1", "account_2")
+                } else {
+                    ("account_2", "account_1")
+                };
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        let _ = storage.transfer(from, to, "7.5");
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let total =
+            storage.get("account_1").unwrap().amount() + storage.get("account_2").unwrap().amount();
+        assert_eq!(total, dec!(2000));
+    }
+
+    #[test]
+    fn test_with_account_mut_not_exist_account() {
+        let mut storage = InMemory::new();
+        let result = storage.with_account_mut("account_1", |balance| balance.amount());
+        assert_eq!(result, Err(StorageError::AccountNotExists));
+    }
+
+    #[test]
+    fn test_with_account_mut_concurrent_deposits_are_not_lost() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_1", "USD", "0");
+
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                let mut storage = storage.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..250 {
+                        let ledger =
+                            Ledger::new(Action::Deposit("100".to_string()), time_ordered())
+                                .unwrap();
+                        storage
+                            .with_account_mut("account_1", |balance| balance.mutate(ledger))
+                            .unwrap()
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.ledgers().len(), 16 * 250);
+        assert_eq!(balance.amount(), dec!(400000));
+    }
+
+    #[test]
+    fn test_get_not_exist_account() {
+        let storage = InMemory::new();
+        assert_eq!(
+            storage.get("account_1"),
+            Err(StorageError::AccountNotExists)
+        );
+    }
+
+    #[test]
+    fn test_list_accounts() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_2", "USD", "0");
+        funded(&mut storage, "account_1", "USD", "0");
+        assert_eq!(storage.list().unwrap(), vec!["account_1", "account_2"]);
+    }
+
+    #[test]
+    fn test_delete_account() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_1", "USD", "10");
+
+        let balance = storage.delete("account_1").unwrap();
+        assert_eq!(balance.amount(), dec!(10));
+        assert_eq!(
+            storage.get("account_1"),
+            Err(StorageError::AccountNotExists)
+        );
+        assert_eq!(
+            storage.delete("account_1"),
+            Err(StorageError::AccountNotExists)
+        );
+    }
+
+    #[test]
+    fn test_append_ledger() {
+        let mut storage = InMemory::new();
+        funded(&mut storage, "account_1", "USD", "10");
+
+        let ledger = Ledger::new(Action::Withdrawal("4".to_string()), time_ordered()).unwrap();
+        assert_eq!(storage.append("account_1", ledger), Ok(dec!(6)));
+
+        let ledger = Ledger::new(Action::Withdrawal("7".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            storage.append("account_1", ledger),
+            Err(StorageError::Balance(BalanceError::BalanceNotEnough))
+        );
+
+        let ledger = Ledger::new(Action::Deposit("1".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            storage.append("account_2", ledger),
+            Err(StorageError::AccountNotExists)
+        );
+    }
+}
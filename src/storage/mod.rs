@@ -0,0 +1,243 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::balance::{Balance, BalanceError};
+use crate::fx::{Conversion, Converter, FxError, RateProvider};
+use crate::hold::Hold;
+use crate::journal::{JournalError, Posted, Posting, Transaction, TrialBalance};
+use crate::ledger::{Ledger, LedgerError};
+use crate::status::{AccountStatus, StatusChange};
+use crate::wallet::Wallet;
+
+#[cfg(test)]
+mod conformance;
+mod memory;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod wal;
+
+pub use memory::InMemory;
+#[cfg(feature = "postgres")]
+pub use postgres::Postgres;
+#[cfg(feature = "sqlite")]
+pub use sqlite::Sqlite;
+pub use wal::AccountSnapshot;
+
+#[derive(Debug, PartialEq)]
+pub enum StorageError {
+    AccountAlreadyExists,
+    AccountNotExists,
+    SameAccount,
+    CurrencyMismatch,
+    PocketAlreadyExists,
+    PocketNotExists,
+    /// Account ids can't contain `/`, which separates pocket keys.
+    InvalidAccountId,
+    /// A pocket's balance was given where an account's is expected.
+    PocketBalance,
+    Ledger(LedgerError),
+    Balance(BalanceError),
+    Fx(FxError),
+    Journal(JournalError),
+    Backend(String),
+}
+
+// Key a non-primary currency pocket of `account_id` is stored under. Pockets
+// don't show up in `Storage::list`.
+pub(crate) fn pocket_key(account_id: &str, currency: &str) -> String {
+    format!("{account_id}/{currency}")
+}
+
+// Rejects inserting `balance` as the new account `account_id`.
+pub(crate) fn check_new_account(account_id: &str, balance: &Balance) -> Result<(), StorageError> {
+    if account_id.contains('/') {
+        return Err(StorageError::InvalidAccountId);
+    }
+    check_account_balance(balance)
+}
+
+// Rejects storing a pocket's balance as an account's.
+pub(crate) fn check_account_balance(balance: &Balance) -> Result<(), StorageError> {
+    match balance.pocket_of() {
+        Some(_) => Err(StorageError::PocketBalance),
+        None => Ok(()),
+    }
+}
+
+// Rejects moving an account to `status` if that closes it while one of its
+// pockets, whose `amounts` are given, still holds funds.
+pub(crate) fn check_pockets_closable(
+    status: AccountStatus,
+    amounts: impl IntoIterator<Item = Decimal>,
+) -> Result<(), StorageError> {
+    if status == AccountStatus::Closed && amounts.into_iter().any(|amount| !amount.is_zero()) {
+        return Err(StorageError::Balance(BalanceError::NonZeroBalance));
+    }
+    Ok(())
+}
+
+// Rejects a write that changes the books' total by `change` on a store that
+// keeps them balanced.
+pub(crate) fn check_double_entry(double_entry: bool, change: Decimal) -> Result<(), StorageError> {
+    if double_entry && !change.is_zero() {
+        return Err(StorageError::Journal(JournalError::SingleSided));
+    }
+    Ok(())
+}
+
+// The ledgers of the `postings` made to the pocket `key`, given the pocket
+// each posting resolved to, and when the earliest of them was booked.
+pub(crate) fn touching<'a>(
+    key: &str,
+    keys: &[String],
+    postings: &'a [Posting],
+) -> (Option<DateTime<Utc>>, Vec<&'a Ledger>) {
+    let ledgers: Vec<&Ledger> = keys
+        .iter()
+        .zip(postings)
+        .filter(|(posted_to, _)| *posted_to == key)
+        .map(|(_, posting)| posting.ledger())
+        .collect();
+    let at = ledgers.iter().map(|ledger| ledger.booked_at()).min();
+    (at, ledgers)
+}
+
+// If one of the `(pocket, ledger)` legs retries an entry its pocket already
+// took under the same idempotency key, the totals `Storage::post` reports
+// for the retry: what the retried legs originally produced, and the
+// others' balance as it stands.
+pub(crate) fn replayed<'a>(
+    legs: impl Iterator<Item = (&'a Balance, &'a Ledger)>,
+) -> Result<Option<Vec<Decimal>>, StorageError> {
+    let mut retry = false;
+    let mut totals = vec![];
+    for (balance, ledger) in legs {
+        match balance.replayed(ledger).map_err(StorageError::Balance)? {
+            Some(total) => {
+                retry = true;
+                totals.push(total);
+            }
+            None => totals.push(balance.amount()),
+        }
+    }
+    Ok(retry.then_some(totals))
+}
+
+/// Persistence for account balances and their ledgers.
+///
+/// Business logic should be written against this trait rather than a
+/// concrete backend so stores can be swapped without touching it.
+///
+/// An account starts with one `Balance` in the currency it was inserted
+/// with. `open_pocket` adds balances in further currencies; the other
+/// methods only ever touch the primary one. Pockets aren't accounts: the
+/// account-level methods report `AccountNotExists` for a pocket's key.
+///
+/// Pockets are governed by their account: its status, policy and velocity
+/// limits apply to every posting on them, measured against the pocket's own
+/// balance and entries. An account can't open pockets unless it is active,
+/// nor be closed while any of its pockets holds funds.
+pub trait Storage {
+    fn insert(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError>;
+
+    fn get(&self, account_id: &str) -> Result<Balance, StorageError>;
+
+    /// Currency of the account's primary balance, without loading it.
+    fn currency(&self, account_id: &str) -> Result<String, StorageError>;
+
+    /// Replaces the stored balance. The replacement's status history must
+    /// extend the stored one, and a closed account can't be replaced.
+    fn update(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError>;
+
+    /// Returns every account id, sorted.
+    fn list(&self) -> Result<Vec<String>, StorageError>;
+
+    fn delete(&mut self, account_id: &str) -> Result<Balance, StorageError>;
+
+    /// Applies `ledger` to the account's balance atomically and returns the
+    /// new total.
+    fn append(&mut self, account_id: &str, ledger: Ledger) -> Result<Decimal, StorageError>;
+
+    /// Reserves `hold` on the account and returns the available balance
+    /// left.
+    fn place_hold(&mut self, account_id: &str, hold: Hold) -> Result<Decimal, StorageError>;
+
+    /// Settles all or part of a hold as a `Withdrawal` ledger and returns the
+    /// new ledger balance; see `Balance::capture`.
+    fn capture_hold(
+        &mut self,
+        account_id: &str,
+        hold_id: &str,
+        amount: Option<&str>,
+    ) -> Result<Decimal, StorageError>;
+
+    /// Drops a hold, returning its funds to the available balance.
+    fn release_hold(&mut self, account_id: &str, hold_id: &str) -> Result<Hold, StorageError>;
+
+    /// Moves the account to `status` and records the change with `reason`;
+    /// see `Balance::transition`.
+    fn set_status(
+        &mut self,
+        account_id: &str,
+        status: AccountStatus,
+        reason: &str,
+    ) -> Result<StatusChange, StorageError>;
+
+    /// Opens an empty `currency` pocket on the account. Deleting the account
+    /// deletes its pockets too.
+    fn open_pocket(&mut self, account_id: &str, currency: &str) -> Result<(), StorageError>;
+
+    /// Currency codes of every pocket of the account, including the one it
+    /// was inserted with, sorted.
+    fn pockets(&self, account_id: &str) -> Result<Vec<String>, StorageError>;
+
+    fn get_pocket(&self, account_id: &str, currency: &str) -> Result<Balance, StorageError>;
+
+    /// Like `append`, but posts to the account's `currency` pocket.
+    fn append_to_pocket(
+        &mut self,
+        account_id: &str,
+        currency: &str,
+        ledger: Ledger,
+    ) -> Result<Decimal, StorageError>;
+
+    /// Applies every posting of `transaction` to the account's pocket in
+    /// the posting's currency, or none of them.
+    ///
+    /// Postings are validated in order against the balances left by the
+    /// ones before them; a rejected posting, or currency rounding leaving
+    /// the transaction unbalanced, posts nothing. If a posting carries an
+    /// idempotency key its pocket already took for the same action and
+    /// amount, the transaction is a retry and nothing is posted either; the
+    /// retried posting then reports the total it originally produced.
+    fn post(&mut self, transaction: Transaction) -> Result<Posted, StorageError>;
+
+    /// Every balance in the store, pockets and internal accounts included,
+    /// with the totals per currency. Writes that bypass `post`, e.g. a
+    /// funded `insert` or a plain `append`, show up as discrepancies.
+    fn trial_balance(&self) -> Result<TrialBalance, StorageError>;
+
+    /// Every pocket of the account, read consistently.
+    fn wallet(&self, account_id: &str) -> Result<Wallet, StorageError>;
+
+    /// Converts `amount` out of the account's `from` pocket into its `to`
+    /// pocket, priced by `converter`.
+    ///
+    /// The `to` pocket is credited the quoted amount rounded down to its
+    /// minor units. The postings are those of `Transaction::conversion`, so
+    /// the FX internal accounts they go to are opened if needed. The
+    /// ledgers share the returned transfer id; if any is rejected, nothing
+    /// is posted.
+    fn convert<R: RateProvider>(
+        &mut self,
+        account_id: &str,
+        from: &str,
+        to: &str,
+        amount: &str,
+        converter: &Converter<R>,
+    ) -> Result<Conversion, StorageError>;
+}
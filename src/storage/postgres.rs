@@ -0,0 +1,1304 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use postgres::types::ToSql;
+use postgres::{Client, GenericClient, NoTls};
+use rust_decimal::Decimal;
+
+use super::{
+    check_account_balance, check_double_entry, check_new_account, check_pockets_closable,
+    pocket_key, replayed, touching, Storage, StorageError,
+};
+use crate::balance::{Balance, Controls};
+use crate::clock::{Clock, SystemClock};
+use crate::currency::Rounding;
+use crate::fx::{Conversion, Converter, FxError, RateProvider};
+use crate::hold::Hold;
+use crate::id::time_ordered;
+use crate::journal::{
+    check_balanced, conversion_accounts, InternalAccount, Posted, Transaction, TrialBalance,
+    TrialBalanceLine,
+};
+use crate::ledger::{Carried, KeyedEntry, Ledger, Ledgers, Links};
+use crate::limits::{Limit, VelocityLimits};
+use crate::policy::Policy;
+use crate::status::{AccountStatus, StatusChange};
+use crate::wallet::Wallet;
+
+// Embedded schema migrations, applied in order by `Postgres::connect`.
+// Entries written before booking timestamps existed are dated at the Unix
+// epoch. Accounts stored without their minor units look them up in the
+// currency registry. Each entry stores the balance it left and each account
+// its total, so writes needn't re-sum its history.
+const MIGRATIONS: &[(i32, &str)] = &[
+    (
+        1,
+        "CREATE TABLE accounts (
+        id TEXT PRIMARY KEY,
+        currency TEXT NOT NULL
+    );
+    CREATE TABLE ledger_entries (
+        seq BIGSERIAL PRIMARY KEY,
+        id TEXT NOT NULL,
+        account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+        action TEXT NOT NULL,
+        amount NUMERIC NOT NULL,
+        transfer_id TEXT,
+        UNIQUE (account_id, id)
+    );
+    CREATE INDEX ledger_entries_account_seq ON ledger_entries (account_id, seq);",
+    ),
+    (
+        2,
+        "ALTER TABLE ledger_entries
+            ADD COLUMN booked_at TIMESTAMPTZ NOT NULL DEFAULT 'epoch',
+            ADD COLUMN value_date DATE NOT NULL DEFAULT 'epoch';
+        ALTER TABLE ledger_entries
+            ALTER COLUMN booked_at DROP DEFAULT,
+            ALTER COLUMN value_date DROP DEFAULT;",
+    ),
+    (
+        3,
+        "ALTER TABLE ledger_entries ADD COLUMN idempotency_key TEXT;
+        CREATE UNIQUE INDEX ledger_entries_account_idempotency_key
+            ON ledger_entries (account_id, idempotency_key);",
+    ),
+    (4, "ALTER TABLE ledger_entries ADD COLUMN reverses TEXT;"),
+    (
+        5,
+        "CREATE TABLE holds (
+            seq BIGSERIAL PRIMARY KEY,
+            id TEXT NOT NULL,
+            account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+            amount NUMERIC NOT NULL,
+            placed_at TIMESTAMPTZ NOT NULL,
+            expires_at TIMESTAMPTZ,
+            UNIQUE (account_id, id)
+        );",
+    ),
+    (
+        6,
+        "ALTER TABLE accounts
+            ADD COLUMN policy TEXT NOT NULL DEFAULT 'no_overdraft',
+            ADD COLUMN policy_amount NUMERIC;",
+    ),
+    (
+        7,
+        "ALTER TABLE accounts
+            ADD COLUMN daily_max_amount NUMERIC,
+            ADD COLUMN daily_max_count BIGINT,
+            ADD COLUMN monthly_max_amount NUMERIC,
+            ADD COLUMN monthly_max_count BIGINT;",
+    ),
+    (
+        8,
+        "CREATE TABLE status_changes (
+            seq BIGSERIAL PRIMARY KEY,
+            account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+            from_status TEXT NOT NULL,
+            to_status TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            changed_at TIMESTAMPTZ NOT NULL
+        );",
+    ),
+    (
+        9,
+        "ALTER TABLE accounts ADD COLUMN pocket_of TEXT REFERENCES accounts (id) ON DELETE CASCADE;
+        CREATE UNIQUE INDEX accounts_pocket_currency ON accounts (pocket_of, currency);",
+    ),
+    (
+        10,
+        "ALTER TABLE accounts
+            ADD COLUMN minor_units INTEGER,
+            ADD COLUMN rounding TEXT NOT NULL DEFAULT 'reject';",
+    ),
+    (
+        11,
+        "ALTER TABLE accounts
+            ADD COLUMN total NUMERIC NOT NULL DEFAULT 0,
+            ADD COLUMN last_seq BIGINT;
+        ALTER TABLE ledger_entries ADD COLUMN total NUMERIC;
+        UPDATE ledger_entries SET total = running.total
+        FROM (
+            SELECT seq, SUM(amount) OVER (PARTITION BY account_id ORDER BY seq) AS total
+            FROM ledger_entries
+        ) running
+        WHERE ledger_entries.seq = running.seq;
+        UPDATE accounts SET total = latest.total, last_seq = latest.seq
+        FROM (
+            SELECT DISTINCT ON (account_id) account_id, seq, total
+            FROM ledger_entries ORDER BY account_id, seq DESC
+        ) latest
+        WHERE accounts.id = latest.account_id;
+        ALTER TABLE ledger_entries ALTER COLUMN total SET NOT NULL;
+        CREATE INDEX ledger_entries_account_booked_at ON ledger_entries (account_id, booked_at);
+        CREATE INDEX ledger_entries_account_reverses ON ledger_entries (account_id, reverses);",
+    ),
+];
+
+impl From<postgres::Error> for StorageError {
+    fn from(e: postgres::Error) -> Self {
+        StorageError::Backend(e.to_string())
+    }
+}
+
+/// PostgreSQL-backed storage.
+///
+/// Ledger appends lock the account row with `SELECT ... FOR UPDATE` before
+/// running `Balance::mutate`'s checks, so concurrent writers on other
+/// connections or processes are serialized per account. Writes touching
+/// several rows lock them all up front in id order.
+pub struct Postgres {
+    client: Mutex<Client>,
+    double_entry: bool,
+}
+
+impl Postgres {
+    pub fn connect(url: &str) -> Result<Postgres, StorageError> {
+        let mut client = Client::connect(url, NoTls)?;
+        migrate(&mut client)?;
+        Ok(Postgres {
+            client: Mutex::new(client),
+            double_entry: false,
+        })
+    }
+
+    /// Keeps the books balanced: amounts only change through `post` and
+    /// `convert`. Writes that would change a single balance, such as
+    /// `append`, capturing a hold or deleting a funded account, fail with
+    /// `JournalError::SingleSided`.
+    pub fn with_double_entry(mut self) -> Self {
+        self.double_entry = true;
+        self
+    }
+}
+
+fn migrate(client: &mut Client) -> Result<(), StorageError> {
+    client.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    )?;
+    let mut tx = client.transaction()?;
+    // Guards against two processes migrating the same database at once.
+    tx.execute("SELECT pg_advisory_xact_lock(7262736)", &[])?;
+    for (version, sql) in MIGRATIONS {
+        let applied = tx.query_opt(
+            "SELECT 1 FROM schema_migrations WHERE version = $1",
+            &[version],
+        )?;
+        if applied.is_none() {
+            tx.batch_execute(sql)?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version) VALUES ($1)",
+                &[version],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// The account's settings and stored total, without any entries, holds or
+// status changes, and the seq of its latest entry.
+fn load_settings(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    for_update: bool,
+) -> Result<(Balance, Decimal, Option<i64>), StorageError> {
+    let sql = if for_update {
+        "SELECT currency, policy, policy_amount,
+                daily_max_amount, daily_max_count, monthly_max_amount, monthly_max_count,
+                pocket_of, minor_units, rounding, total, last_seq
+         FROM accounts WHERE id = $1 FOR UPDATE"
+    } else {
+        "SELECT currency, policy, policy_amount,
+                daily_max_amount, daily_max_count, monthly_max_amount, monthly_max_count,
+                pocket_of, minor_units, rounding, total, last_seq
+         FROM accounts WHERE id = $1"
+    };
+    let row = client
+        .query_opt(sql, &[&account_id])?
+        .ok_or(StorageError::AccountNotExists)?;
+    let currency: String = row.get(0);
+    let policy: &str = row.get(1);
+    let policy = Policy::restore(policy, row.get(2))
+        .ok_or_else(|| StorageError::Backend(format!("invalid stored policy: {policy}")))?;
+    let limits = VelocityLimits {
+        daily: limit(row.get(3), row.get(4))?,
+        monthly: limit(row.get(5), row.get(6))?,
+    };
+    let rounding: &str = row.get(9);
+    let rounding = Rounding::restore(rounding)
+        .ok_or_else(|| StorageError::Backend(format!("invalid stored rounding: {rounding}")))?;
+    let balance = match row.get::<_, Option<i32>>(8) {
+        Some(minor_units) => Balance::restore_currency(&currency, minor_units as u32),
+        None => Balance::new(&currency).map_err(StorageError::Balance)?,
+    };
+    let balance = balance
+        .with_rounding(rounding)
+        .with_policy(policy)
+        .with_limits(limits)
+        .with_pocket_of(row.get(7));
+    Ok((balance, row.get(10), row.get(11)))
+}
+
+fn load(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    for_update: bool,
+) -> Result<Balance, StorageError> {
+    let (mut balance, ..) = load_settings(client, account_id, for_update)?;
+    for ledger in entries(client, "account_id = $1", &[&account_id])? {
+        balance
+            .restore_ledger(ledger)
+            .map_err(StorageError::Ledger)?;
+    }
+    balance.restore_holds(holds(client, account_id)?);
+    balance.restore_status_history(status_changes(client, account_id, false)?);
+    Ok(balance)
+}
+
+// The account as a write booking entries at `at` is checked against, without
+// reading back its whole history: only the entries booked within the longest
+// velocity window before `at` are loaded, on top of the balance they started
+// from. Whatever `ledgers` refer to further back, by id, idempotency key or
+// as the entry they reverse, is looked up by index and carried forward. With
+// `at` unset, no entries are loaded. Only the latest status change is. The
+// caller locks the row.
+fn load_recent(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    at: Option<DateTime<Utc>>,
+    ledgers: &[&Ledger],
+) -> Result<Balance, StorageError> {
+    let (mut balance, total, last_seq) = load_settings(client, account_id, false)?;
+    let start: Option<i64> = match at {
+        Some(at) => client
+            .query_one(
+                "SELECT MIN(seq) FROM ledger_entries WHERE account_id = $1 AND booked_at > $2",
+                &[&account_id, &(at - VelocityLimits::longest_window())],
+            )?
+            .get(0),
+        None => None,
+    };
+    let opening = match (start, last_seq) {
+        (Some(start), _) => client
+            .query_opt(
+                "SELECT id, total FROM ledger_entries
+                 WHERE account_id = $1 AND seq < $2 ORDER BY seq DESC LIMIT 1",
+                &[&account_id, &start],
+            )?
+            .map(|row| (row.get(0), row.get(1))),
+        (None, Some(last_seq)) => {
+            let row =
+                client.query_one("SELECT id FROM ledger_entries WHERE seq = $1", &[&last_seq])?;
+            Some((row.get(0), total))
+        }
+        (None, None) => None,
+    };
+    let mut carried = Carried::default();
+    if let Some((id, total)) = opening {
+        carried.opening = total;
+        carried.opening_id = Some(id);
+    }
+    let before = start.unwrap_or(i64::MAX);
+    for ledger in ledgers {
+        for id in [Some(ledger.id()), ledger.reverses()].into_iter().flatten() {
+            carry_entry(client, account_id, id, before, &mut carried)?;
+        }
+        if let Some(key) = ledger.idempotency_key() {
+            carry_key(client, account_id, key, before, &mut carried)?;
+        }
+    }
+
+    let mut recent = Ledgers::carried_forward(carried);
+    if let Some(start) = start {
+        let filter = "account_id = $1 AND seq >= $2";
+        for ledger in entries(client, filter, &[&account_id, &start])? {
+            recent.add(ledger).map_err(StorageError::Ledger)?;
+        }
+    }
+    balance.restore_ledgers(recent);
+    balance.restore_holds(holds(client, account_id)?);
+    balance.restore_status_history(status_changes(client, account_id, true)?);
+    Ok(balance)
+}
+
+// Carries forward the entry `id` posted before seq `before`, with how much
+// was reversed of it by then, unless it is a reversal itself.
+fn carry_entry(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    id: &str,
+    before: i64,
+    carried: &mut Carried,
+) -> Result<(), StorageError> {
+    let row = client.query_opt(
+        "SELECT amount,
+                (SELECT SUM(ABS(amount)) FROM ledger_entries
+                 WHERE account_id = $1 AND reverses = $2 AND seq < $3)
+         FROM ledger_entries
+         WHERE account_id = $1 AND id = $2 AND seq < $3 AND reverses IS NULL",
+        &[&account_id, &id, &before],
+    )?;
+    let Some(row) = row else {
+        return Ok(());
+    };
+    carried.reversible.insert(id.to_string(), row.get(0));
+    if let Some(reversed) = row.get::<_, Option<Decimal>>(1) {
+        carried.reversed.insert(id.to_string(), reversed);
+    }
+    Ok(())
+}
+
+// Carries forward the entry posted under idempotency `key` before seq
+// `before`.
+fn carry_key(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    key: &str,
+    before: i64,
+    carried: &mut Carried,
+) -> Result<(), StorageError> {
+    let row = client.query_opt(
+        "SELECT id, action, amount, total FROM ledger_entries
+         WHERE account_id = $1 AND idempotency_key = $2 AND seq < $3",
+        &[&account_id, &key, &before],
+    )?;
+    if let Some(row) = row {
+        let entry = KeyedEntry {
+            ledger_id: row.get(0),
+            action: row
+                .get::<_, &str>(1)
+                .parse()
+                .map_err(StorageError::Ledger)?,
+            amount: row.get(2),
+            total: row.get(3),
+        };
+        carried.keys.insert(key.to_string(), entry);
+    }
+    Ok(())
+}
+
+// The entries matching `filter`, in posting order.
+fn entries(
+    client: &mut impl GenericClient,
+    filter: &str,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<Vec<Ledger>, StorageError> {
+    let rows = client.query(
+        &format!(
+            "SELECT id, action, amount, booked_at, value_date,
+                    transfer_id, idempotency_key, reverses
+             FROM ledger_entries
+             WHERE {filter} ORDER BY seq"
+        ),
+        params,
+    )?;
+    rows.iter()
+        .map(|row| {
+            let action = row
+                .get::<_, &str>(1)
+                .parse()
+                .map_err(StorageError::Ledger)?;
+            let links = Links {
+                transfer_id: row.get(5),
+                idempotency_key: row.get(6),
+                reverses: row.get(7),
+            };
+            Ok(Ledger::restore(
+                row.get(0),
+                action,
+                row.get(2),
+                row.get(3),
+                row.get(4),
+                links,
+            ))
+        })
+        .collect()
+}
+
+fn holds(client: &mut impl GenericClient, account_id: &str) -> Result<Vec<Hold>, StorageError> {
+    let rows = client.query(
+        "SELECT id, amount, placed_at, expires_at FROM holds
+         WHERE account_id = $1 ORDER BY seq",
+        &[&account_id],
+    )?;
+    Ok(rows
+        .iter()
+        .map(|row| Hold::restore(row.get(0), row.get(1), row.get(2), row.get(3)))
+        .collect())
+}
+
+// The account's status changes in order, or only the latest.
+fn status_changes(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    latest: bool,
+) -> Result<Vec<StatusChange>, StorageError> {
+    let sql = if latest {
+        "SELECT from_status, to_status, reason, changed_at FROM status_changes
+         WHERE account_id = $1 ORDER BY seq DESC LIMIT 1"
+    } else {
+        "SELECT from_status, to_status, reason, changed_at FROM status_changes
+         WHERE account_id = $1 ORDER BY seq"
+    };
+    let rows = client.query(sql, &[&account_id])?;
+    rows.iter()
+        .map(|row| {
+            Ok(StatusChange::restore(
+                status(row.get(0))?,
+                status(row.get(1))?,
+                row.get(2),
+                row.get(3),
+            ))
+        })
+        .collect()
+}
+
+fn status(name: &str) -> Result<AccountStatus, StorageError> {
+    AccountStatus::restore(name)
+        .ok_or_else(|| StorageError::Backend(format!("invalid stored status: {name}")))
+}
+
+fn limit(max_amount: Option<Decimal>, max_count: Option<i64>) -> Result<Limit, StorageError> {
+    let max_count = max_count
+        .map(u32::try_from)
+        .transpose()
+        .map_err(|e| StorageError::Backend(format!("invalid stored limit: {e}")))?;
+    Ok(Limit {
+        max_amount,
+        max_count,
+    })
+}
+
+// Values for the `accounts` columns, from `id` through `rounding`.
+struct AccountParams<'a> {
+    account_id: &'a str,
+    currency: &'a str,
+    policy: &'static str,
+    policy_amount: Option<Decimal>,
+    daily_max_amount: Option<Decimal>,
+    daily_max_count: Option<i64>,
+    monthly_max_amount: Option<Decimal>,
+    monthly_max_count: Option<i64>,
+    pocket_of: Option<&'a str>,
+    minor_units: i32,
+    rounding: &'static str,
+}
+
+impl AccountParams<'_> {
+    fn as_refs(&self) -> [&(dyn ToSql + Sync); 11] {
+        [
+            &self.account_id,
+            &self.currency,
+            &self.policy,
+            &self.policy_amount,
+            &self.daily_max_amount,
+            &self.daily_max_count,
+            &self.monthly_max_amount,
+            &self.monthly_max_count,
+            &self.pocket_of,
+            &self.minor_units,
+            &self.rounding,
+        ]
+    }
+}
+
+fn account_params<'a>(account_id: &'a str, balance: &'a Balance) -> AccountParams<'a> {
+    let (policy, limits) = (balance.policy(), balance.limits());
+    AccountParams {
+        account_id,
+        currency: &balance.currency,
+        policy: policy.as_str(),
+        policy_amount: policy.amount(),
+        daily_max_amount: limits.daily.max_amount,
+        daily_max_count: limits.daily.max_count.map(i64::from),
+        monthly_max_amount: limits.monthly.max_amount,
+        monthly_max_count: limits.monthly.max_count.map(i64::from),
+        pocket_of: balance.pocket_of(),
+        minor_units: balance.minor_units() as i32,
+        rounding: balance.rounding().as_str(),
+    }
+}
+
+// The account `account_id`, unless it doesn't exist or is itself a pocket.
+fn load_account(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    for_update: bool,
+) -> Result<Balance, StorageError> {
+    match load(client, account_id, for_update)? {
+        balance if balance.pocket_of().is_some() => Err(StorageError::AccountNotExists),
+        balance => Ok(balance),
+    }
+}
+
+// Currency of the account `account_id`, unless it doesn't exist or is itself
+// a pocket.
+fn owner_currency(
+    client: &mut impl GenericClient,
+    account_id: &str,
+) -> Result<String, StorageError> {
+    let row = client
+        .query_opt(
+            "SELECT currency FROM accounts WHERE id = $1 AND pocket_of IS NULL",
+            &[&account_id],
+        )?
+        .ok_or(StorageError::AccountNotExists)?;
+    Ok(row.get(0))
+}
+
+// Id of the row holding the account's `currency` pocket.
+fn pocket(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    currency: &str,
+) -> Result<String, StorageError> {
+    let currency = currency.to_uppercase();
+    if owner_currency(client, account_id)? == currency {
+        return Ok(account_id.to_string());
+    }
+    let row = client
+        .query_opt(
+            "SELECT id FROM accounts WHERE pocket_of = $1 AND currency = $2",
+            &[&account_id, &currency],
+        )?
+        .ok_or(StorageError::PocketNotExists)?;
+    Ok(row.get(0))
+}
+
+// Locks the rows of `keys` and of the accounts owning any pockets among
+// them, in id order, so writers whose rows overlap queue up instead of
+// deadlocking.
+fn lock(client: &mut impl GenericClient, keys: &[String]) -> Result<(), StorageError> {
+    client.query(
+        "SELECT id FROM accounts
+         WHERE id = ANY($1)
+            OR id IN (SELECT pocket_of FROM accounts WHERE id = ANY($1))
+         ORDER BY id FOR UPDATE",
+        &[&keys],
+    )?;
+    Ok(())
+}
+
+// The controls postings on `balance` are checked against: its account's if
+// it is a pocket.
+fn governing(client: &mut impl GenericClient, balance: &Balance) -> Result<Controls, StorageError> {
+    match balance.pocket_of() {
+        Some(account_id) => Ok(load_recent(client, account_id, None, &[])?.controls()),
+        None => Ok(balance.controls()),
+    }
+}
+
+// Amounts of the account's pockets other than its primary balance.
+fn pocket_amounts(
+    client: &mut impl GenericClient,
+    account_id: &str,
+) -> Result<Vec<Decimal>, StorageError> {
+    let rows = client.query(
+        "SELECT total FROM accounts WHERE pocket_of = $1",
+        &[&account_id],
+    )?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// Validates and inserts `ledger`, returning the new total. The caller
+// commits.
+fn post(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    ledger: Ledger,
+) -> Result<Decimal, StorageError> {
+    lock(client, &[account_id.to_string()])?;
+    let mut balance = load_recent(client, account_id, Some(ledger.booked_at()), &[&ledger])?;
+    if let Some(total) = balance.replayed(&ledger).map_err(StorageError::Balance)? {
+        return Ok(total);
+    }
+    let controls = governing(client, &balance)?;
+    let ledger = balance
+        .prepare_under(ledger, controls)
+        .map_err(StorageError::Balance)?;
+    let total = balance.commit(ledger);
+    insert_ledger(
+        client,
+        account_id,
+        balance.ledgers().entries().last().unwrap(),
+        total,
+    )?;
+    Ok(total)
+}
+
+// Validates the postings of `transaction` in order against the balances
+// left by the ones before them and inserts them, tagged with the
+// transaction id; see `Storage::post`. The caller commits.
+fn post_transaction(
+    client: &mut impl GenericClient,
+    transaction: Transaction,
+) -> Result<Posted, StorageError> {
+    let (transaction_id, postings) = transaction.into_parts();
+    let keys = postings
+        .iter()
+        .map(|posting| pocket(client, posting.account_id(), posting.currency()))
+        .collect::<Result<Vec<_>, _>>()?;
+    lock(client, &keys)?;
+    let mut touched: HashMap<String, Balance> = HashMap::new();
+    for key in &keys {
+        if let Entry::Vacant(entry) = touched.entry(key.clone()) {
+            let (at, ledgers) = touching(key, &keys, &postings);
+            entry.insert(load_recent(client, key, at, &ledgers)?);
+        }
+    }
+    let legs = keys.iter().zip(&postings);
+    if let Some(totals) = replayed(legs.map(|(key, posting)| (&touched[key], posting.ledger())))? {
+        return Ok(Posted {
+            transaction_id,
+            totals,
+        });
+    }
+
+    let transfer_id = Arc::new(transaction_id.clone());
+    let mut legs = Vec::with_capacity(postings.len());
+    let mut totals = Vec::with_capacity(postings.len());
+    for (key, posting) in keys.into_iter().zip(postings) {
+        let (_, currency, ledger) = posting.into_parts();
+        let controls = governing(client, &touched[&key])?;
+        let balance = touched.get_mut(&key).unwrap();
+        let ledger = balance
+            .prepare_under(ledger.with_transfer_id(transfer_id.clone()), controls)
+            .map_err(StorageError::Balance)?;
+        legs.push((currency, ledger.amount()));
+        let total = balance.commit(ledger);
+        insert_ledger(
+            client,
+            &key,
+            balance.ledgers().entries().last().unwrap(),
+            total,
+        )?;
+        totals.push(total);
+    }
+    check_balanced(
+        legs.iter()
+            .map(|(currency, amount)| (currency.as_str(), *amount)),
+    )
+    .map_err(StorageError::Journal)?;
+    Ok(Posted {
+        transaction_id,
+        totals,
+    })
+}
+
+// Inserts the internal account in `currency` unless it already exists.
+fn open_internal(
+    client: &mut impl GenericClient,
+    account: InternalAccount,
+    currency: &str,
+) -> Result<(), StorageError> {
+    let balance = account.balance(currency).map_err(StorageError::Balance)?;
+    let account_id = account.account_id(currency);
+    client.execute(
+        "INSERT INTO accounts
+            (id, currency, policy, policy_amount,
+             daily_max_amount, daily_max_count, monthly_max_amount, monthly_max_count,
+             pocket_of, minor_units, rounding)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT DO NOTHING",
+        &account_params(&account_id, &balance).as_refs(),
+    )?;
+    Ok(())
+}
+
+// Inserts `ledger`, which left the account at `total`, and moves the
+// account's stored total on to it.
+fn insert_ledger(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    ledger: &Ledger,
+    total: Decimal,
+) -> Result<(), StorageError> {
+    client.execute(
+        "WITH entry AS (
+            INSERT INTO ledger_entries
+                (id, account_id, action, amount, transfer_id, booked_at, value_date,
+                 idempotency_key, reverses, total)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING seq
+        )
+        UPDATE accounts SET total = $10, last_seq = entry.seq FROM entry WHERE id = $2",
+        &[
+            &ledger.id(),
+            &account_id,
+            &ledger.action().as_str(),
+            &ledger.amount(),
+            &ledger.transfer_id(),
+            &ledger.booked_at(),
+            &ledger.value_date(),
+            &ledger.idempotency_key(),
+            &ledger.reverses(),
+            &total,
+        ],
+    )?;
+    Ok(())
+}
+
+// Inserts every entry of `ledgers`, as a new account history.
+fn insert_ledgers(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    ledgers: &Ledgers,
+) -> Result<(), StorageError> {
+    let mut total = Decimal::ZERO;
+    for ledger in ledgers.entries() {
+        total += ledger.amount();
+        insert_ledger(client, account_id, ledger, total)?;
+    }
+    Ok(())
+}
+
+fn insert_hold(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    hold: &Hold,
+) -> Result<(), StorageError> {
+    client.execute(
+        "INSERT INTO holds (id, account_id, amount, placed_at, expires_at)
+         VALUES ($1, $2, $3, $4, $5)",
+        &[
+            &hold.id(),
+            &account_id,
+            &hold.amount(),
+            &hold.placed_at(),
+            &hold.expires_at(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_status_change(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    change: &StatusChange,
+) -> Result<(), StorageError> {
+    client.execute(
+        "INSERT INTO status_changes (account_id, from_status, to_status, reason, changed_at)
+         VALUES ($1, $2, $3, $4, $5)",
+        &[
+            &account_id,
+            &change.from().as_str(),
+            &change.to().as_str(),
+            &change.reason(),
+            &change.changed_at(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn delete_hold(
+    client: &mut impl GenericClient,
+    account_id: &str,
+    hold_id: &str,
+) -> Result<(), StorageError> {
+    client.execute(
+        "DELETE FROM holds WHERE account_id = $1 AND id = $2",
+        &[&account_id, &hold_id],
+    )?;
+    Ok(())
+}
+
+impl Storage for Postgres {
+    fn insert(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError> {
+        check_new_account(account_id, &balance)?;
+        check_double_entry(self.double_entry, balance.amount())?;
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        let inserted = tx.execute(
+            "INSERT INTO accounts
+                (id, currency, policy, policy_amount,
+                 daily_max_amount, daily_max_count, monthly_max_amount, monthly_max_count,
+                 pocket_of, minor_units, rounding)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT DO NOTHING",
+            &account_params(account_id, &balance).as_refs(),
+        )?;
+        if inserted == 0 {
+            return Err(StorageError::AccountAlreadyExists);
+        }
+        insert_ledgers(&mut tx, account_id, balance.ledgers())?;
+        for hold in balance.holds() {
+            insert_hold(&mut tx, account_id, hold)?;
+        }
+        for change in balance.status_history() {
+            insert_status_change(&mut tx, account_id, change)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get(&self, account_id: &str) -> Result<Balance, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        // Read the account and its ledgers from a single snapshot.
+        let mut tx = client
+            .build_transaction()
+            .isolation_level(postgres::IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()?;
+        let balance = load_account(&mut tx, account_id, false)?;
+        tx.commit()?;
+        Ok(balance)
+    }
+
+    fn currency(&self, account_id: &str) -> Result<String, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        owner_currency(&mut *client, account_id)
+    }
+
+    fn update(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError> {
+        check_account_balance(&balance)?;
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        let stored = load_account(&mut tx, account_id, true)?;
+        balance
+            .check_replaces(&stored)
+            .map_err(StorageError::Balance)?;
+        check_double_entry(self.double_entry, balance.amount() - stored.amount())?;
+        check_pockets_closable(balance.status(), pocket_amounts(&mut tx, account_id)?)?;
+        tx.execute(
+            "UPDATE accounts
+             SET currency = $2, policy = $3, policy_amount = $4,
+                 daily_max_amount = $5, daily_max_count = $6,
+                 monthly_max_amount = $7, monthly_max_count = $8, pocket_of = $9,
+                 minor_units = $10, rounding = $11, total = 0, last_seq = NULL
+             WHERE id = $1",
+            &account_params(account_id, &balance).as_refs(),
+        )?;
+        tx.execute(
+            "DELETE FROM ledger_entries WHERE account_id = $1",
+            &[&account_id],
+        )?;
+        tx.execute("DELETE FROM holds WHERE account_id = $1", &[&account_id])?;
+        tx.execute(
+            "DELETE FROM status_changes WHERE account_id = $1",
+            &[&account_id],
+        )?;
+        insert_ledgers(&mut tx, account_id, balance.ledgers())?;
+        for hold in balance.holds() {
+            insert_hold(&mut tx, account_id, hold)?;
+        }
+        for change in balance.status_history() {
+            insert_status_change(&mut tx, account_id, change)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let rows = client.query(
+            "SELECT id FROM accounts WHERE pocket_of IS NULL ORDER BY id",
+            &[],
+        )?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn delete(&mut self, account_id: &str) -> Result<Balance, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        let balance = load_account(&mut tx, account_id, true)?;
+        let pockets = pocket_amounts(&mut tx, account_id)?;
+        for amount in pockets.into_iter().chain([balance.amount()]) {
+            check_double_entry(self.double_entry, amount)?;
+        }
+        tx.execute("DELETE FROM accounts WHERE id = $1", &[&account_id])?;
+        tx.commit()?;
+        Ok(balance)
+    }
+
+    fn append(&mut self, account_id: &str, ledger: Ledger) -> Result<Decimal, StorageError> {
+        check_double_entry(self.double_entry, ledger.amount())?;
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        owner_currency(&mut tx, account_id)?;
+        let total = post(&mut tx, account_id, ledger)?;
+        tx.commit()?;
+        Ok(total)
+    }
+
+    fn place_hold(&mut self, account_id: &str, hold: Hold) -> Result<Decimal, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        lock(&mut tx, &[account_id.to_string()])?;
+        owner_currency(&mut tx, account_id)?;
+        let mut balance = load_recent(&mut tx, account_id, None, &[])?;
+        let available = balance.place_hold(hold).map_err(StorageError::Balance)?;
+        insert_hold(&mut tx, account_id, balance.holds().last().unwrap())?;
+        tx.commit()?;
+        Ok(available)
+    }
+
+    fn capture_hold(
+        &mut self,
+        account_id: &str,
+        hold_id: &str,
+        amount: Option<&str>,
+    ) -> Result<Decimal, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        lock(&mut tx, &[account_id.to_string()])?;
+        owner_currency(&mut tx, account_id)?;
+        let mut balance = load_recent(&mut tx, account_id, Some(SystemClock.now()), &[])?;
+        let before = balance.amount();
+        let total = balance
+            .capture(hold_id, amount, time_ordered(), &SystemClock)
+            .map_err(StorageError::Balance)?;
+        check_double_entry(self.double_entry, total - before)?;
+        delete_hold(&mut tx, account_id, hold_id)?;
+        let captured = balance.ledgers().entries().last().unwrap();
+        insert_ledger(&mut tx, account_id, captured, total)?;
+        tx.commit()?;
+        Ok(total)
+    }
+
+    fn release_hold(&mut self, account_id: &str, hold_id: &str) -> Result<Hold, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        lock(&mut tx, &[account_id.to_string()])?;
+        owner_currency(&mut tx, account_id)?;
+        let mut balance = load_recent(&mut tx, account_id, None, &[])?;
+        let hold = balance.release(hold_id).map_err(StorageError::Balance)?;
+        delete_hold(&mut tx, account_id, hold_id)?;
+        tx.commit()?;
+        Ok(hold)
+    }
+
+    fn set_status(
+        &mut self,
+        account_id: &str,
+        status: AccountStatus,
+        reason: &str,
+    ) -> Result<StatusChange, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        lock(&mut tx, &[account_id.to_string()])?;
+        owner_currency(&mut tx, account_id)?;
+        let mut balance = load_recent(&mut tx, account_id, None, &[])?;
+        check_pockets_closable(status, pocket_amounts(&mut tx, account_id)?)?;
+        let change = balance
+            .transition(status, reason)
+            .map_err(StorageError::Balance)?;
+        insert_status_change(&mut tx, account_id, &change)?;
+        tx.commit()?;
+        Ok(change)
+    }
+
+    fn open_pocket(&mut self, account_id: &str, currency: &str) -> Result<(), StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        lock(&mut tx, &[account_id.to_string()])?;
+        owner_currency(&mut tx, account_id)?;
+        let primary = load_recent(&mut tx, account_id, None, &[])?;
+        primary
+            .controls()
+            .check_status(true)
+            .map_err(StorageError::Balance)?;
+        let balance = Balance::new(currency)
+            .map_err(StorageError::Balance)?
+            .with_pocket_of(Some(account_id.to_string()));
+        if primary.currency == balance.currency {
+            return Err(StorageError::PocketAlreadyExists);
+        }
+        let key = pocket_key(account_id, &balance.currency);
+        let inserted = tx.execute(
+            "INSERT INTO accounts
+                (id, currency, policy, policy_amount,
+                 daily_max_amount, daily_max_count, monthly_max_amount, monthly_max_count,
+                 pocket_of, minor_units, rounding)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT DO NOTHING",
+            &account_params(&key, &balance).as_refs(),
+        )?;
+        if inserted == 0 {
+            return Err(StorageError::PocketAlreadyExists);
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn pockets(&self, account_id: &str) -> Result<Vec<String>, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        owner_currency(&mut tx, account_id)?;
+        let rows = tx.query(
+            "SELECT currency FROM accounts WHERE id = $1 OR pocket_of = $1 ORDER BY currency",
+            &[&account_id],
+        )?;
+        tx.commit()?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn get_pocket(&self, account_id: &str, currency: &str) -> Result<Balance, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client
+            .build_transaction()
+            .isolation_level(postgres::IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()?;
+        let key = pocket(&mut tx, account_id, currency)?;
+        let balance = load(&mut tx, &key, false)?;
+        tx.commit()?;
+        Ok(balance)
+    }
+
+    fn append_to_pocket(
+        &mut self,
+        account_id: &str,
+        currency: &str,
+        ledger: Ledger,
+    ) -> Result<Decimal, StorageError> {
+        check_double_entry(self.double_entry, ledger.amount())?;
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        let key = pocket(&mut tx, account_id, currency)?;
+        let total = post(&mut tx, &key, ledger)?;
+        tx.commit()?;
+        Ok(total)
+    }
+
+    fn post(&mut self, transaction: Transaction) -> Result<Posted, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        let posted = post_transaction(&mut tx, transaction)?;
+        tx.commit()?;
+        Ok(posted)
+    }
+
+    fn trial_balance(&self) -> Result<TrialBalance, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        // Total every balance from a single snapshot.
+        let mut tx = client
+            .build_transaction()
+            .isolation_level(postgres::IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()?;
+        let rows = tx.query("SELECT id, pocket_of, currency, total FROM accounts", &[])?;
+        let lines = rows
+            .iter()
+            .map(|row| TrialBalanceLine {
+                account_id: row.get::<_, Option<String>>(1).unwrap_or(row.get(0)),
+                currency: row.get(2),
+                amount: row.get(3),
+            })
+            .collect();
+        tx.commit()?;
+        Ok(TrialBalance::new(lines))
+    }
+
+    fn wallet(&self, account_id: &str) -> Result<Wallet, StorageError> {
+        let mut client = self.client.lock().unwrap();
+        // Value every pocket from a single snapshot.
+        let mut tx = client
+            .build_transaction()
+            .isolation_level(postgres::IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()?;
+        owner_currency(&mut tx, account_id)?;
+        let rows = tx.query(
+            "SELECT id FROM accounts WHERE id = $1 OR pocket_of = $1",
+            &[&account_id],
+        )?;
+        let pockets = rows
+            .iter()
+            .map(|row| load(&mut tx, row.get(0), false))
+            .collect::<Result<_, _>>()?;
+        tx.commit()?;
+        Ok(Wallet::new(account_id, pockets))
+    }
+
+    fn convert<R: RateProvider>(
+        &mut self,
+        account_id: &str,
+        from: &str,
+        to: &str,
+        amount: &str,
+        converter: &Converter<R>,
+    ) -> Result<Conversion, StorageError> {
+        let debit = Ledger::conversion_debit(amount, time_ordered(), &SystemClock)
+            .map_err(StorageError::Ledger)?;
+
+        let mut client = self.client.lock().unwrap();
+        let mut tx = client.transaction()?;
+        let source = pocket(&mut tx, account_id, from)?;
+        let target = pocket(&mut tx, account_id, to)?;
+        if source == target {
+            return Err(StorageError::Fx(FxError::SameCurrency));
+        }
+        // Lock the pockets and the internal accounts up front, all in one
+        // sorted pass.
+        let (from, to) = (from.to_uppercase(), to.to_uppercase());
+        let mut keys = vec![source.clone(), target.clone()];
+        for (account, currency) in conversion_accounts(&from, &to) {
+            open_internal(&mut tx, account, currency)?;
+            keys.push(account.account_id(currency));
+        }
+        lock(&mut tx, &keys)?;
+        let source = load_recent(&mut tx, &source, Some(debit.booked_at()), &[&debit])?;
+        let target = load_recent(&mut tx, &target, None, &[])?;
+        // Priced on what the source would actually be debited.
+        let controls = governing(&mut tx, &source)?;
+        let debit = source
+            .prepare_under(debit, controls)
+            .map_err(StorageError::Balance)?;
+        let quote = converter
+            .quote(&source.currency, &target.currency, debit.amount())
+            .map_err(StorageError::Fx)?;
+        let posted = post_transaction(
+            &mut tx,
+            Transaction::conversion(account_id, &quote, &SystemClock),
+        )?;
+        tx.commit()?;
+        Ok(Conversion {
+            transfer_id: posted.transaction_id,
+            quote,
+        })
+    }
+}
+
+// These tests need a running server, so they are ignored by default. Run them
+// with `cargo test --features postgres -- --ignored` and
+// RUST_PG_TEST_DATABASE_URL set, e.g.
+// `postgres://postgres@127.0.0.1:5432/postgres`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fx::StaticRates;
+    use crate::journal::JournalError;
+    use crate::ledger::Action;
+    use crate::storage::conformance::storage_conformance;
+    use crate::storage::test_util::generate_random_string;
+    use rust_decimal_macros::dec;
+
+    fn connect() -> Postgres {
+        let url = std::env::var("RUST_PG_TEST_DATABASE_URL")
+            .expect("RUST_PG_TEST_DATABASE_URL must point at a PostgreSQL database");
+        Postgres::connect(&url).unwrap()
+    }
+
+    storage_conformance!(connect(), #[ignore = "needs RUST_PG_TEST_DATABASE_URL"]);
+
+    fn deposit(amount: &str) -> Ledger {
+        Ledger::new(Action::Deposit(amount.to_string()), time_ordered()).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs RUST_PG_TEST_DATABASE_URL"]
+    fn test_ledger_ids_round_trip() {
+        let mut storage = connect();
+        let id = format!("account_{}", generate_random_string(12));
+        storage.insert(&id, Balance::new("USD").unwrap()).unwrap();
+        let ledger = Ledger::new(Action::Deposit("0.1".to_string()), time_ordered()).unwrap();
+        storage.append(&id, ledger.clone()).unwrap();
+
+        let stored = storage.get(&id).unwrap();
+        assert_eq!(stored.ledgers().entries(), [ledger]);
+    }
+
+    #[test]
+    #[ignore = "needs RUST_PG_TEST_DATABASE_URL"]
+    fn test_append_concurrently_from_many_connections() {
+        let mut storage = connect();
+        let id = format!("account_{}", generate_random_string(12));
+        storage.insert(&id, Balance::new("USD").unwrap()).unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let id = id.clone();
+                let mut storage = connect();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        let ledger =
+                            Ledger::new(Action::Deposit("1.5".to_string()), time_ordered())
+                                .unwrap();
+                        storage.append(&id, ledger).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(storage.get(&id).unwrap().amount(), dec!(150));
+    }
+
+    #[test]
+    #[ignore = "needs RUST_PG_TEST_DATABASE_URL"]
+    fn test_convert_concurrently_in_opposite_directions() {
+        let mut storage = connect();
+        let id = format!("account_{}", generate_random_string(12));
+        storage.insert(&id, Balance::new("EUR").unwrap()).unwrap();
+        storage.open_pocket(&id, "USD").unwrap();
+        storage
+            .append_to_pocket(&id, "EUR", deposit("1000"))
+            .unwrap();
+        storage
+            .append_to_pocket(&id, "USD", deposit("1000"))
+            .unwrap();
+
+        let handles: Vec<_> = [("EUR", "USD"), ("USD", "EUR")]
+            .into_iter()
+            .flat_map(|direction| [direction; 2])
+            .map(|(from, to)| {
+                let id = id.clone();
+                let mut storage = connect();
+                std::thread::spawn(move || {
+                    let rates = StaticRates::new().with_rate("EUR", "USD", "1.25").unwrap();
+                    let converter = Converter::new(rates);
+                    for _ in 0..20 {
+                        storage.convert(&id, from, to, "1", &converter).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // 40 EUR went out at 1.25 and 40 USD came back at 0.8.
+        let wallet = storage.wallet(&id).unwrap();
+        assert_eq!(wallet.pocket("EUR").unwrap().amount(), dec!(992));
+        assert_eq!(wallet.pocket("USD").unwrap().amount(), dec!(1010));
+    }
+
+    #[test]
+    #[ignore = "needs RUST_PG_TEST_DATABASE_URL"]
+    fn test_double_entry_refuses_single_sided_writes() {
+        let mut storage = connect().with_double_entry();
+        let id = format!("account_{}", generate_random_string(12));
+        storage.insert(&id, Balance::new("USD").unwrap()).unwrap();
+        let cash = InternalAccount::Cash.open(&mut storage, "USD").unwrap();
+        let cash_before = storage.get(&cash).unwrap().amount();
+        storage
+            .post(Transaction::deposit(&id, "USD", "100").unwrap())
+            .unwrap();
+        assert_eq!(
+            storage.get(&cash).unwrap().amount() - cash_before,
+            dec!(-100)
+        );
+
+        let single_sided = StorageError::Journal(JournalError::SingleSided);
+        let deposit = Ledger::new(Action::Deposit("1".to_string()), time_ordered()).unwrap();
+        assert_eq!(storage.append(&id, deposit).unwrap_err(), single_sided);
+        let hold = Hold::new("10", time_ordered()).unwrap();
+        let hold_id = hold.id().to_string();
+        storage.place_hold(&id, hold).unwrap();
+        assert_eq!(
+            storage.capture_hold(&id, &hold_id, None).unwrap_err(),
+            single_sided
+        );
+        let funded = storage.get(&id).unwrap();
+        let other = format!("account_{}", generate_random_string(12));
+        assert_eq!(storage.insert(&other, funded).unwrap_err(), single_sided);
+        assert_eq!(storage.delete(&id).unwrap_err(), single_sided);
+        assert_eq!(storage.get(&id).unwrap().amount(), dec!(100));
+    }
+}
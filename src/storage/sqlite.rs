@@ -0,0 +1,1270 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, TransactionBehavior};
+use rust_decimal::Decimal;
+
+use super::{
+    check_account_balance, check_double_entry, check_new_account, check_pockets_closable,
+    pocket_key, replayed, touching, Storage, StorageError,
+};
+use crate::balance::{Balance, Controls};
+use crate::clock::{Clock, SystemClock};
+use crate::currency::Rounding;
+use crate::fx::{Conversion, Converter, FxError, RateProvider};
+use crate::hold::Hold;
+use crate::id::time_ordered;
+use crate::journal::{
+    check_balanced, conversion_accounts, InternalAccount, Posted, Transaction, TrialBalance,
+    TrialBalanceLine,
+};
+use crate::ledger::{Carried, KeyedEntry, Ledger, Ledgers, Links};
+use crate::limits::{Limit, VelocityLimits};
+use crate::policy::Policy;
+use crate::status::{AccountStatus, StatusChange};
+use crate::wallet::Wallet;
+
+// Schema versions, tracked with `PRAGMA user_version`. Amounts are stored as
+// decimal strings so no precision is lost. Entries written before booking
+// timestamps existed are dated at the Unix epoch. Accounts stored without
+// their minor units look them up in the currency registry.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE accounts (
+        id TEXT PRIMARY KEY,
+        currency TEXT NOT NULL
+    );
+    CREATE TABLE ledger_entries (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        id TEXT NOT NULL,
+        account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+        action TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        transfer_id TEXT,
+        UNIQUE (account_id, id)
+    );
+    CREATE INDEX ledger_entries_account_seq ON ledger_entries (account_id, seq);",
+    "ALTER TABLE ledger_entries
+        ADD COLUMN booked_at TEXT NOT NULL DEFAULT '1970-01-01 00:00:00+00:00';
+    ALTER TABLE ledger_entries ADD COLUMN value_date TEXT NOT NULL DEFAULT '1970-01-01';",
+    "ALTER TABLE ledger_entries ADD COLUMN idempotency_key TEXT;
+    CREATE UNIQUE INDEX ledger_entries_account_idempotency_key
+        ON ledger_entries (account_id, idempotency_key);",
+    "ALTER TABLE ledger_entries ADD COLUMN reverses TEXT;",
+    "CREATE TABLE holds (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        id TEXT NOT NULL,
+        account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+        amount TEXT NOT NULL,
+        placed_at TEXT NOT NULL,
+        expires_at TEXT,
+        UNIQUE (account_id, id)
+    );",
+    "ALTER TABLE accounts ADD COLUMN policy TEXT NOT NULL DEFAULT 'no_overdraft';
+    ALTER TABLE accounts ADD COLUMN policy_amount TEXT;",
+    "ALTER TABLE accounts ADD COLUMN daily_max_amount TEXT;
+    ALTER TABLE accounts ADD COLUMN daily_max_count INTEGER;
+    ALTER TABLE accounts ADD COLUMN monthly_max_amount TEXT;
+    ALTER TABLE accounts ADD COLUMN monthly_max_count INTEGER;",
+    "CREATE TABLE status_changes (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+        from_status TEXT NOT NULL,
+        to_status TEXT NOT NULL,
+        reason TEXT NOT NULL,
+        changed_at TEXT NOT NULL
+    );",
+    "ALTER TABLE accounts ADD COLUMN pocket_of TEXT REFERENCES accounts (id) ON DELETE CASCADE;
+    CREATE UNIQUE INDEX accounts_pocket_currency ON accounts (pocket_of, currency);",
+    "ALTER TABLE accounts ADD COLUMN minor_units INTEGER;
+    ALTER TABLE accounts ADD COLUMN rounding TEXT NOT NULL DEFAULT 'reject';",
+    "ALTER TABLE accounts ADD COLUMN total TEXT NOT NULL DEFAULT '0';
+    ALTER TABLE accounts ADD COLUMN last_seq INTEGER;
+    ALTER TABLE ledger_entries ADD COLUMN total TEXT;
+    CREATE INDEX ledger_entries_account_booked_at ON ledger_entries (account_id, booked_at);
+    CREATE INDEX ledger_entries_account_reverses ON ledger_entries (account_id, reverses);",
+];
+
+// Schema version from which each entry stores the balance it left and each
+// account its total. `backfill_totals` computes them for older rows, which
+// SQL can't sum exactly.
+const TOTALS_VERSION: usize = 11;
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Backend(e.to_string())
+    }
+}
+
+/// SQLite-backed storage for single-node deployments.
+///
+/// File databases are opened in WAL mode. Writes run in `IMMEDIATE`
+/// transactions, so the database write lock is held across
+/// `Balance::mutate`'s checks.
+pub struct Sqlite {
+    conn: Mutex<Connection>,
+    double_entry: bool,
+}
+
+impl Sqlite {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Sqlite, StorageError> {
+        let conn = Connection::open(path)?;
+        conn.query_row("PRAGMA journal_mode = WAL", [], |row| {
+            row.get::<_, String>(0)
+        })?;
+        Sqlite::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Sqlite, StorageError> {
+        Sqlite::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Sqlite, StorageError> {
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(Sqlite {
+            conn: Mutex::new(conn),
+            double_entry: false,
+        })
+    }
+
+    /// Keeps the books balanced: amounts only change through `post` and
+    /// `convert`. Writes that would change a single balance, such as
+    /// `append`, capturing a hold or deleting a funded account, fail with
+    /// `JournalError::SingleSided`.
+    pub fn with_double_entry(mut self) -> Self {
+        self.double_entry = true;
+        self
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), StorageError> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version: usize = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for sql in MIGRATIONS.iter().skip(version) {
+        tx.execute_batch(sql)?;
+    }
+    if version < TOTALS_VERSION {
+        backfill_totals(&tx)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Computes each entry's and each account's total from the amounts stored so
+// far.
+fn backfill_totals(conn: &Connection) -> Result<(), StorageError> {
+    let mut stmt =
+        conn.prepare("SELECT seq, account_id, amount FROM ledger_entries ORDER BY seq")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut accounts: HashMap<String, (Decimal, i64)> = HashMap::new();
+    for (seq, account_id, amount) in rows {
+        let (total, last_seq) = accounts.entry(account_id).or_default();
+        *total += decimal(&amount)?;
+        *last_seq = seq;
+        conn.execute(
+            "UPDATE ledger_entries SET total = ?2 WHERE seq = ?1",
+            params![seq, total.to_string()],
+        )?;
+    }
+    for (account_id, (total, last_seq)) in accounts {
+        conn.execute(
+            "UPDATE accounts SET total = ?2, last_seq = ?3 WHERE id = ?1",
+            params![account_id, total.to_string(), last_seq],
+        )?;
+    }
+    Ok(())
+}
+
+// The account's settings and stored total, without any entries, holds or
+// status changes, and the seq of its latest entry.
+fn load_settings(
+    conn: &Connection,
+    account_id: &str,
+) -> Result<(Balance, Decimal, Option<i64>), StorageError> {
+    let (settings, total, last_seq) = conn
+        .query_row(
+            "SELECT currency, policy, policy_amount,
+                    daily_max_amount, daily_max_count, monthly_max_amount, monthly_max_count,
+                    pocket_of, minor_units, rounding, total, last_seq
+             FROM accounts WHERE id = ?1",
+            [account_id],
+            |row| {
+                let settings = (
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get(2)?,
+                    (row.get(3)?, row.get(4)?),
+                    (row.get(5)?, row.get(6)?),
+                    row.get(7)?,
+                    row.get::<_, Option<u32>>(8)?,
+                    row.get::<_, String>(9)?,
+                );
+                Ok((settings, row.get::<_, String>(10)?, row.get(11)?))
+            },
+        )
+        .optional()?
+        .ok_or(StorageError::AccountNotExists)?;
+    let (currency, policy, policy_amount, daily, monthly, pocket_of, minor_units, rounding) =
+        settings;
+    let policy = Policy::restore(&policy, opt_decimal(policy_amount)?)
+        .ok_or_else(|| StorageError::Backend(format!("invalid stored policy: {policy}")))?;
+    let limits = VelocityLimits {
+        daily: limit(daily)?,
+        monthly: limit(monthly)?,
+    };
+    let rounding = Rounding::restore(&rounding)
+        .ok_or_else(|| StorageError::Backend(format!("invalid stored rounding: {rounding}")))?;
+    let balance = match minor_units {
+        Some(minor_units) => Balance::restore_currency(&currency, minor_units),
+        None => Balance::new(&currency).map_err(StorageError::Balance)?,
+    };
+    let balance = balance
+        .with_rounding(rounding)
+        .with_policy(policy)
+        .with_limits(limits)
+        .with_pocket_of(pocket_of);
+    Ok((balance, decimal(&total)?, last_seq))
+}
+
+fn load(conn: &Connection, account_id: &str) -> Result<Balance, StorageError> {
+    let (mut balance, ..) = load_settings(conn, account_id)?;
+    for ledger in entries(conn, "account_id = ?1", params![account_id])? {
+        balance
+            .restore_ledger(ledger)
+            .map_err(StorageError::Ledger)?;
+    }
+    balance.restore_holds(holds(conn, account_id)?);
+    balance.restore_status_history(status_changes(conn, account_id, false)?);
+    Ok(balance)
+}
+
+// The account as a write booking entries at `at` is checked against, without
+// reading back its whole history: only the entries booked within the longest
+// velocity window before `at` are loaded, on top of the balance they started
+// from. Whatever `ledgers` refer to further back, by id, idempotency key or
+// as the entry they reverse, is looked up by index and carried forward. With
+// `at` unset, no entries are loaded. Only the latest status change is.
+fn load_recent(
+    conn: &Connection,
+    account_id: &str,
+    at: Option<DateTime<Utc>>,
+    ledgers: &[&Ledger],
+) -> Result<Balance, StorageError> {
+    let (mut balance, total, last_seq) = load_settings(conn, account_id)?;
+    let start: Option<i64> = match at {
+        Some(at) => conn.query_row(
+            "SELECT MIN(seq) FROM ledger_entries WHERE account_id = ?1 AND booked_at > ?2",
+            params![account_id, at - VelocityLimits::longest_window()],
+            |row| row.get(0),
+        )?,
+        None => None,
+    };
+    let opening = match (start, last_seq) {
+        (Some(start), _) => conn
+            .query_row(
+                "SELECT id, total FROM ledger_entries
+                 WHERE account_id = ?1 AND seq < ?2 ORDER BY seq DESC LIMIT 1",
+                params![account_id, start],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?
+            .map(|(id, total)| Ok::<_, StorageError>((id, decimal(&total)?)))
+            .transpose()?,
+        (None, Some(last_seq)) => {
+            let id = conn.query_row(
+                "SELECT id FROM ledger_entries WHERE seq = ?1",
+                [last_seq],
+                |row| row.get(0),
+            )?;
+            Some((id, total))
+        }
+        (None, None) => None,
+    };
+    let mut carried = Carried::default();
+    if let Some((id, total)) = opening {
+        carried.opening = total;
+        carried.opening_id = Some(id);
+    }
+    let before = start.unwrap_or(i64::MAX);
+    for ledger in ledgers {
+        for id in [Some(ledger.id()), ledger.reverses()].into_iter().flatten() {
+            carry_entry(conn, account_id, id, before, &mut carried)?;
+        }
+        if let Some(key) = ledger.idempotency_key() {
+            carry_key(conn, account_id, key, before, &mut carried)?;
+        }
+    }
+
+    let mut recent = Ledgers::carried_forward(carried);
+    if let Some(start) = start {
+        let filter = "account_id = ?1 AND seq >= ?2";
+        for ledger in entries(conn, filter, params![account_id, start])? {
+            recent.add(ledger).map_err(StorageError::Ledger)?;
+        }
+    }
+    balance.restore_ledgers(recent);
+    balance.restore_holds(holds(conn, account_id)?);
+    balance.restore_status_history(status_changes(conn, account_id, true)?);
+    Ok(balance)
+}
+
+// Carries forward the entry `id` posted before seq `before`, with how much
+// was reversed of it by then, unless it is a reversal itself.
+fn carry_entry(
+    conn: &Connection,
+    account_id: &str,
+    id: &str,
+    before: i64,
+    carried: &mut Carried,
+) -> Result<(), StorageError> {
+    let amount: Option<String> = conn
+        .query_row(
+            "SELECT amount FROM ledger_entries
+             WHERE account_id = ?1 AND id = ?2 AND seq < ?3 AND reverses IS NULL",
+            params![account_id, id, before],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(amount) = amount else {
+        return Ok(());
+    };
+    carried.reversible.insert(id.to_string(), decimal(&amount)?);
+    let mut stmt = conn.prepare(
+        "SELECT amount FROM ledger_entries
+         WHERE account_id = ?1 AND reverses = ?2 AND seq < ?3",
+    )?;
+    let reversed = stmt
+        .query_map(params![account_id, id, before], |row| {
+            row.get::<_, String>(0)
+        })?
+        .map(|amount| Ok(decimal(&amount?)?.abs()))
+        .sum::<Result<Decimal, StorageError>>()?;
+    if !reversed.is_zero() {
+        carried.reversed.insert(id.to_string(), reversed);
+    }
+    Ok(())
+}
+
+// Carries forward the entry posted under idempotency `key` before seq
+// `before`.
+fn carry_key(
+    conn: &Connection,
+    account_id: &str,
+    key: &str,
+    before: i64,
+    carried: &mut Carried,
+) -> Result<(), StorageError> {
+    let row = conn
+        .query_row(
+            "SELECT id, action, amount, total FROM ledger_entries
+             WHERE account_id = ?1 AND idempotency_key = ?2 AND seq < ?3",
+            params![account_id, key, before],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            },
+        )
+        .optional()?;
+    if let Some((ledger_id, action, amount, total)) = row {
+        let entry = KeyedEntry {
+            ledger_id,
+            action: action.parse().map_err(StorageError::Ledger)?,
+            amount: decimal(&amount)?,
+            total: decimal(&total)?,
+        };
+        carried.keys.insert(key.to_string(), entry);
+    }
+    Ok(())
+}
+
+// The entries matching `filter`, in posting order.
+fn entries(
+    conn: &Connection,
+    filter: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<Ledger>, StorageError> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, action, amount, booked_at, value_date,
+                transfer_id, idempotency_key, reverses
+         FROM ledger_entries
+         WHERE {filter} ORDER BY seq"
+    ))?;
+    let rows = stmt.query_map(params, |row| {
+        let links = Links {
+            transfer_id: row.get(5)?,
+            idempotency_key: row.get(6)?,
+            reverses: row.get(7)?,
+        };
+        Ok((
+            row.get(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            links,
+        ))
+    })?;
+    let mut ledgers = vec![];
+    for row in rows {
+        let (id, action, amount, booked_at, value_date, links) = row?;
+        let action = action.parse().map_err(StorageError::Ledger)?;
+        ledgers.push(Ledger::restore(
+            id,
+            action,
+            decimal(&amount)?,
+            booked_at,
+            value_date,
+            links,
+        ));
+    }
+    Ok(ledgers)
+}
+
+fn holds(conn: &Connection, account_id: &str) -> Result<Vec<Hold>, StorageError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, amount, placed_at, expires_at FROM holds
+         WHERE account_id = ?1 ORDER BY seq",
+    )?;
+    let rows = stmt.query_map([account_id], |row| {
+        Ok((
+            row.get(0)?,
+            row.get::<_, String>(1)?,
+            row.get(2)?,
+            row.get(3)?,
+        ))
+    })?;
+    let mut holds = vec![];
+    for row in rows {
+        let (id, amount, placed_at, expires_at) = row?;
+        holds.push(Hold::restore(id, decimal(&amount)?, placed_at, expires_at));
+    }
+    Ok(holds)
+}
+
+// The account's status changes in order, or only the latest.
+fn status_changes(
+    conn: &Connection,
+    account_id: &str,
+    latest: bool,
+) -> Result<Vec<StatusChange>, StorageError> {
+    let sql = if latest {
+        "SELECT from_status, to_status, reason, changed_at FROM status_changes
+         WHERE account_id = ?1 ORDER BY seq DESC LIMIT 1"
+    } else {
+        "SELECT from_status, to_status, reason, changed_at FROM status_changes
+         WHERE account_id = ?1 ORDER BY seq"
+    };
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([account_id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get(2)?,
+            row.get(3)?,
+        ))
+    })?;
+    let mut history = vec![];
+    for row in rows {
+        let (from, to, reason, changed_at) = row?;
+        history.push(StatusChange::restore(
+            status(&from)?,
+            status(&to)?,
+            reason,
+            changed_at,
+        ));
+    }
+    Ok(history)
+}
+
+fn status(name: &str) -> Result<AccountStatus, StorageError> {
+    AccountStatus::restore(name)
+        .ok_or_else(|| StorageError::Backend(format!("invalid stored status: {name}")))
+}
+
+fn decimal(amount: &str) -> Result<Decimal, StorageError> {
+    Decimal::from_str(amount)
+        .map_err(|e| StorageError::Backend(format!("invalid stored amount: {e}")))
+}
+
+fn opt_decimal(amount: Option<String>) -> Result<Option<Decimal>, StorageError> {
+    amount.as_deref().map(decimal).transpose()
+}
+
+fn limit((max_amount, max_count): (Option<String>, Option<u32>)) -> Result<Limit, StorageError> {
+    Ok(Limit {
+        max_amount: opt_decimal(max_amount)?,
+        max_count,
+    })
+}
+
+// Values for the `accounts` columns, from `id` through `rounding`.
+fn account_params(account_id: &str, balance: &Balance) -> Vec<Value> {
+    let text = |amount: Option<Decimal>| amount.map(|amount| amount.to_string());
+    let (policy, limits) = (balance.policy(), balance.limits());
+    vec![
+        account_id.to_string().into(),
+        balance.currency.clone().into(),
+        policy.as_str().to_string().into(),
+        text(policy.amount()).into(),
+        text(limits.daily.max_amount).into(),
+        limits.daily.max_count.into(),
+        text(limits.monthly.max_amount).into(),
+        limits.monthly.max_count.into(),
+        balance.pocket_of().map(str::to_string).into(),
+        balance.minor_units().into(),
+        balance.rounding().as_str().to_string().into(),
+    ]
+}
+
+// The account `account_id`, unless it doesn't exist or is itself a pocket.
+fn load_account(conn: &Connection, account_id: &str) -> Result<Balance, StorageError> {
+    match load(conn, account_id)? {
+        balance if balance.pocket_of().is_some() => Err(StorageError::AccountNotExists),
+        balance => Ok(balance),
+    }
+}
+
+// Currency of the account `account_id`, unless it doesn't exist or is itself
+// a pocket.
+fn owner_currency(conn: &Connection, account_id: &str) -> Result<String, StorageError> {
+    conn.query_row(
+        "SELECT currency FROM accounts WHERE id = ?1 AND pocket_of IS NULL",
+        [account_id],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or(StorageError::AccountNotExists)
+}
+
+// Id of the row holding the account's `currency` pocket.
+fn pocket(conn: &Connection, account_id: &str, currency: &str) -> Result<String, StorageError> {
+    let currency = currency.to_uppercase();
+    if owner_currency(conn, account_id)? == currency {
+        return Ok(account_id.to_string());
+    }
+    conn.query_row(
+        "SELECT id FROM accounts WHERE pocket_of = ?1 AND currency = ?2",
+        [account_id, &currency],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or(StorageError::PocketNotExists)
+}
+
+// The controls postings on `balance` are checked against: its account's if
+// it is a pocket.
+fn governing(conn: &Connection, balance: &Balance) -> Result<Controls, StorageError> {
+    match balance.pocket_of() {
+        Some(account_id) => Ok(load_recent(conn, account_id, None, &[])?.controls()),
+        None => Ok(balance.controls()),
+    }
+}
+
+// Amounts of the account's pockets other than its primary balance.
+fn pocket_amounts(conn: &Connection, account_id: &str) -> Result<Vec<Decimal>, StorageError> {
+    let mut stmt = conn.prepare("SELECT total FROM accounts WHERE pocket_of = ?1")?;
+    let totals = stmt.query_map([account_id], |row| row.get::<_, String>(0))?;
+    totals.map(|total| decimal(&total?)).collect()
+}
+
+// Validates and inserts `ledger`, returning the new total. The caller
+// commits.
+fn post(conn: &Connection, account_id: &str, ledger: Ledger) -> Result<Decimal, StorageError> {
+    let mut balance = load_recent(conn, account_id, Some(ledger.booked_at()), &[&ledger])?;
+    if let Some(total) = balance.replayed(&ledger).map_err(StorageError::Balance)? {
+        return Ok(total);
+    }
+    let controls = governing(conn, &balance)?;
+    let ledger = balance
+        .prepare_under(ledger, controls)
+        .map_err(StorageError::Balance)?;
+    let total = balance.commit(ledger);
+    insert_ledger(
+        conn,
+        account_id,
+        balance.ledgers().entries().last().unwrap(),
+        total,
+    )?;
+    Ok(total)
+}
+
+// Validates the postings of `transaction` in order against the balances
+// left by the ones before them and inserts them, tagged with the
+// transaction id; see `Storage::post`. The caller commits.
+fn post_transaction(conn: &Connection, transaction: Transaction) -> Result<Posted, StorageError> {
+    let (transaction_id, postings) = transaction.into_parts();
+    let keys = postings
+        .iter()
+        .map(|posting| pocket(conn, posting.account_id(), posting.currency()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut touched: HashMap<String, Balance> = HashMap::new();
+    for key in &keys {
+        if let Entry::Vacant(entry) = touched.entry(key.clone()) {
+            let (at, ledgers) = touching(key, &keys, &postings);
+            entry.insert(load_recent(conn, key, at, &ledgers)?);
+        }
+    }
+    let legs = keys.iter().zip(&postings);
+    if let Some(totals) = replayed(legs.map(|(key, posting)| (&touched[key], posting.ledger())))? {
+        return Ok(Posted {
+            transaction_id,
+            totals,
+        });
+    }
+
+    let transfer_id = Arc::new(transaction_id.clone());
+    let mut legs = Vec::with_capacity(postings.len());
+    let mut totals = Vec::with_capacity(postings.len());
+    for (key, posting) in keys.into_iter().zip(postings) {
+        let (_, currency, ledger) = posting.into_parts();
+        let controls = governing(conn, &touched[&key])?;
+        let balance = touched.get_mut(&key).unwrap();
+        let ledger = balance
+            .prepare_under(ledger.with_transfer_id(transfer_id.clone()), controls)
+            .map_err(StorageError::Balance)?;
+        legs.push((currency, ledger.amount()));
+        let total = balance.commit(ledger);
+        insert_ledger(
+            conn,
+            &key,
+            balance.ledgers().entries().last().unwrap(),
+            total,
+        )?;
+        totals.push(total);
+    }
+    check_balanced(
+        legs.iter()
+            .map(|(currency, amount)| (currency.as_str(), *amount)),
+    )
+    .map_err(StorageError::Journal)?;
+    Ok(Posted {
+        transaction_id,
+        totals,
+    })
+}
+
+// Inserts the internal account in `currency` unless it already exists.
+fn open_internal(
+    conn: &Connection,
+    account: InternalAccount,
+    currency: &str,
+) -> Result<(), StorageError> {
+    let balance = account.balance(currency).map_err(StorageError::Balance)?;
+    conn.execute(
+        "INSERT OR IGNORE INTO accounts
+            (id, currency, policy, policy_amount,
+             daily_max_amount, daily_max_count, monthly_max_amount, monthly_max_count,
+             pocket_of, minor_units, rounding)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params_from_iter(account_params(&account.account_id(currency), &balance)),
+    )?;
+    Ok(())
+}
+
+// Inserts `ledger`, which left the account at `total`, and moves the
+// account's stored total on to it.
+fn insert_ledger(
+    conn: &Connection,
+    account_id: &str,
+    ledger: &Ledger,
+    total: Decimal,
+) -> Result<(), StorageError> {
+    conn.execute(
+        "INSERT INTO ledger_entries
+            (id, account_id, action, amount, transfer_id, booked_at, value_date,
+             idempotency_key, reverses, total)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            ledger.id(),
+            account_id,
+            ledger.action().as_str(),
+            ledger.amount().to_string(),
+            ledger.transfer_id(),
+            ledger.booked_at(),
+            ledger.value_date(),
+            ledger.idempotency_key(),
+            ledger.reverses(),
+            total.to_string(),
+        ],
+    )?;
+    conn.execute(
+        "UPDATE accounts SET total = ?2, last_seq = last_insert_rowid() WHERE id = ?1",
+        params![account_id, total.to_string()],
+    )?;
+    Ok(())
+}
+
+// Inserts every entry of `ledgers`, as a new account history.
+fn insert_ledgers(
+    conn: &Connection,
+    account_id: &str,
+    ledgers: &Ledgers,
+) -> Result<(), StorageError> {
+    let mut total = Decimal::ZERO;
+    for ledger in ledgers.entries() {
+        total += ledger.amount();
+        insert_ledger(conn, account_id, ledger, total)?;
+    }
+    Ok(())
+}
+
+fn insert_hold(conn: &Connection, account_id: &str, hold: &Hold) -> Result<(), StorageError> {
+    conn.execute(
+        "INSERT INTO holds (id, account_id, amount, placed_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            hold.id(),
+            account_id,
+            hold.amount().to_string(),
+            hold.placed_at(),
+            hold.expires_at(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_status_change(
+    conn: &Connection,
+    account_id: &str,
+    change: &StatusChange,
+) -> Result<(), StorageError> {
+    conn.execute(
+        "INSERT INTO status_changes (account_id, from_status, to_status, reason, changed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            account_id,
+            change.from().as_str(),
+            change.to().as_str(),
+            change.reason(),
+            change.changed_at(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn delete_hold(conn: &Connection, account_id: &str, hold_id: &str) -> Result<(), StorageError> {
+    conn.execute(
+        "DELETE FROM holds WHERE account_id = ?1 AND id = ?2",
+        [account_id, hold_id],
+    )?;
+    Ok(())
+}
+
+impl Storage for Sqlite {
+    fn insert(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError> {
+        check_new_account(account_id, &balance)?;
+        check_double_entry(self.double_entry, balance.amount())?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO accounts
+                (id, currency, policy, policy_amount,
+                 daily_max_amount, daily_max_count, monthly_max_amount, monthly_max_count,
+                 pocket_of, minor_units, rounding)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params_from_iter(account_params(account_id, &balance)),
+        )?;
+        if inserted == 0 {
+            return Err(StorageError::AccountAlreadyExists);
+        }
+        insert_ledgers(&tx, account_id, balance.ledgers())?;
+        for hold in balance.holds() {
+            insert_hold(&tx, account_id, hold)?;
+        }
+        for change in balance.status_history() {
+            insert_status_change(&tx, account_id, change)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get(&self, account_id: &str) -> Result<Balance, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        load_account(&tx, account_id)
+    }
+
+    fn currency(&self, account_id: &str) -> Result<String, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        owner_currency(&conn, account_id)
+    }
+
+    fn update(&mut self, account_id: &str, balance: Balance) -> Result<(), StorageError> {
+        check_account_balance(&balance)?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let stored = load_account(&tx, account_id)?;
+        balance
+            .check_replaces(&stored)
+            .map_err(StorageError::Balance)?;
+        check_double_entry(self.double_entry, balance.amount() - stored.amount())?;
+        check_pockets_closable(balance.status(), pocket_amounts(&tx, account_id)?)?;
+        tx.execute(
+            "UPDATE accounts
+             SET currency = ?2, policy = ?3, policy_amount = ?4,
+                 daily_max_amount = ?5, daily_max_count = ?6,
+                 monthly_max_amount = ?7, monthly_max_count = ?8, pocket_of = ?9,
+                 minor_units = ?10, rounding = ?11, total = '0', last_seq = NULL
+             WHERE id = ?1",
+            params_from_iter(account_params(account_id, &balance)),
+        )?;
+        tx.execute(
+            "DELETE FROM ledger_entries WHERE account_id = ?1",
+            [account_id],
+        )?;
+        tx.execute("DELETE FROM holds WHERE account_id = ?1", [account_id])?;
+        tx.execute(
+            "DELETE FROM status_changes WHERE account_id = ?1",
+            [account_id],
+        )?;
+        insert_ledgers(&tx, account_id, balance.ledgers())?;
+        for hold in balance.holds() {
+            insert_hold(&tx, account_id, hold)?;
+        }
+        for change in balance.status_history() {
+            insert_status_change(&tx, account_id, change)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT id FROM accounts WHERE pocket_of IS NULL ORDER BY id")?;
+        let ids = stmt.query_map([], |row| row.get(0))?;
+        Ok(ids.collect::<Result<_, _>>()?)
+    }
+
+    fn delete(&mut self, account_id: &str) -> Result<Balance, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let balance = load_account(&tx, account_id)?;
+        let pockets = pocket_amounts(&tx, account_id)?;
+        for amount in pockets.into_iter().chain([balance.amount()]) {
+            check_double_entry(self.double_entry, amount)?;
+        }
+        tx.execute("DELETE FROM accounts WHERE id = ?1", [account_id])?;
+        tx.commit()?;
+        Ok(balance)
+    }
+
+    fn append(&mut self, account_id: &str, ledger: Ledger) -> Result<Decimal, StorageError> {
+        check_double_entry(self.double_entry, ledger.amount())?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        owner_currency(&tx, account_id)?;
+        let total = post(&tx, account_id, ledger)?;
+        tx.commit()?;
+        Ok(total)
+    }
+
+    fn place_hold(&mut self, account_id: &str, hold: Hold) -> Result<Decimal, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        owner_currency(&tx, account_id)?;
+        let mut balance = load_recent(&tx, account_id, None, &[])?;
+        let available = balance.place_hold(hold).map_err(StorageError::Balance)?;
+        insert_hold(&tx, account_id, balance.holds().last().unwrap())?;
+        tx.commit()?;
+        Ok(available)
+    }
+
+    fn capture_hold(
+        &mut self,
+        account_id: &str,
+        hold_id: &str,
+        amount: Option<&str>,
+    ) -> Result<Decimal, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        owner_currency(&tx, account_id)?;
+        let mut balance = load_recent(&tx, account_id, Some(SystemClock.now()), &[])?;
+        let before = balance.amount();
+        let total = balance
+            .capture(hold_id, amount, time_ordered(), &SystemClock)
+            .map_err(StorageError::Balance)?;
+        check_double_entry(self.double_entry, total - before)?;
+        delete_hold(&tx, account_id, hold_id)?;
+        let captured = balance.ledgers().entries().last().unwrap();
+        insert_ledger(&tx, account_id, captured, total)?;
+        tx.commit()?;
+        Ok(total)
+    }
+
+    fn release_hold(&mut self, account_id: &str, hold_id: &str) -> Result<Hold, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        owner_currency(&tx, account_id)?;
+        let mut balance = load_recent(&tx, account_id, None, &[])?;
+        let hold = balance.release(hold_id).map_err(StorageError::Balance)?;
+        delete_hold(&tx, account_id, hold_id)?;
+        tx.commit()?;
+        Ok(hold)
+    }
+
+    fn set_status(
+        &mut self,
+        account_id: &str,
+        status: AccountStatus,
+        reason: &str,
+    ) -> Result<StatusChange, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        owner_currency(&tx, account_id)?;
+        let mut balance = load_recent(&tx, account_id, None, &[])?;
+        check_pockets_closable(status, pocket_amounts(&tx, account_id)?)?;
+        let change = balance
+            .transition(status, reason)
+            .map_err(StorageError::Balance)?;
+        insert_status_change(&tx, account_id, &change)?;
+        tx.commit()?;
+        Ok(change)
+    }
+
+    fn open_pocket(&mut self, account_id: &str, currency: &str) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        owner_currency(&tx, account_id)?;
+        let primary = load_recent(&tx, account_id, None, &[])?;
+        primary
+            .controls()
+            .check_status(true)
+            .map_err(StorageError::Balance)?;
+        let balance = Balance::new(currency)
+            .map_err(StorageError::Balance)?
+            .with_pocket_of(Some(account_id.to_string()));
+        if primary.currency == balance.currency {
+            return Err(StorageError::PocketAlreadyExists);
+        }
+        let key = pocket_key(account_id, &balance.currency);
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO accounts
+                (id, currency, policy, policy_amount,
+                 daily_max_amount, daily_max_count, monthly_max_amount, monthly_max_count,
+                 pocket_of, minor_units, rounding)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params_from_iter(account_params(&key, &balance)),
+        )?;
+        if inserted == 0 {
+            return Err(StorageError::PocketAlreadyExists);
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn pockets(&self, account_id: &str) -> Result<Vec<String>, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        owner_currency(&tx, account_id)?;
+        let mut stmt = tx.prepare(
+            "SELECT currency FROM accounts WHERE id = ?1 OR pocket_of = ?1 ORDER BY currency",
+        )?;
+        let currencies = stmt.query_map([account_id], |row| row.get(0))?;
+        Ok(currencies.collect::<Result<_, _>>()?)
+    }
+
+    fn get_pocket(&self, account_id: &str, currency: &str) -> Result<Balance, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        load(&tx, &pocket(&tx, account_id, currency)?)
+    }
+
+    fn append_to_pocket(
+        &mut self,
+        account_id: &str,
+        currency: &str,
+        ledger: Ledger,
+    ) -> Result<Decimal, StorageError> {
+        check_double_entry(self.double_entry, ledger.amount())?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let key = pocket(&tx, account_id, currency)?;
+        let total = post(&tx, &key, ledger)?;
+        tx.commit()?;
+        Ok(total)
+    }
+
+    fn post(&mut self, transaction: Transaction) -> Result<Posted, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let posted = post_transaction(&tx, transaction)?;
+        tx.commit()?;
+        Ok(posted)
+    }
+
+    fn trial_balance(&self) -> Result<TrialBalance, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut stmt = tx.prepare("SELECT id, pocket_of, currency, total FROM accounts")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        let lines = rows
+            .map(|row| {
+                let (id, pocket_of, currency, total) = row?;
+                Ok(TrialBalanceLine {
+                    account_id: pocket_of.unwrap_or(id),
+                    currency,
+                    amount: decimal(&total)?,
+                })
+            })
+            .collect::<Result<_, StorageError>>()?;
+        Ok(TrialBalance::new(lines))
+    }
+
+    fn wallet(&self, account_id: &str) -> Result<Wallet, StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        owner_currency(&tx, account_id)?;
+        let mut stmt = tx.prepare("SELECT id FROM accounts WHERE id = ?1 OR pocket_of = ?1")?;
+        let ids = stmt
+            .query_map([account_id], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let pockets = ids
+            .iter()
+            .map(|id| load(&tx, id))
+            .collect::<Result<_, _>>()?;
+        Ok(Wallet::new(account_id, pockets))
+    }
+
+    fn convert<R: RateProvider>(
+        &mut self,
+        account_id: &str,
+        from: &str,
+        to: &str,
+        amount: &str,
+        converter: &Converter<R>,
+    ) -> Result<Conversion, StorageError> {
+        let debit = Ledger::conversion_debit(amount, time_ordered(), &SystemClock)
+            .map_err(StorageError::Ledger)?;
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let source = pocket(&tx, account_id, from)?;
+        let target = pocket(&tx, account_id, to)?;
+        if source == target {
+            return Err(StorageError::Fx(FxError::SameCurrency));
+        }
+        let source = load_recent(&tx, &source, Some(debit.booked_at()), &[&debit])?;
+        let target = load_recent(&tx, &target, None, &[])?;
+        // Priced on what the source would actually be debited.
+        let debit = source
+            .prepare_under(debit, governing(&tx, &source)?)
+            .map_err(StorageError::Balance)?;
+        let quote = converter
+            .quote(&source.currency, &target.currency, debit.amount())
+            .map_err(StorageError::Fx)?;
+
+        for (account, currency) in conversion_accounts(&quote.from, &quote.to) {
+            open_internal(&tx, account, currency)?;
+        }
+        let posted = post_transaction(
+            &tx,
+            Transaction::conversion(account_id, &quote, &SystemClock),
+        )?;
+        tx.commit()?;
+        Ok(Conversion {
+            transfer_id: posted.transaction_id,
+            quote,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::JournalError;
+    use crate::ledger::Action;
+    use crate::storage::conformance::storage_conformance;
+    use crate::storage::test_util::TempPath;
+    use rust_decimal_macros::dec;
+
+    storage_conformance!(Sqlite::open_in_memory().unwrap());
+
+    fn temp_path() -> TempPath {
+        TempPath::new("sqlite")
+    }
+
+    #[test]
+    fn test_open_uses_wal() {
+        let path = temp_path();
+        let storage = Sqlite::open(&path).unwrap();
+        let conn = storage.conn.lock().unwrap();
+        let mode: String = conn
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+    }
+
+    #[test]
+    fn test_balances_survive_reopen() {
+        let path = temp_path();
+        {
+            let mut storage = Sqlite::open(&path).unwrap();
+            storage
+                .insert("account_1", Balance::new("USD").unwrap())
+                .unwrap();
+            let ledger = Ledger::new(Action::Deposit("0.1".to_string()), time_ordered()).unwrap();
+            storage.append("account_1", ledger).unwrap();
+        }
+
+        let storage = Sqlite::open(&path).unwrap();
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.currency, "USD");
+        assert_eq!(balance.amount(), dec!(0.1));
+        assert_eq!(balance.ledgers().len(), 1);
+    }
+
+    #[test]
+    fn test_migration_backfills_totals() {
+        let path = temp_path();
+        {
+            let conn = Connection::open(&path).unwrap();
+            for sql in &MIGRATIONS[..TOTALS_VERSION - 1] {
+                conn.execute_batch(sql).unwrap();
+            }
+            conn.pragma_update(None, "user_version", TOTALS_VERSION - 1)
+                .unwrap();
+            conn.execute_batch(
+                "INSERT INTO accounts (id, currency) VALUES ('account_1', 'USD');
+                INSERT INTO ledger_entries (id, account_id, action, amount)
+                VALUES ('1', 'account_1', 'Deposit', '10.10'),
+                       ('2', 'account_1', 'Withdrawal', '-0.2');",
+            )
+            .unwrap();
+        }
+
+        let mut storage = Sqlite::open(&path).unwrap();
+        let lines = storage.trial_balance().unwrap().lines().to_vec();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].amount, dec!(9.9));
+        let deposit = Ledger::new(Action::Deposit("1".to_string()), time_ordered()).unwrap();
+        assert_eq!(storage.append("account_1", deposit), Ok(dec!(10.9)));
+    }
+
+    #[test]
+    fn test_unregistered_currency_loads_with_stored_minor_units() {
+        let mut storage = Sqlite::open_in_memory().unwrap();
+        storage
+            .insert("account_1", Balance::new("USD").unwrap())
+            .unwrap();
+        // As if the currency had been registered by an earlier process.
+        storage
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE accounts SET currency = 'QQX', minor_units = 6 WHERE id = 'account_1'",
+                [],
+            )
+            .unwrap();
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.currency, "QQX");
+        assert_eq!(balance.minor_units(), 6);
+    }
+
+    #[test]
+    fn test_append_concurrently_from_many_connections() {
+        let path = temp_path();
+        let mut storage = Sqlite::open(&path).unwrap();
+        storage
+            .insert("account_1", Balance::new("USD").unwrap())
+            .unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let mut storage = Sqlite::open(&path).unwrap();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        let ledger =
+                            Ledger::new(Action::Deposit("1.5".to_string()), time_ordered())
+                                .unwrap();
+                        storage.append("account_1", ledger).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(storage.get("account_1").unwrap().amount(), dec!(150));
+    }
+
+    #[test]
+    fn test_double_entry_keeps_books_balanced() {
+        let mut storage = Sqlite::open_in_memory().unwrap().with_double_entry();
+        storage
+            .insert("account_1", Balance::new("USD").unwrap())
+            .unwrap();
+        let cash = InternalAccount::Cash.open(&mut storage, "USD").unwrap();
+        storage
+            .post(Transaction::deposit("account_1", "USD", "100").unwrap())
+            .unwrap();
+        storage
+            .post(Transaction::withdrawal("account_1", "USD", "30").unwrap())
+            .unwrap();
+        let trial = storage.trial_balance().unwrap();
+        assert!(trial.is_balanced());
+        assert_eq!(trial.lines().len(), 2);
+        assert_eq!(storage.get(&cash).unwrap().amount(), dec!(-70));
+
+        // Writes that would change a single balance are refused.
+        let single_sided = StorageError::Journal(JournalError::SingleSided);
+        let deposit = Ledger::new(Action::Deposit("1".to_string()), time_ordered()).unwrap();
+        assert_eq!(
+            storage.append("account_1", deposit).unwrap_err(),
+            single_sided
+        );
+        let hold = Hold::new("10", time_ordered()).unwrap();
+        let hold_id = hold.id().to_string();
+        storage.place_hold("account_1", hold).unwrap();
+        assert_eq!(
+            storage
+                .capture_hold("account_1", &hold_id, None)
+                .unwrap_err(),
+            single_sided
+        );
+        storage.release_hold("account_1", &hold_id).unwrap();
+        let funded = storage.get("account_1").unwrap();
+        assert_eq!(
+            storage.insert("account_2", funded.clone()).unwrap_err(),
+            single_sided
+        );
+        assert_eq!(storage.delete("account_1").unwrap_err(), single_sided);
+        storage
+            .insert("account_2", Balance::new("USD").unwrap())
+            .unwrap();
+        assert_eq!(
+            storage.update("account_2", funded).unwrap_err(),
+            single_sided
+        );
+        assert!(storage.trial_balance().unwrap().is_balanced());
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::{Path, PathBuf};
+
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
+/// A unique path in the temp dir, removed along with any sibling files the
+/// stores derive from it on drop.
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    pub(crate) fn new(extension: &str) -> TempPath {
+        let name = format!("rust-pg-{}.{extension}", generate_random_string(12));
+        TempPath(std::env::temp_dir().join(name))
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm", ".archive", ".compact"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+pub(crate) fn generate_random_string(len: usize) -> String {
+    let s: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect::<String>();
+    s
+}
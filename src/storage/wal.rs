@@ -0,0 +1,1581 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use rust_decimal::Decimal;
+
+use super::StorageError;
+use crate::balance::Balance;
+use crate::currency::Rounding;
+use crate::hold::Hold;
+use crate::ledger::{Carried, KeyedEntry, Ledger, Ledgers, Links};
+use crate::limits::{Limit, VelocityLimits};
+use crate::policy::Policy;
+use crate::status::{AccountStatus, StatusChange};
+
+// Journal and archive files start with `MAGIC` and the format version their
+// frames are encoded in. Files written before the format was versioned have
+// no header and are refused rather than misread.
+const MAGIC: &[u8; 4] = b"RPGJ";
+const VERSION: u8 = 1;
+const FILE_HEADER: usize = 5;
+
+// Each frame is `len: u32 LE | crc32(payload): u32 LE | payload`, where the
+// payload holds one or more records that must be replayed together.
+const FRAME_HEADER: usize = 8;
+
+const TAG_INSERT: u8 = 1;
+const TAG_UPDATE: u8 = 2;
+const TAG_DELETE: u8 = 3;
+const TAG_APPEND: u8 = 4;
+const TAG_SNAPSHOT: u8 = 5;
+const TAG_HOLDS: u8 = 6;
+const TAG_STATUS: u8 = 7;
+
+/// Point-in-time state of one account, as recorded by a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountSnapshot {
+    pub account_id: String,
+    pub currency: String,
+    pub minor_units: u32,
+    pub rounding: Rounding,
+    pub balance: Decimal,
+    pub last_ledger_id: Option<String>,
+    /// The account's entries as compaction keeps them: those booked within
+    /// the longest velocity window, with the older ones carried forward.
+    pub ledgers: Ledgers,
+    pub holds: Vec<Hold>,
+    pub policy: Policy,
+    pub limits: VelocityLimits,
+    pub status_history: Vec<StatusChange>,
+    /// The owning account, if this is one of its currency pockets.
+    pub pocket_of: Option<String>,
+}
+
+impl AccountSnapshot {
+    /// Snapshots `balance`, keeping the entries booked after `retain_after`.
+    pub(crate) fn new(
+        account_id: &str,
+        balance: &Balance,
+        retain_after: DateTime<Utc>,
+    ) -> AccountSnapshot {
+        AccountSnapshot {
+            account_id: account_id.to_string(),
+            currency: balance.currency.clone(),
+            minor_units: balance.minor_units(),
+            rounding: balance.rounding(),
+            balance: balance.amount(),
+            last_ledger_id: balance.ledgers().last_id().map(str::to_string),
+            ledgers: balance.ledgers().compact(retain_after),
+            holds: balance.holds().to_vec(),
+            policy: balance.policy(),
+            limits: balance.limits(),
+            status_history: balance.status_history().to_vec(),
+            pocket_of: balance.pocket_of().map(str::to_string),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Record {
+    Insert {
+        account_id: String,
+        balance: Balance,
+    },
+    Update {
+        account_id: String,
+        balance: Balance,
+    },
+    Delete {
+        account_id: String,
+    },
+    Append {
+        account_id: String,
+        ledger: Ledger,
+    },
+    // Replaces the account's outstanding holds.
+    Holds {
+        account_id: String,
+        holds: Vec<Hold>,
+    },
+    // Appends a status change to the account's history.
+    Status {
+        account_id: String,
+        change: StatusChange,
+    },
+    // Only ever the first record of a compacted journal. `archive_len` is the
+    // size of the archive holding the history the snapshot replaces.
+    Snapshot {
+        archive_len: u64,
+        accounts: Vec<AccountSnapshot>,
+    },
+}
+
+impl Record {
+    pub(crate) fn insert(account_id: &str, balance: &Balance) -> Record {
+        Record::Insert {
+            account_id: account_id.to_string(),
+            balance: balance.clone(),
+        }
+    }
+
+    pub(crate) fn update(account_id: &str, balance: &Balance) -> Record {
+        Record::Update {
+            account_id: account_id.to_string(),
+            balance: balance.clone(),
+        }
+    }
+
+    pub(crate) fn delete(account_id: &str) -> Record {
+        Record::Delete {
+            account_id: account_id.to_string(),
+        }
+    }
+
+    pub(crate) fn append(account_id: &str, ledger: &Ledger) -> Record {
+        Record::Append {
+            account_id: account_id.to_string(),
+            ledger: ledger.clone(),
+        }
+    }
+
+    pub(crate) fn holds(account_id: &str, holds: &[Hold]) -> Record {
+        Record::Holds {
+            account_id: account_id.to_string(),
+            holds: holds.to_vec(),
+        }
+    }
+
+    pub(crate) fn status(account_id: &str, change: &StatusChange) -> Record {
+        Record::Status {
+            account_id: account_id.to_string(),
+            change: change.clone(),
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Record::Insert {
+                account_id,
+                balance,
+            }
+            | Record::Update {
+                account_id,
+                balance,
+            } => {
+                let tag = match self {
+                    Record::Insert { .. } => TAG_INSERT,
+                    _ => TAG_UPDATE,
+                };
+                buf.push(tag);
+                put_str(buf, account_id);
+                put_str(buf, &balance.currency);
+                put_u32(buf, balance.minor_units());
+                put_str(buf, balance.rounding().as_str());
+                put_ledgers(buf, balance.ledgers());
+                put_holds(buf, balance.holds());
+                put_policy(buf, balance.policy());
+                put_limits(buf, balance.limits());
+                put_status_history(buf, balance.status_history());
+                put_opt_str(buf, balance.pocket_of());
+            }
+            Record::Delete { account_id } => {
+                buf.push(TAG_DELETE);
+                put_str(buf, account_id);
+            }
+            Record::Append { account_id, ledger } => {
+                buf.push(TAG_APPEND);
+                put_str(buf, account_id);
+                put_ledger(buf, ledger);
+            }
+            Record::Holds { account_id, holds } => {
+                buf.push(TAG_HOLDS);
+                put_str(buf, account_id);
+                put_holds(buf, holds);
+            }
+            Record::Status { account_id, change } => {
+                buf.push(TAG_STATUS);
+                put_str(buf, account_id);
+                put_status_change(buf, change);
+            }
+            Record::Snapshot {
+                archive_len,
+                accounts,
+            } => {
+                buf.push(TAG_SNAPSHOT);
+                buf.extend_from_slice(&archive_len.to_le_bytes());
+                put_u32(buf, accounts.len() as u32);
+                for account in accounts {
+                    put_str(buf, &account.account_id);
+                    put_str(buf, &account.currency);
+                    put_u32(buf, account.minor_units);
+                    put_str(buf, account.rounding.as_str());
+                    put_ledgers(buf, &account.ledgers);
+                    put_holds(buf, &account.holds);
+                    put_policy(buf, account.policy);
+                    put_limits(buf, account.limits);
+                    put_status_history(buf, &account.status_history);
+                    put_opt_str(buf, account.pocket_of.as_deref());
+                }
+            }
+        }
+    }
+
+    fn decode(cursor: &mut Cursor) -> Option<Record> {
+        let record = match cursor.u8()? {
+            tag @ (TAG_INSERT | TAG_UPDATE) => {
+                let account_id = cursor.string()?;
+                let currency = cursor.string()?;
+                let minor_units = cursor.u32()?;
+                let rounding = cursor.rounding()?;
+                let ledgers = cursor.ledgers()?;
+                let holds = cursor.holds()?;
+                let mut balance = Balance::restore(&currency, minor_units, ledgers)
+                    .with_rounding(rounding)
+                    .with_policy(cursor.policy()?)
+                    .with_limits(cursor.limits()?);
+                balance.restore_holds(holds);
+                balance.restore_status_history(cursor.status_history()?);
+                let balance = balance.with_pocket_of(cursor.opt_string()?);
+                if tag == TAG_INSERT {
+                    Record::Insert {
+                        account_id,
+                        balance,
+                    }
+                } else {
+                    Record::Update {
+                        account_id,
+                        balance,
+                    }
+                }
+            }
+            TAG_DELETE => Record::Delete {
+                account_id: cursor.string()?,
+            },
+            TAG_APPEND => Record::Append {
+                account_id: cursor.string()?,
+                ledger: cursor.ledger()?,
+            },
+            TAG_HOLDS => Record::Holds {
+                account_id: cursor.string()?,
+                holds: cursor.holds()?,
+            },
+            TAG_STATUS => Record::Status {
+                account_id: cursor.string()?,
+                change: cursor.status_change()?,
+            },
+            TAG_SNAPSHOT => {
+                let archive_len = cursor.u64()?;
+                let accounts = (0..cursor.u32()?)
+                    .map(|_| {
+                        let account_id = cursor.string()?;
+                        let currency = cursor.string()?;
+                        let minor_units = cursor.u32()?;
+                        let rounding = cursor.rounding()?;
+                        let ledgers = cursor.ledgers()?;
+                        Some(AccountSnapshot {
+                            account_id,
+                            currency,
+                            minor_units,
+                            rounding,
+                            balance: ledgers.sum(),
+                            last_ledger_id: ledgers.last_id().map(str::to_string),
+                            ledgers,
+                            holds: cursor.holds()?,
+                            policy: cursor.policy()?,
+                            limits: cursor.limits()?,
+                            status_history: cursor.status_history()?,
+                            pocket_of: cursor.opt_string()?,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Record::Snapshot {
+                    archive_len,
+                    accounts,
+                }
+            }
+            _ => return None,
+        };
+        Some(record)
+    }
+
+    fn apply(self, balances: &mut HashMap<String, Balance>) -> Result<(), StorageError> {
+        match self {
+            Record::Insert {
+                account_id,
+                balance,
+            }
+            | Record::Update {
+                account_id,
+                balance,
+            } => {
+                balances.insert(account_id, balance);
+            }
+            Record::Delete { account_id } => {
+                balances.remove(&account_id);
+            }
+            Record::Append { account_id, ledger } => {
+                balances
+                    .get_mut(&account_id)
+                    .ok_or_else(|| corrupt("ledger for unknown account"))?
+                    .restore_ledger(ledger)
+                    .map_err(|_| corrupt("duplicate ledger"))?;
+            }
+            Record::Holds { account_id, holds } => {
+                balances
+                    .get_mut(&account_id)
+                    .ok_or_else(|| corrupt("holds for unknown account"))?
+                    .restore_holds(holds);
+            }
+            Record::Status { account_id, change } => {
+                let balance = balances
+                    .get_mut(&account_id)
+                    .ok_or_else(|| corrupt("status change for unknown account"))?;
+                let mut history = balance.status_history().to_vec();
+                history.push(change);
+                balance.restore_status_history(history);
+            }
+            Record::Snapshot { accounts, .. } => {
+                balances.clear();
+                for account in accounts {
+                    let mut balance =
+                        Balance::restore(&account.currency, account.minor_units, account.ledgers)
+                            .with_rounding(account.rounding)
+                            .with_policy(account.policy)
+                            .with_limits(account.limits)
+                            .with_pocket_of(account.pocket_of);
+                    balance.restore_holds(account.holds);
+                    balance.restore_status_history(account.status_history);
+                    balances.insert(account.account_id, balance);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Every ledger this record posts, for audit export.
+    fn ledgers(&self) -> Vec<(&str, &Ledger)> {
+        match self {
+            Record::Insert {
+                account_id,
+                balance,
+            }
+            | Record::Update {
+                account_id,
+                balance,
+            } => balance
+                .ledgers()
+                .entries()
+                .iter()
+                .map(|ledger| (account_id.as_str(), ledger))
+                .collect(),
+            Record::Append { account_id, ledger } => vec![(account_id, ledger)],
+            Record::Delete { .. }
+            | Record::Holds { .. }
+            | Record::Status { .. }
+            | Record::Snapshot { .. } => vec![],
+        }
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_u32(buf, s.len() as u32);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn put_opt_str(buf: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        Some(s) => {
+            buf.push(1);
+            put_str(buf, s);
+        }
+        None => buf.push(0),
+    }
+}
+
+fn put_timestamp(buf: &mut Vec<u8>, at: DateTime<Utc>) {
+    buf.extend_from_slice(&at.timestamp_micros().to_le_bytes());
+}
+
+fn put_policy(buf: &mut Vec<u8>, policy: Policy) {
+    put_str(buf, policy.as_str());
+    put_opt_str(
+        buf,
+        policy.amount().map(|amount| amount.to_string()).as_deref(),
+    );
+}
+
+fn put_limits(buf: &mut Vec<u8>, limits: VelocityLimits) {
+    for limit in [limits.daily, limits.monthly] {
+        let max_amount = limit.max_amount.map(|amount| amount.to_string());
+        put_opt_str(buf, max_amount.as_deref());
+        match limit.max_count {
+            Some(count) => {
+                buf.push(1);
+                put_u32(buf, count);
+            }
+            None => buf.push(0),
+        }
+    }
+}
+
+fn put_status_change(buf: &mut Vec<u8>, change: &StatusChange) {
+    put_str(buf, change.from().as_str());
+    put_str(buf, change.to().as_str());
+    put_str(buf, change.reason());
+    put_timestamp(buf, change.changed_at());
+}
+
+fn put_status_history(buf: &mut Vec<u8>, history: &[StatusChange]) {
+    put_u32(buf, history.len() as u32);
+    for change in history {
+        put_status_change(buf, change);
+    }
+}
+
+fn put_holds(buf: &mut Vec<u8>, holds: &[Hold]) {
+    put_u32(buf, holds.len() as u32);
+    for hold in holds {
+        put_str(buf, hold.id());
+        put_str(buf, &hold.amount().to_string());
+        put_timestamp(buf, hold.placed_at());
+        match hold.expires_at() {
+            Some(at) => {
+                buf.push(1);
+                put_timestamp(buf, at);
+            }
+            None => buf.push(0),
+        }
+    }
+}
+
+fn put_amounts(buf: &mut Vec<u8>, amounts: &BTreeMap<String, Decimal>) {
+    put_u32(buf, amounts.len() as u32);
+    for (id, amount) in amounts {
+        put_str(buf, id);
+        put_str(buf, &amount.to_string());
+    }
+}
+
+// What the collection carried forward from compaction, then its entries.
+fn put_ledgers(buf: &mut Vec<u8>, ledgers: &Ledgers) {
+    let carried = ledgers.carried();
+    put_str(buf, &carried.opening.to_string());
+    put_opt_str(buf, carried.opening_id.as_deref());
+    put_amounts(buf, &carried.reversible);
+    put_amounts(buf, &carried.reversed);
+    put_u32(buf, carried.keys.len() as u32);
+    for (key, entry) in &carried.keys {
+        put_str(buf, key);
+        put_str(buf, &entry.ledger_id);
+        put_str(buf, entry.action.as_str());
+        put_str(buf, &entry.amount.to_string());
+        put_str(buf, &entry.total.to_string());
+    }
+    put_u32(buf, ledgers.len() as u32);
+    for ledger in ledgers.entries() {
+        put_ledger(buf, ledger);
+    }
+}
+
+fn put_ledger(buf: &mut Vec<u8>, ledger: &Ledger) {
+    put_str(buf, ledger.id());
+    put_str(buf, ledger.action().as_str());
+    put_str(buf, &ledger.amount().to_string());
+    put_opt_str(buf, ledger.transfer_id());
+    put_timestamp(buf, ledger.booked_at());
+    buf.extend_from_slice(&ledger.value_date().num_days_from_ce().to_le_bytes());
+    put_opt_str(buf, ledger.idempotency_key());
+    put_opt_str(buf, ledger.reverses());
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        let bytes = self.buf.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    // `Some(None)` for an absent value, `None` for malformed input.
+    fn opt_string(&mut self) -> Option<Option<String>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => Some(Some(self.string()?)),
+            _ => None,
+        }
+    }
+
+    fn decimal(&mut self) -> Option<Decimal> {
+        Decimal::from_str(&self.string()?).ok()
+    }
+
+    fn timestamp(&mut self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp_micros(self.u64()? as i64)
+    }
+
+    fn holds(&mut self) -> Option<Vec<Hold>> {
+        (0..self.u32()?)
+            .map(|_| {
+                let id = self.string()?;
+                let amount = self.decimal()?;
+                let placed_at = self.timestamp()?;
+                let expires_at = match self.u8()? {
+                    0 => None,
+                    1 => Some(self.timestamp()?),
+                    _ => return None,
+                };
+                Some(Hold::restore(id, amount, placed_at, expires_at))
+            })
+            .collect()
+    }
+
+    fn rounding(&mut self) -> Option<Rounding> {
+        Rounding::restore(&self.string()?)
+    }
+
+    fn policy(&mut self) -> Option<Policy> {
+        let name = self.string()?;
+        let amount = match self.u8()? {
+            0 => None,
+            1 => Some(self.decimal()?),
+            _ => return None,
+        };
+        Policy::restore(&name, amount)
+    }
+
+    fn limits(&mut self) -> Option<VelocityLimits> {
+        let mut limit = || {
+            let max_amount = match self.u8()? {
+                0 => None,
+                1 => Some(self.decimal()?),
+                _ => return None,
+            };
+            let max_count = match self.u8()? {
+                0 => None,
+                1 => Some(self.u32()?),
+                _ => return None,
+            };
+            Some(Limit {
+                max_amount,
+                max_count,
+            })
+        };
+        Some(VelocityLimits {
+            daily: limit()?,
+            monthly: limit()?,
+        })
+    }
+
+    fn status(&mut self) -> Option<AccountStatus> {
+        AccountStatus::restore(&self.string()?)
+    }
+
+    fn status_change(&mut self) -> Option<StatusChange> {
+        let from = self.status()?;
+        let to = self.status()?;
+        let reason = self.string()?;
+        Some(StatusChange::restore(from, to, reason, self.timestamp()?))
+    }
+
+    fn status_history(&mut self) -> Option<Vec<StatusChange>> {
+        (0..self.u32()?).map(|_| self.status_change()).collect()
+    }
+
+    fn ledger(&mut self) -> Option<Ledger> {
+        let id = self.string()?;
+        let action = self.string()?.parse().ok()?;
+        let amount = self.decimal()?;
+        let transfer_id = self.opt_string()?;
+        let booked_at = self.timestamp()?;
+        let value_date = NaiveDate::from_num_days_from_ce_opt(self.u32()? as i32)?;
+        let links = Links {
+            transfer_id,
+            idempotency_key: self.opt_string()?,
+            reverses: self.opt_string()?,
+        };
+        Some(Ledger::restore(
+            id, action, amount, booked_at, value_date, links,
+        ))
+    }
+
+    fn amounts(&mut self) -> Option<BTreeMap<String, Decimal>> {
+        (0..self.u32()?)
+            .map(|_| Some((self.string()?, self.decimal()?)))
+            .collect()
+    }
+
+    fn keyed_entries(&mut self) -> Option<BTreeMap<String, KeyedEntry>> {
+        let count = self.u32()?;
+        let mut entry = || {
+            let key = self.string()?;
+            let entry = KeyedEntry {
+                ledger_id: self.string()?,
+                action: self.string()?.parse().ok()?,
+                amount: self.decimal()?,
+                total: self.decimal()?,
+            };
+            Some((key, entry))
+        };
+        (0..count).map(|_| entry()).collect()
+    }
+
+    fn ledgers(&mut self) -> Option<Ledgers> {
+        let carried = Carried {
+            opening: self.decimal()?,
+            opening_id: self.opt_string()?,
+            reversible: self.amounts()?,
+            reversed: self.amounts()?,
+            keys: self.keyed_entries()?,
+        };
+        let mut ledgers = Ledgers::carried_forward(carried);
+        for _ in 0..self.u32()? {
+            ledgers.add(self.ledger()?).ok()?;
+        }
+        Some(ledgers)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+}
+
+fn corrupt(reason: &str) -> StorageError {
+    StorageError::Backend(format!("corrupt journal: {reason}"))
+}
+
+fn unsupported(version: &str) -> StorageError {
+    StorageError::Backend(format!("unsupported journal version: {version}"))
+}
+
+fn file_header() -> [u8; FILE_HEADER] {
+    let mut header = [VERSION; FILE_HEADER];
+    header[..MAGIC.len()].copy_from_slice(MAGIC);
+    header
+}
+
+// Checks the header `bytes` start with. A file cut short within its header
+// was torn while being created, and counts as empty.
+fn check_header(bytes: &[u8]) -> Result<bool, StorageError> {
+    if bytes.len() < FILE_HEADER && file_header().starts_with(bytes) {
+        return Ok(false);
+    }
+    if !bytes.starts_with(MAGIC) {
+        return Err(unsupported("no format header"));
+    }
+    match bytes.get(MAGIC.len()) {
+        Some(&VERSION) => Ok(true),
+        Some(version) => Err(unsupported(&version.to_string())),
+        None => Err(unsupported("no format header")),
+    }
+}
+
+// Opens the journal or archive at `path` for appending, writing a fresh
+// header if it is new.
+fn open_versioned(path: &Path) -> Result<File, StorageError> {
+    let mut file = open_append(path)?;
+    let mut header = vec![];
+    (&file)
+        .take(FILE_HEADER as u64)
+        .read_to_end(&mut header)
+        .map_err(io_error)?;
+    if !check_header(&header)? {
+        file.set_len(0).map_err(io_error)?;
+        file.write_all(&file_header()).map_err(io_error)?;
+        file.sync_all().map_err(io_error)?;
+    }
+    Ok(file)
+}
+
+fn io_error(e: std::io::Error) -> StorageError {
+    StorageError::Backend(format!("journal: {e}"))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(suffix);
+    path.into()
+}
+
+fn open_append(path: &Path) -> Result<File, StorageError> {
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(io_error)
+}
+
+fn encode_frame(records: &[Record]) -> Vec<u8> {
+    let mut payload = vec![];
+    put_u32(&mut payload, records.len() as u32);
+    for record in records {
+        record.encode(&mut payload);
+    }
+    let mut frame = Vec::with_capacity(FRAME_HEADER + payload.len());
+    put_u32(&mut frame, payload.len() as u32);
+    put_u32(&mut frame, crc32fast::hash(&payload));
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+/// Append-only journal of `InMemory` writes.
+///
+/// Compaction moves the journal's frames, unchanged, onto the end of an
+/// archive file next to it (`<path>.archive`) and starts a new journal whose
+/// first frame is a snapshot of every account. Recovery only replays the
+/// journal; the archive is kept for `export_ledgers`. Both files carry the
+/// format version, and one in an unknown version fails to open.
+pub(crate) struct Journal {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    // Length of the leading snapshot frame, which isn't history to archive.
+    head_len: u64,
+    archive_len: u64,
+    compact_after: Option<u64>,
+}
+
+impl Journal {
+    /// Opens (or creates) the journal at `path` and replays it into a fresh
+    /// balance map.
+    ///
+    /// A last frame that is short or fails its checksum is treated as a write
+    /// torn by a crash: it is discarded and the file is truncated back to the
+    /// last complete frame. A damaged frame with more data after it is
+    /// corruption, not a torn write, and fails the open without touching the
+    /// file.
+    pub(crate) fn open<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<(Journal, HashMap<String, Balance>), StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = open_versioned(&path)?;
+        let mut bytes = vec![];
+        file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        file.read_to_end(&mut bytes).map_err(io_error)?;
+
+        let mut balances = HashMap::new();
+        let mut offset = FILE_HEADER;
+        let mut head_len = FILE_HEADER as u64;
+        let mut archive_len = FILE_HEADER as u64;
+        while let Some((records, next)) = read_frame(&bytes, offset)? {
+            for record in records {
+                if let Record::Snapshot { archive_len: n, .. } = record {
+                    if offset != FILE_HEADER {
+                        return Err(corrupt("snapshot after the first frame"));
+                    }
+                    head_len = next as u64;
+                    archive_len = n;
+                }
+                record.apply(&mut balances)?;
+            }
+            offset = next;
+        }
+        if offset < bytes.len() {
+            file.set_len(offset as u64).map_err(io_error)?;
+            file.sync_all().map_err(io_error)?;
+        }
+
+        // A crash mid-compaction can leave frames on the archive that the
+        // journal still holds; drop them so they aren't exported twice.
+        let archive = open_versioned(&with_suffix(&path, ".archive"))?;
+        let actual = archive.metadata().map_err(io_error)?.len();
+        if actual < archive_len {
+            return Err(corrupt("archive is shorter than the snapshot expects"));
+        }
+        if actual > archive_len {
+            archive.set_len(archive_len).map_err(io_error)?;
+            archive.sync_all().map_err(io_error)?;
+        }
+
+        let journal = Journal {
+            path,
+            file,
+            len: offset as u64,
+            head_len,
+            archive_len,
+            compact_after: None,
+        };
+        Ok((journal, balances))
+    }
+
+    pub(crate) fn set_compact_after(&mut self, bytes: Option<u64>) {
+        self.compact_after = bytes;
+    }
+
+    /// Whether the history since the last snapshot exceeds the configured
+    /// compaction threshold.
+    pub(crate) fn compaction_due(&self) -> bool {
+        self.compact_after
+            .is_some_and(|limit| self.len - self.head_len > limit)
+    }
+
+    /// Durably appends `records` as a single frame. On failure the file is
+    /// truncated back to its previous length, so a partly written frame
+    /// can't end up in the middle of the journal.
+    pub(crate) fn write(&mut self, records: &[Record]) -> Result<(), StorageError> {
+        let frame = encode_frame(records);
+        let written = self
+            .file
+            .write_all(&frame)
+            .and_then(|()| self.file.sync_data());
+        if let Err(e) = written {
+            // If this fails too, the next open reports the damaged frame.
+            let _ = self.file.set_len(self.len);
+            return Err(io_error(e));
+        }
+        self.len += frame.len() as u64;
+        Ok(())
+    }
+
+    /// Archives the journal's history and replaces it with a single snapshot
+    /// frame. `accounts` must describe the state the journal replays to.
+    pub(crate) fn compact(&mut self, accounts: Vec<AccountSnapshot>) -> Result<(), StorageError> {
+        let mut bytes = vec![];
+        let mut file = File::open(&self.path).map_err(io_error)?;
+        file.read_to_end(&mut bytes).map_err(io_error)?;
+        let history = &bytes[self.head_len as usize..];
+
+        let mut archive = open_append(&with_suffix(&self.path, ".archive"))?;
+        archive.write_all(history).map_err(io_error)?;
+        archive.sync_data().map_err(io_error)?;
+        let archive_len = self.archive_len + history.len() as u64;
+
+        // The rename is the commit point: until then the old journal and the
+        // old snapshot's `archive_len` stay authoritative.
+        let mut head = file_header().to_vec();
+        head.extend(encode_frame(&[Record::Snapshot {
+            archive_len,
+            accounts,
+        }]));
+        let tmp = with_suffix(&self.path, ".compact");
+        let mut file = File::create(&tmp).map_err(io_error)?;
+        file.write_all(&head).map_err(io_error)?;
+        file.sync_all().map_err(io_error)?;
+        fs::rename(&tmp, &self.path).map_err(io_error)?;
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            File::open(dir)
+                .and_then(|d| d.sync_all())
+                .map_err(io_error)?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.len = head.len() as u64;
+        self.head_len = self.len;
+        self.archive_len = archive_len;
+        Ok(())
+    }
+
+    /// Every ledger ever journaled, archived or not, in posting order.
+    pub(crate) fn export_ledgers(&self) -> Result<Vec<(String, Ledger)>, StorageError> {
+        let mut seen = HashSet::new();
+        let mut ledgers = vec![];
+        for path in [with_suffix(&self.path, ".archive"), self.path.clone()] {
+            let bytes = fs::read(path).map_err(io_error)?;
+            check_header(&bytes)?;
+            let mut offset = FILE_HEADER;
+            while let Some((records, next)) = read_frame(&bytes, offset)? {
+                for record in &records {
+                    for (account_id, ledger) in record.ledgers() {
+                        // Updates restate ledgers that were already posted.
+                        if seen.insert((account_id.to_string(), ledger.id().to_string())) {
+                            ledgers.push((account_id.to_string(), ledger.clone()));
+                        }
+                    }
+                }
+                offset = next;
+            }
+        }
+        Ok(ledgers)
+    }
+}
+
+// Returns the records of the frame starting at `offset` and the offset of the
+// next frame, or `None` when the rest of `bytes` is a torn frame: one cut
+// short, or one that fails its checksum and ends exactly at the end.
+fn read_frame(bytes: &[u8], offset: usize) -> Result<Option<(Vec<Record>, usize)>, StorageError> {
+    let mut cursor = Cursor {
+        buf: bytes,
+        pos: offset,
+    };
+    let (Some(len), Some(checksum)) = (cursor.u32(), cursor.u32()) else {
+        return Ok(None);
+    };
+    let Some(payload) = cursor.take(len as usize) else {
+        return Ok(None);
+    };
+    if crc32fast::hash(payload) != checksum {
+        if cursor.pos == bytes.len() {
+            return Ok(None);
+        }
+        return Err(corrupt("checksum mismatch before the last frame"));
+    }
+
+    let mut payload = Cursor {
+        buf: payload,
+        pos: 0,
+    };
+    let count = payload
+        .u32()
+        .ok_or_else(|| corrupt("missing record count"))?;
+    let mut records = vec![];
+    for _ in 0..count {
+        records.push(Record::decode(&mut payload).ok_or_else(|| corrupt("malformed record"))?);
+    }
+    if !payload.is_empty() {
+        return Err(corrupt("trailing bytes in frame"));
+    }
+    Ok(Some((records, cursor.pos)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::BalanceError;
+    use crate::clock::{FixedClock, SystemClock};
+    use crate::fx::{Converter, StaticRates};
+    use crate::id::time_ordered;
+    use crate::journal::InternalAccount;
+    use crate::ledger::Action;
+    use crate::limits::VelocityLimit;
+    use crate::status::AccountStatus;
+    use crate::storage::test_util::TempPath;
+    use crate::storage::{InMemory, Storage};
+    use rust_decimal::RoundingStrategy;
+    use rust_decimal_macros::dec;
+
+    fn snapshot(storage: &InMemory) -> Vec<(String, Balance)> {
+        let ids = storage.list().unwrap();
+        ids.into_iter()
+            .map(|id| {
+                let balance = storage.get(&id).unwrap();
+                (id, balance)
+            })
+            .collect()
+    }
+
+    fn deposit(amount: &str) -> Ledger {
+        Ledger::new(Action::Deposit(amount.to_string()), time_ordered()).unwrap()
+    }
+
+    // Runs a mix of writes against a journaled store, returning the journal
+    // length and the visible state after each one.
+    fn write_history(path: &TempPath) -> Vec<(u64, Vec<(String, Balance)>)> {
+        let mut storage = InMemory::open(path).unwrap();
+        let mut history = vec![(FILE_HEADER as u64, vec![])];
+        let mut checkpoint = |storage: &InMemory| {
+            let len = std::fs::metadata(path).unwrap().len();
+            history.push((len, snapshot(storage)));
+        };
+
+        let mut funded = Balance::new("EUR").unwrap();
+        funded.mutate(deposit("5")).unwrap();
+        storage.insert("account_1", funded).unwrap();
+        checkpoint(&storage);
+        let overdrawn = Balance::new("EUR")
+            .unwrap()
+            .with_rounding(Rounding::Round(RoundingStrategy::MidpointNearestEven))
+            .with_policy(Policy::overdraft("10").unwrap());
+        storage.insert("account_2", overdrawn).unwrap();
+        checkpoint(&storage);
+        storage.append("account_1", deposit("100.25")).unwrap();
+        checkpoint(&storage);
+        storage.transfer("account_1", "account_2", "40").unwrap();
+        checkpoint(&storage);
+        storage
+            .with_account_mut("account_2", |balance| {
+                balance.mutate(deposit("1")).unwrap();
+                balance.mutate(deposit("2")).unwrap();
+            })
+            .unwrap();
+        checkpoint(&storage);
+        let mut replaced = storage.get("account_1").unwrap();
+        replaced.mutate(deposit("0.75")).unwrap();
+        storage.update("account_1", replaced).unwrap();
+        checkpoint(&storage);
+        storage
+            .insert("account_3", Balance::new("USD").unwrap())
+            .unwrap();
+        checkpoint(&storage);
+        storage.delete("account_3").unwrap();
+        checkpoint(&storage);
+
+        history
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let ledger = deposit("12.5")
+            .with_transfer_id(std::sync::Arc::new("t1".to_string()))
+            .with_idempotency_key("req-1".to_string());
+        let refund = Ledger::reversing(ledger.id(), dec!(2.5), time_ordered(), &SystemClock);
+        let mut frozen = Balance::new("USD").unwrap();
+        frozen.transition(AccountStatus::Frozen, "kyc").unwrap();
+        let records = vec![
+            Record::insert("a", &Balance::new("USD").unwrap()),
+            Record::insert("b", &frozen),
+            Record::update(
+                "a",
+                &Balance::new("USD")
+                    .unwrap()
+                    .with_policy(Policy::overdraft("100").unwrap())
+                    .with_limits(VelocityLimits {
+                        daily: Limit {
+                            max_amount: Some(dec!(20.5)),
+                            max_count: Some(3),
+                        },
+                        monthly: Limit::default(),
+                    }),
+            ),
+            Record::append("a", &ledger),
+            Record::append("a", &refund),
+            Record::holds("a", &[Hold::new("3", time_ordered()).unwrap()]),
+            Record::status("a", &frozen.status_history()[0]),
+            Record::delete("a"),
+        ];
+        let mut buf = vec![];
+        for record in &records {
+            record.encode(&mut buf);
+        }
+        let mut cursor = Cursor { buf: &buf, pos: 0 };
+        for record in records {
+            assert_eq!(Record::decode(&mut cursor), Some(record));
+        }
+        assert!(cursor.is_empty());
+    }
+
+    #[test]
+    fn test_replay_rebuilds_state() {
+        let path = TempPath::new("wal");
+        let history = write_history(&path);
+
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(snapshot(&storage), history.last().unwrap().1);
+        assert_eq!(storage.get("account_1").unwrap().amount(), dec!(66));
+        assert_eq!(storage.get("account_2").unwrap().amount(), dec!(43));
+    }
+
+    #[test]
+    fn test_idempotency_key_survives_reopen() {
+        let path = TempPath::new("wal");
+        let keyed = || deposit("10").with_idempotency_key("req-1".to_string());
+        {
+            let mut storage = InMemory::open(&path).unwrap();
+            storage
+                .insert("account_1", Balance::new("USD").unwrap())
+                .unwrap();
+            assert_eq!(storage.append("account_1", keyed()), Ok(dec!(10)));
+        }
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        let mut storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.append("account_1", keyed()), Ok(dec!(10)));
+        assert_eq!(storage.get("account_1").unwrap().ledgers().len(), 1);
+        // Nothing new was journaled for the replay.
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_holds_survive_reopen_and_compaction() {
+        let path = TempPath::new("wal");
+        let hold = Hold::new("30", time_ordered())
+            .unwrap()
+            .with_expiry(Utc::now() + chrono::Duration::days(7));
+        let hold_id = hold.id().to_string();
+        {
+            let mut storage = InMemory::open(&path).unwrap();
+            let mut funded = Balance::new("USD").unwrap();
+            funded.mutate(deposit("100")).unwrap();
+            storage.insert("account_1", funded).unwrap();
+            storage.place_hold("account_1", hold.clone()).unwrap();
+            storage
+                .place_hold("account_1", Hold::new("20", time_ordered()).unwrap())
+                .unwrap();
+            let other = storage.get("account_1").unwrap().holds()[1]
+                .id()
+                .to_string();
+            storage.release_hold("account_1", &other).unwrap();
+        }
+
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(
+            storage.get("account_1").unwrap().holds(),
+            std::slice::from_ref(&hold)
+        );
+        storage.compact().unwrap();
+
+        let mut storage = InMemory::open(&path).unwrap();
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.holds(), [hold]);
+        assert_eq!(balance.available(), dec!(70));
+        assert_eq!(
+            storage.capture_hold("account_1", &hold_id, Some("10")),
+            Ok(dec!(90))
+        );
+        let storage = InMemory::open(&path).unwrap();
+        let balance = storage.get("account_1").unwrap();
+        assert!(balance.holds().is_empty());
+        assert_eq!(balance.amount(), dec!(90));
+    }
+
+    #[test]
+    fn test_status_history_survives_reopen_and_compaction() {
+        let path = TempPath::new("wal");
+        {
+            let mut storage = InMemory::open(&path).unwrap();
+            storage
+                .insert("account_1", Balance::new("USD").unwrap())
+                .unwrap();
+            storage
+                .set_status("account_1", AccountStatus::Frozen, "kyc pending")
+                .unwrap();
+        }
+        let storage = InMemory::open(&path).unwrap();
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.status(), AccountStatus::Frozen);
+        storage.compact().unwrap();
+
+        let mut storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.get("account_1").unwrap(), balance);
+        storage
+            .set_status("account_1", AccountStatus::Active, "kyc passed")
+            .unwrap();
+        let storage = InMemory::open(&path).unwrap();
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.status(), AccountStatus::Active);
+        assert_eq!(balance.status_history().len(), 2);
+    }
+
+    #[test]
+    fn test_pockets_survive_reopen_and_compaction() {
+        let path = TempPath::new("wal");
+        {
+            let mut storage = InMemory::open(&path).unwrap();
+            storage
+                .insert("account_1", Balance::new("USD").unwrap())
+                .unwrap();
+            storage.open_pocket("account_1", "EUR").unwrap();
+            let ledger = Ledger::new(Action::Deposit("7.25".to_string()), time_ordered()).unwrap();
+            storage
+                .append_to_pocket("account_1", "EUR", ledger)
+                .unwrap();
+        }
+        let storage = InMemory::open(&path).unwrap();
+        let wallet = storage.wallet("account_1").unwrap();
+        assert_eq!(wallet.currencies().collect::<Vec<_>>(), ["EUR", "USD"]);
+        assert_eq!(wallet.pocket("EUR").unwrap().amount(), dec!(7.25));
+        assert_eq!(storage.list().unwrap(), ["account_1"]);
+        storage.compact().unwrap();
+
+        let mut storage = InMemory::open(&path).unwrap();
+        let compacted = storage.wallet("account_1").unwrap();
+        assert_eq!(compacted.pocket("EUR").unwrap().amount(), dec!(7.25));
+        assert_eq!(
+            compacted.pocket("EUR").unwrap().pocket_of(),
+            Some("account_1")
+        );
+        storage.delete("account_1").unwrap();
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.list().unwrap(), Vec::<String>::new());
+        assert_eq!(
+            storage.get_pocket("account_1", "EUR"),
+            Err(StorageError::AccountNotExists)
+        );
+    }
+
+    #[test]
+    fn test_conversions_survive_reopen_and_compaction() {
+        let path = TempPath::new("wal");
+        let rates = StaticRates::new()
+            .with_rate("EUR", "USD", "1.0850")
+            .unwrap();
+        let converter = Converter::new(rates);
+        let residue_id = InternalAccount::FxResidue.account_id("EUR");
+        let (wallet, residue) = {
+            let mut storage = InMemory::open(&path).unwrap();
+            storage
+                .insert("account_1", Balance::new("USD").unwrap())
+                .unwrap();
+            storage.open_pocket("account_1", "EUR").unwrap();
+            let ledger = Ledger::new(Action::Deposit("10".to_string()), time_ordered()).unwrap();
+            storage.append("account_1", ledger).unwrap();
+            // The inverse rate leaves a residue finer than any amount
+            // `parse_amount` accepts.
+            let conversion = storage
+                .convert("account_1", "USD", "EUR", "10", &converter)
+                .unwrap();
+            assert!(conversion.quote.residue.scale() > 18);
+            let residue = storage.get(&residue_id).unwrap();
+            assert_eq!(residue.amount(), conversion.quote.residue);
+            (storage.wallet("account_1").unwrap(), residue)
+        };
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.wallet("account_1").unwrap(), wallet);
+        assert_eq!(storage.get(&residue_id).unwrap(), residue);
+        storage.compact().unwrap();
+
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.get(&residue_id).unwrap().amount(), residue.amount());
+        assert_eq!(
+            storage
+                .wallet("account_1")
+                .unwrap()
+                .pocket("EUR")
+                .unwrap()
+                .amount(),
+            dec!(9.21)
+        );
+    }
+
+    #[test]
+    fn test_crash_at_every_byte_offset() {
+        let path = TempPath::new("wal");
+        let history = write_history(&path);
+        let bytes = std::fs::read(&path).unwrap();
+
+        for offset in 0..=bytes.len() {
+            let torn = TempPath::new("wal");
+            std::fs::write(&torn, &bytes[..offset]).unwrap();
+
+            // A torn header is written afresh.
+            let (len, expected) = history
+                .iter()
+                .rev()
+                .find(|(len, _)| *len as usize <= offset)
+                .unwrap_or(&history[0]);
+            let storage = InMemory::open(&torn).unwrap();
+            assert_eq!(&snapshot(&storage), expected, "crash at byte {offset}");
+            assert_eq!(std::fs::metadata(&torn).unwrap().len(), *len);
+        }
+    }
+
+    #[test]
+    fn test_recovered_journal_accepts_new_writes() {
+        let path = TempPath::new("wal");
+        let history = write_history(&path);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+
+        {
+            let mut storage = InMemory::open(&path).unwrap();
+            assert_eq!(snapshot(&storage), history[history.len() - 2].1);
+            storage.append("account_2", deposit("7")).unwrap();
+        }
+
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.get("account_2").unwrap().amount(), dec!(50));
+        // The torn delete was discarded.
+        assert!(storage.get("account_3").is_ok());
+    }
+
+    #[test]
+    fn test_checksum_mismatch_in_last_frame_is_truncated() {
+        let path = TempPath::new("wal");
+        let history = write_history(&path);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let storage = InMemory::open(&path).unwrap();
+        let (len, expected) = &history[history.len() - 2];
+        assert_eq!(&snapshot(&storage), expected);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), *len);
+    }
+
+    #[test]
+    fn test_damaged_frame_before_the_last_is_an_error() {
+        let path = TempPath::new("wal");
+        let history = write_history(&path);
+        let mut bytes = std::fs::read(&path).unwrap();
+        // The last byte of the second frame.
+        let (end, _) = history[2];
+        bytes[end as usize - 1] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        assert!(matches!(
+            InMemory::open(&path),
+            Err(StorageError::Backend(e)) if e.contains("checksum mismatch")
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_unsupported_journal_version() {
+        let path = TempPath::new("wal");
+        write_history(&path);
+        let bytes = std::fs::read(&path).unwrap();
+        let expect_unsupported = |path: &TempPath, version: &str| {
+            assert_eq!(
+                InMemory::open(path).err(),
+                Some(StorageError::Backend(format!(
+                    "unsupported journal version: {version}"
+                )))
+            );
+        };
+
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        std::fs::write(&path, &newer).unwrap();
+        expect_unsupported(&path, &(VERSION + 1).to_string());
+        assert_eq!(std::fs::read(&path).unwrap(), newer);
+
+        // Journals from before the header existed start with a frame.
+        std::fs::write(&path, &bytes[FILE_HEADER..]).unwrap();
+        expect_unsupported(&path, "no format header");
+    }
+
+    #[test]
+    fn test_snapshot_reports_running_balances() {
+        let path = TempPath::new("wal");
+        write_history(&path);
+        let storage = InMemory::open(&path).unwrap();
+
+        let snapshot = storage.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].account_id, "account_1");
+        assert_eq!(snapshot[0].currency, "EUR");
+        assert_eq!(snapshot[0].balance, dec!(66));
+        let account_1 = storage.get("account_1").unwrap();
+        let last = account_1.ledgers().entries().last().unwrap();
+        assert_eq!(snapshot[0].last_ledger_id.as_deref(), Some(last.id()));
+    }
+
+    #[test]
+    fn test_compact_replaces_history_with_snapshot() {
+        let path = TempPath::new("wal");
+        write_history(&path);
+        let storage = InMemory::open(&path).unwrap();
+        let before = storage.snapshot();
+        let entries = storage
+            .get("account_1")
+            .unwrap()
+            .ledgers()
+            .entries()
+            .to_vec();
+        let journal_len = std::fs::metadata(&path).unwrap().len();
+
+        storage.compact().unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() < journal_len);
+
+        let mut storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.snapshot(), before);
+        // Everything was booked within the longest velocity window.
+        let account_1 = storage.get("account_1").unwrap();
+        assert_eq!(account_1.ledgers().entries(), entries);
+        assert_eq!(
+            storage.get("account_2").unwrap().policy(),
+            Policy::overdraft("10").unwrap()
+        );
+        assert_eq!(account_1.amount(), dec!(66));
+
+        // Writes after a snapshot replay on top of it.
+        storage.append("account_1", deposit("4")).unwrap();
+        let withdrawal =
+            Ledger::new(Action::Withdrawal("70.01".to_string()), time_ordered()).unwrap();
+        assert!(storage.append("account_1", withdrawal).is_err());
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.get("account_1").unwrap().amount(), dec!(70));
+    }
+
+    #[test]
+    fn test_export_ledgers_includes_compacted_history() {
+        let path = TempPath::new("wal");
+        write_history(&path);
+        let storage = InMemory::open(&path).unwrap();
+        let exported = storage.export_ledgers().unwrap();
+        // 3 ledgers on account_1 and 3 on account_2, plus the one added by
+        // the overwrite of account_1.
+        assert_eq!(exported.len(), 7);
+
+        storage.compact().unwrap();
+        let mut storage = InMemory::open(&path).unwrap();
+        storage.append("account_2", deposit("9")).unwrap();
+        storage.compact().unwrap();
+        storage.append("account_2", deposit("10")).unwrap();
+
+        let storage = InMemory::open(&path).unwrap();
+        let all = storage.export_ledgers().unwrap();
+        assert_eq!(all.len(), 9);
+        assert_eq!(all[..7], exported[..]);
+        let total: Decimal = all
+            .iter()
+            .filter(|(id, _)| id == "account_2")
+            .map(|(_, ledger)| ledger.amount())
+            .sum();
+        assert_eq!(total, storage.get("account_2").unwrap().amount());
+    }
+
+    #[test]
+    fn test_compaction_keeps_what_later_writes_need() {
+        let path = TempPath::new("wal");
+        let mut storage = InMemory::open(&path).unwrap();
+        let limits = VelocityLimits {
+            monthly: Limit {
+                max_amount: None,
+                max_count: Some(1),
+            },
+            ..VelocityLimits::default()
+        };
+        storage
+            .insert(
+                "account_1",
+                Balance::new("USD").unwrap().with_limits(limits),
+            )
+            .unwrap();
+        let old = FixedClock::new(Utc::now() - chrono::Duration::days(60));
+        let old_deposit = |amount: &str| {
+            Ledger::new_with_clock(Action::Deposit(amount.to_string()), time_ordered(), &old)
+                .unwrap()
+        };
+        let keyed = |amount: &str| old_deposit(amount).with_idempotency_key("key_1".to_string());
+        storage.append("account_1", keyed("100")).unwrap();
+        let refunded = old_deposit("50");
+        let refunded_id = refunded.id().to_string();
+        storage.append("account_1", refunded).unwrap();
+        let withdrawal =
+            || Ledger::new(Action::Withdrawal("10".to_string()), time_ordered()).unwrap();
+        storage.append("account_1", withdrawal()).unwrap();
+        storage.compact().unwrap();
+
+        let mut storage = InMemory::open(&path).unwrap();
+        let balance = storage.get("account_1").unwrap();
+        assert_eq!(balance.ledgers().len(), 1);
+        assert_eq!(balance.ledgers().opening(), dec!(150));
+        assert_eq!(
+            storage.append("account_1", withdrawal()),
+            Err(StorageError::Balance(BalanceError::VelocityLimitExceeded(
+                VelocityLimit::MonthlyCount
+            )))
+        );
+        let refund = |storage: &InMemory, amount: &str| {
+            storage.get("account_1").unwrap().reversal_of(
+                &refunded_id,
+                Some(amount),
+                time_ordered(),
+                &SystemClock,
+            )
+        };
+        let partial = refund(&storage, "20").unwrap();
+        assert_eq!(storage.append("account_1", partial), Ok(dec!(120)));
+        storage.compact().unwrap();
+
+        let mut storage = InMemory::open(&path).unwrap();
+        let rest = refund(&storage, "30").unwrap();
+        let too_much = refund(&storage, "31").unwrap();
+        assert_eq!(
+            storage.append("account_1", too_much),
+            Err(StorageError::Balance(BalanceError::RefundExceedsOriginal {
+                remaining: dec!(30)
+            }))
+        );
+        assert_eq!(storage.append("account_1", rest), Ok(dec!(90)));
+        // Retries of compacted entries are still recognised.
+        assert_eq!(storage.append("account_1", keyed("100")), Ok(dec!(100)));
+        assert_eq!(
+            storage.append("account_1", keyed("99")),
+            Err(StorageError::Balance(BalanceError::IdempotencyConflict))
+        );
+    }
+
+    #[test]
+    fn test_compaction_threshold() {
+        let path = TempPath::new("wal");
+        let mut storage = InMemory::open(&path)
+            .unwrap()
+            .with_compaction_threshold(1024);
+        storage
+            .insert("account_1", Balance::new("USD").unwrap())
+            .unwrap();
+        // Booked before the longest velocity window, so compaction can drop
+        // them.
+        let old = FixedClock::new(Utc::now() - chrono::Duration::days(60));
+        for posted in 1..=100 {
+            let deposit =
+                Ledger::new_with_clock(Action::Deposit("1".to_string()), time_ordered(), &old)
+                    .unwrap();
+            storage.append("account_1", deposit).unwrap();
+            // Each compacted deposit still leaves its id and amount behind so
+            // it can be refunded, a fraction of the frame that posted it.
+            assert!(std::fs::metadata(&path).unwrap().len() < 1536 + 40 * posted);
+        }
+
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.get("account_1").unwrap().amount(), dec!(100));
+        assert_eq!(storage.export_ledgers().unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_crash_during_compaction() {
+        let path = TempPath::new("wal");
+        write_history(&path);
+        let storage = InMemory::open(&path).unwrap();
+        storage.compact().unwrap();
+        let mut storage = InMemory::open(&path).unwrap();
+        storage.append("account_1", deposit("1")).unwrap();
+        let expected = storage.snapshot();
+        let exported = storage.export_ledgers().unwrap();
+
+        // The history was copied to the archive but the snapshot was never
+        // renamed into place.
+        let journal = std::fs::read(&path).unwrap();
+        let frame_len = &journal[FILE_HEADER..FILE_HEADER + 4];
+        let head_len = FILE_HEADER + 8 + u32::from_le_bytes(frame_len.try_into().unwrap()) as usize;
+        let mut archive = std::fs::read(with_suffix(path.as_ref(), ".archive")).unwrap();
+        let archive_len = archive.len();
+        archive.extend_from_slice(&journal[head_len..]);
+        std::fs::write(with_suffix(path.as_ref(), ".archive"), &archive).unwrap();
+        std::fs::write(with_suffix(path.as_ref(), ".compact"), b"partial").unwrap();
+
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.snapshot(), expected);
+        assert_eq!(storage.export_ledgers().unwrap(), exported);
+        let archive = std::fs::metadata(with_suffix(path.as_ref(), ".archive")).unwrap();
+        assert_eq!(archive.len() as usize, archive_len);
+
+        storage.compact().unwrap();
+        let storage = InMemory::open(&path).unwrap();
+        assert_eq!(storage.snapshot(), expected);
+        assert_eq!(storage.export_ledgers().unwrap(), exported);
+    }
+
+    #[test]
+    fn test_missing_archive_is_an_error() {
+        let path = TempPath::new("wal");
+        write_history(&path);
+        InMemory::open(&path).unwrap().compact().unwrap();
+        std::fs::remove_file(with_suffix(path.as_ref(), ".archive")).unwrap();
+        assert!(matches!(
+            InMemory::open(&path),
+            Err(StorageError::Backend(_))
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::BTreeMap;
+
+use crate::balance::Balance;
+use crate::currency;
+use crate::fx::RateProvider;
+
+#[derive(Debug, PartialEq)]
+pub enum WalletError {
+    InvalidCurrency,
+    MissingRate {
+        from: String,
+        to: String,
+    },
+    /// The total doesn't fit in a `Decimal`.
+    Overflow,
+}
+
+/// Every currency pocket of one account, keyed by currency code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wallet {
+    account_id: String,
+    pockets: BTreeMap<String, Balance>,
+}
+
+impl Wallet {
+    pub(crate) fn new(account_id: &str, pockets: Vec<Balance>) -> Wallet {
+        Wallet {
+            account_id: account_id.to_string(),
+            pockets: pockets
+                .into_iter()
+                .map(|balance| (balance.currency.clone(), balance))
+                .collect(),
+        }
+    }
+
+    pub fn account_id(&self) -> &str {
+        &self.account_id
+    }
+
+    /// Currency codes of the pockets, sorted.
+    pub fn currencies(&self) -> impl Iterator<Item = &str> + '_ {
+        self.pockets.keys().map(String::as_str)
+    }
+
+    pub fn pocket(&self, currency: &str) -> Option<&Balance> {
+        self.pockets.get(&currency.to_uppercase())
+    }
+
+    pub fn pockets(&self) -> impl Iterator<Item = &Balance> + '_ {
+        self.pockets.values()
+    }
+
+    /// Total of every pocket expressed in `currency`, rounded half-to-even
+    /// to its minor units. `rates` is not consulted for pockets already in
+    /// `currency`.
+    pub fn value_in(
+        &self,
+        currency: &str,
+        rates: &impl RateProvider,
+    ) -> Result<Decimal, WalletError> {
+        let reporting = currency::lookup(currency).ok_or(WalletError::InvalidCurrency)?;
+        let mut total = Decimal::ZERO;
+        for (code, balance) in &self.pockets {
+            let amount = if *code == reporting.code {
+                balance.amount()
+            } else {
+                let rate =
+                    rates
+                        .rate(code, &reporting.code)
+                        .ok_or_else(|| WalletError::MissingRate {
+                            from: code.clone(),
+                            to: reporting.code.clone(),
+                        })?;
+                balance
+                    .amount()
+                    .checked_mul(rate)
+                    .ok_or(WalletError::Overflow)?
+            };
+            total = total.checked_add(amount).ok_or(WalletError::Overflow)?;
+        }
+        Ok(total
+            .round_dp_with_strategy(reporting.minor_units, RoundingStrategy::MidpointNearestEven))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fx::StaticRates;
+    use crate::id::time_ordered;
+    use crate::ledger::{Action, Ledger};
+    use rust_decimal_macros::dec;
+
+    fn pocket(currency: &str, amount: &str) -> Balance {
+        let mut balance = Balance::new(currency).unwrap();
+        let ledger = Ledger::new(Action::Deposit(amount.to_string()), time_ordered()).unwrap();
+        balance.mutate(ledger).unwrap();
+        balance
+    }
+
+    #[test]
+    fn test_wallet_value_in() {
+        let wallet = Wallet::new(
+            "account_1",
+            vec![
+                pocket("USD", "10"),
+                pocket("EUR", "1.25"),
+                pocket("JPY", "150"),
+            ],
+        );
+        assert_eq!(
+            wallet.currencies().collect::<Vec<_>>(),
+            ["EUR", "JPY", "USD"]
+        );
+        assert_eq!(wallet.pocket("jpy").unwrap().amount(), dec!(150));
+        let rate = |from: &str, to: &str| match (from, to) {
+            ("EUR", "USD") => Some(dec!(1.1)),
+            ("JPY", "USD") => Some(dec!(0.0067)),
+            ("USD", "JPY") => Some(dec!(149.5)),
+            ("EUR", "JPY") => Some(dec!(163.3)),
+            _ => None,
+        };
+        // 10 + 1.375 + 1.005 = 12.38
+        assert_eq!(wallet.value_in("usd", &rate), Ok(dec!(12.38)));
+        // 1495 + 204.125 + 150 = 1849.125, rounded to whole yen.
+        assert_eq!(wallet.value_in("JPY", &rate), Ok(dec!(1849)));
+    }
+
+    #[test]
+    fn test_wallet_value_in_errors() {
+        let wallet = Wallet::new("account_1", vec![pocket("USD", "1"), pocket("GBP", "1")]);
+        assert_eq!(
+            wallet.value_in("USD", &StaticRates::new()),
+            Err(WalletError::MissingRate {
+                from: "GBP".to_string(),
+                to: "USD".to_string()
+            })
+        );
+        assert_eq!(
+            wallet.value_in("XXX", &StaticRates::new()),
+            Err(WalletError::InvalidCurrency)
+        );
+
+        let max = Decimal::MAX.to_string();
+        let rates = StaticRates::new().with_rate("GBP", "USD", "2").unwrap();
+        let wallet = Wallet::new("account_1", vec![pocket("GBP", &max)]);
+        assert_eq!(wallet.value_in("USD", &rates), Err(WalletError::Overflow));
+        let wallet = Wallet::new("account_1", vec![pocket("USD", &max), pocket("GBP", "1")]);
+        assert_eq!(wallet.value_in("USD", &rates), Err(WalletError::Overflow));
+        // Pockets already in the reporting currency need no rate.
+        let usd = Wallet::new("account_2", vec![pocket("USD", "2.5")]);
+        assert_eq!(usd.value_in("USD", &StaticRates::new()), Ok(dec!(2.5)));
+    }
+}